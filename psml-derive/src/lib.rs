@@ -0,0 +1,254 @@
+//! Derive macros for the `psml` crate.
+//!
+//! These are re-exported by `psml` when its `derive` feature is enabled.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{
+    ext::IdentExt, parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields,
+    GenericArgument, LitStr, Path, PathArguments, Type,
+};
+
+/// Implements `psml::properties::PsmlProperties` for a struct with named fields.
+///
+/// See the `psml::properties` module for the supported field types and attributes.
+#[proc_macro_derive(PsmlProperties, attributes(psml))]
+pub fn derive_psml_properties(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// How a field type holds its values.
+enum Shape<'a> {
+    One(&'a Type),
+    Option(&'a Type),
+    Vec(&'a Type),
+}
+
+/// Options from the `psml` attributes on a field.
+#[derive(Default)]
+struct FieldOptions {
+    name: Option<LitStr>,
+    title: Option<LitStr>,
+    datatype: Option<LitStr>,
+    multiple: bool,
+    xref: Option<LitStr>,
+    skip: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut krate: Path = syn::parse_quote!(::psml);
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("psml"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported psml attribute"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.span(),
+                    "PsmlProperties requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "PsmlProperties can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut writes = vec![];
+    let mut reads = vec![];
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let options = field_options(field)?;
+        if options.skip {
+            reads.push(quote!(#ident: ::core::default::Default::default()));
+            continue;
+        }
+
+        let name = options
+            .name
+            .clone()
+            .unwrap_or_else(|| LitStr::new(&ident.unraw().to_string(), ident.span()));
+        if !is_property_name(&name.value()) {
+            return Err(Error::new(
+                name.span(),
+                "property names may only contain letters, digits, '_' and '-', \
+                 and may not start with '-'; set one with #[psml(name = \"...\")]",
+            ));
+        }
+        let title = match &options.title {
+            Some(title) => quote!(::core::option::Option::Some(#title)),
+            None => quote!(::core::option::Option::None),
+        };
+        let shape = shape(&field.ty);
+        let value_ty = match shape {
+            Shape::One(ty) | Shape::Option(ty) | Shape::Vec(ty) => ty,
+        };
+        if options.multiple && !matches!(shape, Shape::Vec(_)) {
+            return Err(Error::new(field.ty.span(), "multiple requires a Vec field"));
+        }
+
+        let xref_key = match &options.xref {
+            Some(key) => Some(match key.value().as_str() {
+                "docid" => quote!(#krate::properties::XRefKey::Docid),
+                "uriid" => quote!(#krate::properties::XRefKey::URIID),
+                "href" => quote!(#krate::properties::XRefKey::Href),
+                _ => return Err(Error::new(key.span(), "xref must be docid, uriid or href")),
+            }),
+            None => None,
+        };
+
+        let datatype = match (&options.datatype, &xref_key) {
+            (Some(datatype), _) => quote! {
+                #krate::model::PropertyDatatype::from(::std::string::String::from(#datatype))
+            },
+            (None, Some(_)) => quote!(#krate::model::PropertyDatatype::XRef),
+            (None, None) => quote! {
+                <#value_ty as #krate::properties::PropertyType>::datatype()
+            },
+        };
+        let (to_value, from_value) = match &xref_key {
+            Some(key) => (
+                quote!(|value: &#value_ty| #krate::properties::xref_value(#key, value)),
+                quote!(|value: &#krate::model::PropertyValue| {
+                    #krate::properties::xref_target(#key, value)
+                }),
+            ),
+            None => (
+                quote!(<#value_ty as #krate::properties::PropertyType>::to_value),
+                quote!(<#value_ty as #krate::properties::PropertyType>::from_value),
+            ),
+        };
+        let values = match shape {
+            Shape::One(_) => quote!(::std::vec![(#to_value)(&self.#ident)]),
+            Shape::Option(_) | Shape::Vec(_) => {
+                quote!(self.#ident.iter().map(#to_value).collect())
+            }
+        };
+        let cardinality = match options.multiple {
+            true => quote!(#krate::properties::Cardinality::Multiple),
+            false => quote!(#krate::properties::Cardinality::Repeated),
+        };
+        writes.push(quote_spanned! {field.span()=>
+            #krate::properties::push_properties(
+                &mut properties,
+                #name,
+                #title,
+                #datatype,
+                #values,
+                #cardinality,
+            );
+        });
+
+        let read = match shape {
+            Shape::One(_) => quote!(read_one),
+            Shape::Option(_) => quote!(read_optional),
+            Shape::Vec(_) => quote!(read_all),
+        };
+        reads.push(quote_spanned! {field.span()=>
+            #ident: #krate::properties::#read(
+                #name,
+                #krate::properties::property_values(fragment, #name),
+                #from_value,
+            )?
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::properties::PsmlProperties for #ident #ty_generics #where_clause {
+            fn to_properties_fragment(
+                &self,
+                id: ::std::string::String,
+            ) -> #krate::model::PropertiesFragment {
+                let mut properties = ::std::vec::Vec::new();
+                #(#writes)*
+                #krate::model::PropertiesFragment::new(id).with_properties(properties)
+            }
+
+            fn from_properties_fragment(
+                fragment: &#krate::model::PropertiesFragment,
+            ) -> #krate::PsmlResult<Self> {
+                ::core::result::Result::Ok(Self {
+                    #(#reads,)*
+                })
+            }
+        }
+    })
+}
+
+fn field_options(field: &syn::Field) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("psml"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                options.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("title") {
+                options.title = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("datatype") {
+                options.datatype = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("xref") {
+                options.xref = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("multiple") {
+                options.multiple = true;
+            } else if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else {
+                return Err(meta.error("unsupported psml attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+/// Returns whether the name is a legal PSML property name.
+fn is_property_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Returns how a field type holds its values, by the name of its outer type.
+fn shape(ty: &Type) -> Shape<'_> {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                if let Some(GenericArgument::Type(inner)) = args.args.first() {
+                    if segment.ident == "Option" {
+                        return Shape::Option(inner);
+                    } else if segment.ident == "Vec" {
+                        return Shape::Vec(inner);
+                    }
+                }
+            }
+        }
+    }
+    Shape::One(ty)
+}
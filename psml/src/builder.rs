@@ -0,0 +1,747 @@
+//! Fluent construction of PSML documents.
+//!
+//! [`DocumentBuilder`] fills in the document info and section attributes, and numbers fragments
+//! in document order unless they are given an ID, skipping the IDs given to any fragment.
+//! Nested content is built with closures:
+//!
+//! ```
+//! let doc = psml::builder::DocumentBuilder::new()
+//!     .title("Guide")
+//!     .section("body", |section| {
+//!         section.fragment(|fragment| {
+//!             fragment
+//!                 .heading(1, |heading| heading.text("Guide"))
+//!                 .para(|para| para.text("Hello ").bold(|bold| bold.text("world")))
+//!         })
+//!     })
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! The [`psml!`](crate::psml) macro builds the same document from a more compact syntax.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
+
+use crate::{
+    convert::{self, Content},
+    diff::fragment_id,
+    error::{PsmlError, PsmlResult},
+    fragments::fragment_id_mut,
+    model::{
+        BlockXRef, Description, Document, DocumentInfo, DocumentLevel, Fragment, FragmentContent,
+        Fragments, LabelSet, List, ListItem, NumberedList, PropertiesFragment, Property,
+        PropertyValue, Section, Table, TableCaption, TableCell, TableColumn, TablePart, TableRow,
+        TableRowContent, URIDescriptor, XRef, XRefFragment,
+    },
+    text::{
+        Anchor, Bold, CharacterStyle, Heading, Image, Inline, Italic, Link, Monospace, Para,
+        ParaContent, Placeholder, Subscript, Superscript, Underline,
+    },
+};
+
+impl Document {
+    /// Returns a builder for a new portable document.
+    pub fn builder() -> DocumentBuilder {
+        DocumentBuilder::new()
+    }
+}
+
+/// Builds a [`Document`].
+#[derive(Debug, Default)]
+pub struct DocumentBuilder {
+    document: Document,
+    uri: URIDescriptor,
+    /// Section index and content index of each fragment without an explicit ID.
+    generated: HashSet<(usize, usize)>,
+}
+
+impl DocumentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn uriid(mut self, uriid: impl Into<String>) -> Self {
+        self.uri.id = Some(uriid.into());
+        self
+    }
+
+    pub fn docid(mut self, docid: impl Into<String>) -> Self {
+        self.uri.docid = Some(docid.into());
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.uri.title = Some(title.into());
+        self
+    }
+
+    /// Sets the document type on both the document and its URI.
+    pub fn doc_type(mut self, doc_type: impl Into<String>) -> Self {
+        let doc_type = doc_type.into();
+        self.document.doc_type = Some(doc_type.clone());
+        self.uri.doc_type = Some(doc_type);
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.uri.description = Some(Description {
+            value: description.into(),
+        });
+        self
+    }
+
+    /// Sets the comma-separated labels on the document, which are checked when it is built.
+    pub fn labels(mut self, labels: impl Into<String>) -> Self {
+        self.uri.labels = Some(LabelSet::from(labels.into()));
+        self
+    }
+
+    pub fn level(mut self, level: DocumentLevel) -> Self {
+        self.document.level = level;
+        self
+    }
+
+    /// Adds a section with the given ID.
+    pub fn section(
+        mut self,
+        id: impl Into<String>,
+        build: impl FnOnce(SectionBuilder) -> SectionBuilder,
+    ) -> Self {
+        let section = build(SectionBuilder {
+            section: Section::new(id.into()),
+            generated: vec![],
+        });
+        let index = self.document.sections.len();
+        self.generated.extend(
+            section
+                .generated
+                .into_iter()
+                .map(|content| (index, content)),
+        );
+        self.document.sections.push(section.section);
+        self
+    }
+
+    /// Gives each fragment without an explicit ID its number in document order,
+    /// or the next number after it that is not an explicit ID.
+    fn generate_ids(&mut self) {
+        let mut explicit = HashSet::new();
+        for (i, section) in self.document.sections.iter().enumerate() {
+            for (j, content) in section.content.iter().enumerate() {
+                if let Some(id) = fragment_id(content) {
+                    if !self.generated.contains(&(i, j)) {
+                        explicit.insert(id.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut number = 0usize;
+        for (i, section) in self.document.sections.iter_mut().enumerate() {
+            for (j, content) in section.content.iter_mut().enumerate() {
+                let Some(id) = fragment_id_mut(content) else {
+                    continue;
+                };
+                number += 1;
+                if self.generated.contains(&(i, j)) {
+                    while explicit.contains(&number.to_string()) {
+                        number += 1;
+                    }
+                    *id = number.to_string();
+                }
+            }
+        }
+    }
+
+    /// Returns the document, or an error listing every rule it violates.
+    pub fn build(mut self) -> PsmlResult<Document> {
+        self.generate_ids();
+        self.document.doc_info = Some(DocumentInfo {
+            uri: Some(self.uri),
+            publication: None,
+        });
+        let violations = self.document.validate();
+        match violations.is_empty() {
+            true => Ok(self.document),
+            false => Err(PsmlError::InvalidDocument { violations }),
+        }
+    }
+}
+
+/// Builds a [`Section`].
+#[derive(Debug)]
+pub struct SectionBuilder {
+    section: Section,
+    /// Content index of each fragment without an explicit ID.
+    generated: Vec<usize>,
+}
+
+impl SectionBuilder {
+    /// Sets the title of the section in the UI.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.section.title = Some(title.into());
+        self
+    }
+
+    /// Sets the title of the content.
+    pub fn content_title(mut self, title: impl Into<String>) -> Self {
+        self.section.content_title = Some(title.into());
+        self
+    }
+
+    /// Sets the comma-separated fragment types the section is allowed to contain.
+    pub fn fragment_types(mut self, fragment_types: impl Into<String>) -> Self {
+        self.section.fragment_types = Some(fragment_types.into());
+        self
+    }
+
+    pub fn edit(mut self, edit: bool) -> Self {
+        self.section.edit = Some(edit);
+        self
+    }
+
+    pub fn lockstructure(mut self, lockstructure: bool) -> Self {
+        self.section.lockstructure = Some(lockstructure);
+        self
+    }
+
+    /// Adds a fragment, recording it for an ID to be generated when the document is built
+    /// if it was not given one.
+    fn add_fragment(&mut self, fragment: Fragments, explicit: bool) {
+        self.section.add_fragment(fragment);
+        if !explicit {
+            self.generated.push(self.section.content.len() - 1);
+        }
+    }
+
+    /// Adds a fragment with a generated ID.
+    pub fn fragment(mut self, build: impl FnOnce(FragmentBuilder) -> FragmentBuilder) -> Self {
+        let fragment = build(FragmentBuilder {
+            fragment: Fragment::new(String::new()),
+            explicit: false,
+        });
+        self.add_fragment(Fragments::Fragment(fragment.fragment), fragment.explicit);
+        self
+    }
+
+    /// Adds a properties fragment with a generated ID.
+    pub fn properties(
+        mut self,
+        build: impl FnOnce(PropertiesFragmentBuilder) -> PropertiesFragmentBuilder,
+    ) -> Self {
+        let fragment = build(PropertiesFragmentBuilder {
+            fragment: PropertiesFragment::new(String::new()),
+            explicit: false,
+        });
+        self.add_fragment(Fragments::Properties(fragment.fragment), fragment.explicit);
+        self
+    }
+
+    /// Adds an xref fragment with a generated ID.
+    pub fn xrefs(mut self, build: impl FnOnce(XRefFragmentBuilder) -> XRefFragmentBuilder) -> Self {
+        let fragment = build(XRefFragmentBuilder {
+            fragment: XRefFragment::new(String::new()),
+            explicit: false,
+        });
+        self.add_fragment(Fragments::Xref(fragment.fragment), fragment.explicit);
+        self
+    }
+}
+
+/// Sets the frag attribute of a block xref to the default fragment if it is empty.
+fn default_frag(mut xref: BlockXRef) -> BlockXRef {
+    if xref.frag.is_empty() {
+        xref.frag = "default".to_string();
+    }
+    xref
+}
+
+/// Generates the methods adding block content to a builder with a `content` field.
+macro_rules! impl_block_content {
+    ($name:ty, $($content:ident).+) => {
+        impl $name {
+            fn push(mut self, content: FragmentContent) -> Self {
+                self.$($content).+.push(content);
+                self
+            }
+
+            pub fn heading(
+                self,
+                level: u8,
+                build: impl FnOnce(InlineBuilder) -> InlineBuilder,
+            ) -> Self {
+                self.push(FragmentContent::Heading(Heading {
+                    level,
+                    content: build(InlineBuilder::default()).char_styles(),
+                }))
+            }
+
+            pub fn para(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+                self.push(FragmentContent::Para(Para::new(
+                    build(InlineBuilder::default()).content,
+                )))
+            }
+
+            /// Adds a numbered paragraph at the indent level.
+            pub fn numbered_para(
+                self,
+                indent: u8,
+                build: impl FnOnce(InlineBuilder) -> InlineBuilder,
+            ) -> Self {
+                self.push(FragmentContent::Para(Para {
+                    indent: Some(indent),
+                    numbered: Some(true),
+                    ..Para::new(build(InlineBuilder::default()).content)
+                }))
+            }
+
+            pub fn preformat(self, text: impl Into<String>) -> Self {
+                self.push(FragmentContent::Preformat {
+                    child: vec![CharacterStyle::Text(text.into())],
+                })
+            }
+
+            pub fn image(self, image: Image) -> Self {
+                self.push(FragmentContent::Image(image))
+            }
+
+            /// Adds a block xref, targeting the default fragment unless it has a frag.
+            pub fn xref(self, xref: BlockXRef) -> Self {
+                self.push(FragmentContent::BlockXRef(default_frag(xref)))
+            }
+
+            /// Adds a labelled block.
+            pub fn block(
+                self,
+                label: impl Into<String>,
+                build: impl FnOnce(BlockBuilder) -> BlockBuilder,
+            ) -> Self {
+                self.push(FragmentContent::Block {
+                    label: Some(label.into()),
+                    child: build(BlockBuilder::default()).content,
+                })
+            }
+
+            pub fn list(self, build: impl FnOnce(ListBuilder) -> ListBuilder) -> Self {
+                self.push(FragmentContent::List(List::new(
+                    build(ListBuilder::default()).items,
+                )))
+            }
+
+            pub fn nlist(self, build: impl FnOnce(ListBuilder) -> ListBuilder) -> Self {
+                self.push(FragmentContent::NumberedList(NumberedList::new(
+                    build(ListBuilder::default()).items,
+                )))
+            }
+
+            pub fn table(self, build: impl FnOnce(TableBuilder) -> TableBuilder) -> Self {
+                self.push(FragmentContent::Table(build(TableBuilder::default()).build()))
+            }
+        }
+    };
+}
+
+/// Builds a [`Fragment`].
+#[derive(Debug)]
+pub struct FragmentBuilder {
+    fragment: Fragment,
+    /// Whether the fragment was given an ID.
+    explicit: bool,
+}
+
+impl FragmentBuilder {
+    /// Replaces the generated ID.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.fragment.id = id.into();
+        self.explicit = true;
+        self
+    }
+
+    pub fn frag_type(mut self, frag_type: impl Into<String>) -> Self {
+        self.fragment.frag_type = Some(frag_type.into());
+        self
+    }
+
+    /// Sets the comma-separated labels on the fragment, which are checked when it is built.
+    pub fn labels(mut self, labels: impl Into<String>) -> Self {
+        self.fragment.labels = LabelSet::from(labels.into());
+        self
+    }
+}
+
+impl_block_content!(FragmentBuilder, fragment.content);
+
+/// Builds the content of a block, list item or table cell.
+#[derive(Debug, Default)]
+pub struct BlockBuilder {
+    content: Vec<FragmentContent>,
+}
+
+impl_block_content!(BlockBuilder, content);
+
+/// Builds the content of a paragraph, heading or character style.
+#[derive(Debug, Default)]
+pub struct InlineBuilder {
+    content: Vec<ParaContent>,
+}
+
+impl InlineBuilder {
+    fn push(mut self, content: ParaContent) -> Self {
+        self.content.push(content);
+        self
+    }
+
+    /// Returns the content as character styles, replacing images with their alternate text.
+    fn char_styles(self) -> Vec<CharacterStyle> {
+        self.content
+            .into_iter()
+            .filter_map(convert::char_style)
+            .collect()
+    }
+
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.push(ParaContent::Text(text.into()))
+    }
+
+    pub fn bold(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        self.push(ParaContent::Bold(Bold::new(
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn italic(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        self.push(ParaContent::Italic(Italic::new(
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn underline(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        self.push(ParaContent::Underline(Underline::new(
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn subscript(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        self.push(ParaContent::Subscript(Subscript::new(
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn superscript(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        self.push(ParaContent::Superscript(Superscript::new(
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn monospace(self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        self.push(ParaContent::Monospace(Monospace::new(
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn link(
+        self,
+        href: impl Into<String>,
+        build: impl FnOnce(InlineBuilder) -> InlineBuilder,
+    ) -> Self {
+        self.push(ParaContent::Link(
+            Link::new(build(InlineBuilder::default()).char_styles()).with_href(href.into()),
+        ))
+    }
+
+    /// Adds a labelled inline.
+    pub fn inline(
+        self,
+        label: impl Into<String>,
+        build: impl FnOnce(InlineBuilder) -> InlineBuilder,
+    ) -> Self {
+        self.push(ParaContent::Inline(Inline::new(
+            label.into(),
+            build(InlineBuilder::default()).char_styles(),
+        )))
+    }
+
+    pub fn xref(self, xref: XRef) -> Self {
+        self.push(ParaContent::XRef(xref))
+    }
+
+    pub fn anchor(self, name: impl Into<String>) -> Self {
+        self.push(ParaContent::Anchor(Anchor { name: name.into() }))
+    }
+
+    pub fn placeholder(self, name: impl Into<String>) -> Self {
+        self.push(ParaContent::Placeholder(Placeholder {
+            name: name.into(),
+            content: String::new(),
+        }))
+    }
+
+    pub fn br(self) -> Self {
+        self.push(ParaContent::Br)
+    }
+
+    /// Adds an image, which is replaced by its alternate text inside a character style or heading.
+    pub fn image(self, image: Image) -> Self {
+        self.push(ParaContent::Image(image))
+    }
+}
+
+/// Builds the items of a list or numbered list.
+#[derive(Debug, Default)]
+pub struct ListBuilder {
+    items: Vec<ListItem>,
+}
+
+impl ListBuilder {
+    pub fn item(mut self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        let content = build(InlineBuilder::default()).content;
+        self.items.push(ListItem::new(convert::mixed_content(
+            content.into_iter().map(Content::Inline).collect(),
+        )));
+        self
+    }
+
+    /// Adds an item with block content, such as paragraphs or a nested list.
+    pub fn item_blocks(mut self, build: impl FnOnce(BlockBuilder) -> BlockBuilder) -> Self {
+        let content = build(BlockBuilder::default()).content;
+        self.items.push(ListItem::new(convert::mixed_content(
+            content.into_iter().map(Content::Block).collect(),
+        )));
+        self
+    }
+}
+
+/// Builds a [`Table`] with a column for each cell in its longest row.
+#[derive(Debug, Default)]
+pub struct TableBuilder {
+    caption: Option<String>,
+    rows: Vec<TableRow>,
+}
+
+impl TableBuilder {
+    pub fn caption(mut self, caption: impl Into<String>) -> Self {
+        self.caption = Some(caption.into());
+        self
+    }
+
+    /// Adds a row of header cells.
+    pub fn header(mut self, build: impl FnOnce(RowBuilder) -> RowBuilder) -> Self {
+        self.rows.push(TableRow {
+            part: Some(TablePart::Header),
+            cells: build(RowBuilder::default())
+                .cells
+                .into_iter()
+                .map(TableRowContent::HeaderCell)
+                .collect(),
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn row(mut self, build: impl FnOnce(RowBuilder) -> RowBuilder) -> Self {
+        self.rows.push(TableRow {
+            cells: build(RowBuilder::default())
+                .cells
+                .into_iter()
+                .map(TableRowContent::Cell)
+                .collect(),
+            ..Default::default()
+        });
+        self
+    }
+
+    fn build(self) -> Table {
+        let cols = self.rows.iter().map(|row| row.cells.len()).max();
+        Table {
+            caption: self.caption.map(|caption| TableCaption { caption }),
+            role: None,
+            summary: None,
+            height: None,
+            width: None,
+            cols: (0..cols.unwrap_or_default())
+                .map(|_| TableColumn::default())
+                .collect(),
+            rows: self.rows,
+        }
+    }
+}
+
+/// Builds the cells of a table row.
+#[derive(Debug, Default)]
+pub struct RowBuilder {
+    cells: Vec<TableCell>,
+}
+
+impl RowBuilder {
+    pub fn cell(mut self, build: impl FnOnce(InlineBuilder) -> InlineBuilder) -> Self {
+        let content = build(InlineBuilder::default()).content;
+        self.cells.push(TableCell::new(convert::mixed_content(
+            content.into_iter().map(Content::Inline).collect(),
+        )));
+        self
+    }
+
+    /// Adds a cell with block content, such as paragraphs or a list.
+    pub fn cell_blocks(mut self, build: impl FnOnce(BlockBuilder) -> BlockBuilder) -> Self {
+        let content = build(BlockBuilder::default()).content;
+        self.cells.push(TableCell::new(convert::mixed_content(
+            content.into_iter().map(Content::Block).collect(),
+        )));
+        self
+    }
+}
+
+/// Builds a [`PropertiesFragment`].
+#[derive(Debug)]
+pub struct PropertiesFragmentBuilder {
+    fragment: PropertiesFragment,
+    /// Whether the fragment was given an ID.
+    explicit: bool,
+}
+
+impl PropertiesFragmentBuilder {
+    /// Replaces the generated ID.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.fragment.id = id.into();
+        self.explicit = true;
+        self
+    }
+
+    pub fn frag_type(mut self, frag_type: impl Into<String>) -> Self {
+        self.fragment.frag_type = Some(frag_type.into());
+        self
+    }
+
+    /// Sets the comma-separated labels on the fragment, which are checked when it is built.
+    pub fn labels(mut self, labels: impl Into<String>) -> Self {
+        self.fragment.labels = LabelSet::from(labels.into());
+        self
+    }
+
+    pub fn property(mut self, property: Property) -> Self {
+        self.fragment.properties.push(property);
+        self
+    }
+
+    pub fn string(
+        self,
+        name: impl Into<String>,
+        title: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.property(Property::string(name.into(), title.into(), value.into()))
+    }
+
+    pub fn date(self, name: impl Into<String>, title: impl Into<String>, value: NaiveDate) -> Self {
+        self.property(Property::date(name.into(), title.into(), value))
+    }
+
+    pub fn datetime(
+        self,
+        name: impl Into<String>,
+        title: impl Into<String>,
+        value: DateTime<FixedOffset>,
+    ) -> Self {
+        self.property(Property::datetime(name.into(), title.into(), value))
+    }
+
+    pub fn markup(
+        self,
+        name: impl Into<String>,
+        title: impl Into<String>,
+        build: impl FnOnce(InlineBuilder) -> InlineBuilder,
+    ) -> Self {
+        self.property(Property::markup(
+            name.into(),
+            title.into(),
+            build(InlineBuilder::default()).char_styles(),
+        ))
+    }
+
+    pub fn xref(self, name: impl Into<String>, title: impl Into<String>, xref: XRef) -> Self {
+        self.property(Property::with_value(
+            name.into(),
+            title.into(),
+            PropertyValue::XRef(Box::new(xref)),
+        ))
+    }
+}
+
+/// Builds an [`XRefFragment`].
+#[derive(Debug)]
+pub struct XRefFragmentBuilder {
+    fragment: XRefFragment,
+    /// Whether the fragment was given an ID.
+    explicit: bool,
+}
+
+impl XRefFragmentBuilder {
+    /// Replaces the generated ID.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.fragment.id = id.into();
+        self.explicit = true;
+        self
+    }
+
+    pub fn frag_type(mut self, frag_type: impl Into<String>) -> Self {
+        self.fragment.frag_type = Some(frag_type.into());
+        self
+    }
+
+    /// Sets the comma-separated labels on the fragment, which are checked when it is built.
+    pub fn labels(mut self, labels: impl Into<String>) -> Self {
+        self.fragment.labels = LabelSet::from(labels.into());
+        self
+    }
+
+    /// Adds a block xref, targeting the default fragment unless it has a frag.
+    pub fn xref(mut self, xref: BlockXRef) -> Self {
+        self.fragment.xrefs.push(default_frag(xref));
+        self
+    }
+}
+
+/// Builds a [`Document`] from nested method calls on the builders in [`crate::builder`].
+///
+/// Each item is a builder method, written as `method`, `method(args)`, `method { items }`
+/// or `method(args) { items }`, where the items build the content passed to the method.
+/// A string literal adds text.
+///
+/// ```
+/// let doc = psml::psml! {
+///     title("Guide")
+///     section("body") {
+///         fragment {
+///             heading(1) { "Guide" }
+///             para { "Hello " bold { "world" } }
+///         }
+///         properties {
+///             string("author", "Author", "Jane")
+///         }
+///     }
+/// }
+/// .unwrap();
+/// ```
+#[macro_export]
+macro_rules! psml {
+    // Each brace group expands in one step, so only nesting counts toward the recursion limit.
+    (@build $builder:expr;
+        $($text:literal)*
+        $($method:ident $(($($arg:expr),* $(,)?))? $({ $($body:tt)* })? $($tail:literal)*)*
+    ) => {
+        $builder
+            $(.text($text))*
+            $(
+                .$method(
+                    $($($arg,)*)?
+                    $(|builder| $crate::psml!(@build builder; $($body)*))?
+                )
+                $(.text($tail))*
+            )*
+    };
+    ($($body:tt)*) => {
+        $crate::psml!(@build $crate::builder::DocumentBuilder::new(); $($body)*).build()
+    };
+}
@@ -0,0 +1,270 @@
+//! Offline checking of the links between the PSML documents in a directory.
+//!
+//! A target is looked up by its uriid, then docid, then href. Relative hrefs are
+//! percent-decoded and resolved against the directory of the linking file, and links with
+//! no target or an empty href are to the linking document. Hrefs with a URL scheme or an
+//! absolute path are not checked, as they point outside the directory.
+//!
+//! Hrefs to files other than `.psml` files are checked to exist by [`check_dir`],
+//! and not checked by [`check_documents`], which has no directory to look in.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    diff::fragment_id,
+    error::{PsmlError, PsmlResult},
+    model::{Document, XRefTarget},
+    xrefs::{Reference, XRefIndex},
+};
+
+/// Why a link is broken.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BrokenLinkKind {
+    #[error("no document with {attr} {value:?}")]
+    MissingDocument { attr: &'static str, value: String },
+    #[error("no fragment {frag:?} in the target document")]
+    MissingFragment { frag: String },
+    #[error("block xref is marked as unresolved")]
+    Unresolved,
+}
+
+/// A link that does not resolve to a document or fragment in the checked set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// Path of the file containing the link, relative to the checked directory.
+    pub file: PathBuf,
+    /// ID of the section containing the link.
+    pub section: String,
+    /// ID of the fragment containing the link.
+    pub fragment: String,
+    pub kind: BrokenLinkKind,
+}
+
+impl Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (section {}, fragment {}): {}",
+            self.file.display(),
+            self.section,
+            self.fragment,
+            self.kind
+        )
+    }
+}
+
+/// The result of checking the links in a set of documents.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LinkReport {
+    /// Number of links checked, excluding links outside the directory.
+    pub checked: usize,
+    /// Broken links in file order, then document order.
+    pub broken: Vec<BrokenLink>,
+}
+
+impl LinkReport {
+    pub fn is_ok(&self) -> bool {
+        self.broken.is_empty()
+    }
+}
+
+/// Reads every `.psml` file under `dir` and checks the links between them.
+pub fn check_dir(dir: impl AsRef<Path>) -> PsmlResult<LinkReport> {
+    let dir = dir.as_ref();
+    let mut files = vec![];
+    psml_files(dir, &mut files)?;
+    files.sort();
+
+    let mut documents = vec![];
+    for file in files {
+        let document = fs::read_to_string(&file)
+            .map_err(PsmlError::from)
+            .and_then(|xml| crate::from_str(&xml))
+            .map_err(|err| PsmlError::FileError {
+                path: file.clone(),
+                source: Box::new(err),
+            })?;
+        let path = file.strip_prefix(dir).unwrap_or(&file).to_path_buf();
+        documents.push((path, document));
+    }
+    Ok(check(&documents, Some(dir)))
+}
+
+fn psml_files(dir: &Path, files: &mut Vec<PathBuf>) -> PsmlResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            psml_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "psml") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// What a link resolves to.
+enum Target {
+    /// The document at the index.
+    Document(usize),
+    /// A file in the directory that is not a PSML document.
+    File,
+    /// Something outside the directory, which is not checked.
+    Outside,
+}
+
+/// Checks the links between documents, each with its path relative to a common directory.
+/// Links to files other than PSML documents are not checked.
+pub fn check_documents(documents: &[(PathBuf, Document)]) -> LinkReport {
+    check(documents, None)
+}
+
+/// Checks the links between documents, checking that links to other files exist in `dir`.
+fn check(documents: &[(PathBuf, Document)], dir: Option<&Path>) -> LinkReport {
+    let mut uriids = HashMap::new();
+    let mut docids = HashMap::new();
+    let mut paths = HashMap::new();
+    for (index, (path, document)) in documents.iter().enumerate() {
+        if let Some(uriid) = document.uriid() {
+            uriids.insert(uriid, index);
+        }
+        if let Some(docid) = document.docid() {
+            docids.insert(docid, index);
+        }
+        paths.insert(normalise(path), index);
+    }
+    let fragments: Vec<HashSet<&str>> = documents
+        .iter()
+        .map(|(_, document)| {
+            document
+                .sections
+                .iter()
+                .flat_map(|section| section.content.iter().filter_map(fragment_id))
+                .collect()
+        })
+        .collect();
+
+    let index = XRefIndex::new(documents.iter().map(|(_, document)| document));
+    let mut report = LinkReport::default();
+    for reference in index.references() {
+        let (file, _) = &documents[reference.document];
+        let target = |attr, value: &str, found: Option<&usize>| match found {
+            Some(&index) => Ok(Target::Document(index)),
+            None => Err(BrokenLinkKind::MissingDocument {
+                attr,
+                value: value.to_string(),
+            }),
+        };
+        let XRefTarget {
+            uriid, docid, href, ..
+        } = reference.target;
+        let found = if let Some(uriid) = uriid {
+            target("uriid", uriid, uriids.get(uriid))
+        } else if let Some(docid) = docid {
+            target("docid", docid, docids.get(docid))
+        } else if href.unwrap_or_default().is_empty() {
+            // A link with no target is to this document.
+            Ok(Target::Document(reference.document))
+        } else if let Some(href) = href.filter(|href| !has_scheme(href) && !href.starts_with('/')) {
+            let path = resolve(file, href);
+            match (path.extension().is_some_and(|ext| ext == "psml"), dir) {
+                (true, _) => target("href", href, paths.get(&path)),
+                (false, Some(dir)) if dir.join(&path).is_file() => Ok(Target::File),
+                (false, Some(_)) => target("href", href, None),
+                (false, None) => Ok(Target::Outside),
+            }
+        } else {
+            Ok(Target::Outside)
+        };
+
+        let kind = match found {
+            _ if reference.unresolved => Some(BrokenLinkKind::Unresolved),
+            Err(kind) => Some(kind),
+            Ok(Target::Document(target)) => missing_fragment(reference, &fragments[target]),
+            Ok(Target::File) => None,
+            Ok(Target::Outside) => continue,
+        };
+        report.checked += 1;
+        if let Some(kind) = kind {
+            report.broken.push(BrokenLink {
+                file: file.clone(),
+                section: reference.section.to_string(),
+                fragment: reference.fragment.to_string(),
+                kind,
+            });
+        }
+    }
+    report
+}
+
+fn missing_fragment(reference: &Reference, fragments: &HashSet<&str>) -> Option<BrokenLinkKind> {
+    match reference.target.frag {
+        "" | "default" => None,
+        frag if fragments.contains(frag) => None,
+        frag => Some(BrokenLinkKind::MissingFragment {
+            frag: frag.to_string(),
+        }),
+    }
+}
+
+/// Returns true if the href starts with a URL scheme, such as `https:` or `mailto:`.
+fn has_scheme(href: &str) -> bool {
+    href.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.len() > 1
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// Resolves an href against the directory of the file containing it.
+fn resolve(file: &Path, href: &str) -> PathBuf {
+    let href = percent_decode(href.split(['#', '?']).next().unwrap_or_default());
+    normalise(&file.parent().unwrap_or(Path::new("")).join(href))
+}
+
+/// Decodes the percent-encoded bytes in a URL path.
+/// Percent signs not followed by two hex digits are kept.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Removes `.` and `..` components from a relative path.
+fn normalise(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::Normal(part) => normal.push(part),
+            _ => {}
+        }
+    }
+    normal
+}
@@ -0,0 +1,694 @@
+//! Conversion from HTML to PSML.
+
+use scraper::{node::Element, ElementRef, Html, Node};
+
+use super::{char_style, fragment_content, mixed_content, para_content, Content};
+use crate::{
+    model::{
+        FragmentContent, LabelSet, List, ListItem, MixedContent, NumberedList, NumberedListKind,
+        Table, TableCaption, TableCell, TableColumn, TablePart, TableRow, TableRowContent, XRef,
+        XRefDisplayKind,
+    },
+    text::{
+        Alignment, Anchor, Bold, CharacterStyle, Heading, Image, Inline, Italic, Link, Monospace,
+        Para, ParaContent, Subscript, Superscript, Underline,
+    },
+};
+
+/// The result of converting HTML to PSML.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlImport {
+    /// The converted content.
+    pub content: Vec<FragmentContent>,
+    /// Markup that could not be converted exactly, in document order.
+    pub losses: Vec<Loss>,
+}
+
+/// Markup that could not be converted to PSML exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loss {
+    /// Path of element names from the root to the element, e.g. `/div/p/font`.
+    pub path: String,
+    pub kind: LossKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LossKind {
+    /// The element has no PSML equivalent and was converted to a labelled block or inline.
+    Labelled { label: String },
+    /// The element and its content were dropped.
+    Dropped,
+    /// The element was removed but its content was kept.
+    Unwrapped,
+    /// The markup in the element was reduced to text,
+    /// or a block in it was wrapped in a block because it is not allowed there.
+    Flattened,
+    /// An attribute with no PSML equivalent was dropped.
+    Attribute { name: String },
+}
+
+/// Parses an HTML fragment into fragment content, reporting any lossy conversions.
+///
+/// Links with a relative URL are converted to xrefs and links with an absolute URL to links.
+/// Elements with no PSML equivalent are converted to blocks or inlines labelled with
+/// their first class name, or their element name if they have no class or it is not a valid label.
+pub fn to_content(html: &str) -> HtmlImport {
+    let html = Html::parse_fragment(html);
+    let mut importer = Importer {
+        path: vec![],
+        losses: vec![],
+    };
+    let content = importer.children(html.root_element(), Context::Block);
+    HtmlImport {
+        content: fragment_content(content),
+        losses: importer.losses,
+    }
+}
+
+/// Elements that are dropped with their content.
+const DROPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "iframe", "object", "embed", "canvas", "svg",
+    "math", "form", "input", "button", "select", "textarea", "head", "title", "meta", "link",
+    "base", "hr", "wbr",
+];
+
+/// Elements without a PSML equivalent that are converted to inlines rather than blocks.
+const INLINE: &[&str] = &[
+    "span", "font", "small", "big", "mark", "abbr", "acronym", "cite", "q", "s", "strike", "del",
+    "dfn", "time", "label", "bdi", "bdo", "data", "ruby",
+];
+
+/// Elements whose content is kept without the element.
+const UNWRAPPED: &[&str] = &["html", "body", "tbody", "thead", "tfoot", "picture"];
+
+/// The kind of content the parent element accepts.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Block,
+    /// Block and inline content, such as in a list item or table cell.
+    Mixed,
+    Inline,
+    Preformat,
+}
+
+struct Importer {
+    /// Names of the elements from the root to the current element.
+    path: Vec<String>,
+    losses: Vec<Loss>,
+}
+
+impl Importer {
+    fn loss(&mut self, kind: LossKind) {
+        self.losses.push(Loss {
+            path: format!("/{}", self.path.join("/")),
+            kind,
+        });
+    }
+
+    /// Reports the attributes on the current element that were not used.
+    fn unused_attrs(&mut self, element: &Element, used: &[&str]) {
+        for (name, _) in element.attrs() {
+            if !used.contains(&name) {
+                self.loss(LossKind::Attribute {
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Returns the label for an element with no PSML equivalent and reports it.
+    /// If the first class of the element is not a valid label, the class is reported as dropped
+    /// and the element name is used instead.
+    fn label(&mut self, element: &Element) -> String {
+        let class = element
+            .attr("class")
+            .and_then(|classes| classes.split_whitespace().next());
+        let label = match class {
+            Some(class) if LabelSet::is_valid(class) => class,
+            _ => element.name(),
+        };
+        self.loss(LossKind::Labelled {
+            label: label.to_string(),
+        });
+        if class.is_some_and(|class| class != label) {
+            self.loss(LossKind::Attribute {
+                name: "class".to_string(),
+            });
+        }
+        label.to_string()
+    }
+
+    /// Converts the children of an element.
+    fn children(&mut self, element: ElementRef, context: Context) -> Vec<Content> {
+        let mut content = vec![];
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => {
+                    let text = match context {
+                        Context::Preformat => text.to_string(),
+                        _ => collapse_whitespace(text),
+                    };
+                    if context == Context::Block && text.trim().is_empty() {
+                        continue;
+                    }
+                    push_text(&mut content, text);
+                }
+                Node::Element(_) => {
+                    if let Some(element) = ElementRef::wrap(child) {
+                        for node in self.element(element, context) {
+                            match node {
+                                Content::Inline(ParaContent::Text(text)) => {
+                                    push_text(&mut content, text)
+                                }
+                                node => content.push(node),
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        content
+    }
+
+    fn element(&mut self, element: ElementRef, context: Context) -> Vec<Content> {
+        let name = element.value().name().to_string();
+        self.path.push(name.clone());
+        let content = self.convert(element, &name, context);
+        self.path.pop();
+        content
+    }
+
+    fn convert(&mut self, element: ElementRef, name: &str, context: Context) -> Vec<Content> {
+        let el = element.value();
+
+        if DROPPED.contains(&name) {
+            self.loss(LossKind::Dropped);
+            return vec![];
+        }
+        if UNWRAPPED.contains(&name) {
+            self.unused_attrs(el, &[]);
+            return self.children(element, context);
+        }
+
+        let inline_context = match context {
+            Context::Block | Context::Mixed => Context::Inline,
+            context => context,
+        };
+        let inline = |content: ParaContent| vec![Content::Inline(content)];
+
+        match name {
+            "b" | "strong" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Bold(Bold::new(
+                    self.char_styles(element, inline_context),
+                )))
+            }
+            "i" | "em" | "var" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Italic(Italic::new(
+                    self.char_styles(element, inline_context),
+                )))
+            }
+            "u" | "ins" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Underline(Underline::new(
+                    self.char_styles(element, inline_context),
+                )))
+            }
+            "sub" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Subscript(Subscript::new(
+                    self.char_styles(element, inline_context),
+                )))
+            }
+            "sup" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Superscript(Superscript::new(
+                    self.char_styles(element, inline_context),
+                )))
+            }
+            "code" | "kbd" | "samp" | "tt" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Monospace(Monospace::new(
+                    self.char_styles(element, inline_context),
+                )))
+            }
+            "br" => {
+                self.unused_attrs(el, &[]);
+                inline(ParaContent::Br)
+            }
+            "a" => self.anchor(element, inline_context),
+            "img" => self
+                .image(el)
+                .map(|image| inline(ParaContent::Image(image)))
+                .unwrap_or_default(),
+            _ if !matches!(context, Context::Block | Context::Mixed) => {
+                if INLINE.contains(&name) {
+                    self.labelled_inline(element, inline_context)
+                } else {
+                    // Blocks cannot be nested in inline content.
+                    self.loss(LossKind::Unwrapped);
+                    self.unused_attrs(el, &[]);
+                    self.children(element, context)
+                }
+            }
+            "p" => {
+                self.unused_attrs(el, &["id"]);
+                let mut content = self.id_anchor(el);
+                content.extend(self.para_content(element));
+                vec![Content::Block(FragmentContent::Para(Para::new(content)))]
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.unused_attrs(el, &["id"]);
+                let mut content: Vec<CharacterStyle> = self
+                    .id_anchor(el)
+                    .into_iter()
+                    .filter_map(char_style)
+                    .collect();
+                content.extend(self.char_styles(element, Context::Inline));
+                trim_char_styles(&mut content);
+                vec![Content::Block(FragmentContent::Heading(Heading {
+                    level: name[1..].parse().unwrap_or(1),
+                    content,
+                }))]
+            }
+            "pre" => {
+                self.unused_attrs(el, &[]);
+                let mut child = self.char_styles(element, Context::Preformat);
+                if let Some(CharacterStyle::Text(text)) = child.first_mut() {
+                    if text.starts_with('\n') {
+                        text.remove(0);
+                    }
+                }
+                vec![Content::Block(FragmentContent::Preformat { child })]
+            }
+            "ul" | "ol" | "menu" => vec![Content::Block(self.list(element, name))],
+            "table" => vec![Content::Block(FragmentContent::Table(self.table(element)))],
+            _ if INLINE.contains(&name) => self.labelled_inline(element, Context::Inline),
+            _ => {
+                let label = self.label(el);
+                self.unused_attrs(el, &["class"]);
+                let child = fragment_content(self.children(element, Context::Block));
+                vec![Content::Block(FragmentContent::Block {
+                    label: Some(label),
+                    child,
+                })]
+            }
+        }
+    }
+
+    /// Converts the content of an element as the inline content of a paragraph.
+    fn para_content(&mut self, element: ElementRef) -> Vec<ParaContent> {
+        let mut content = para_content(self.children(element, Context::Inline));
+        trim_para_content(&mut content);
+        content
+    }
+
+    /// Collects the content of a list item or table cell, reporting each block that is not
+    /// allowed there and is wrapped in an unlabelled block instead.
+    /// Whitespace between inline nodes is kept, and whitespace next to blocks is dropped.
+    fn mixed_content(&mut self, mut content: Vec<Content>) -> Vec<MixedContent> {
+        let is_block = |node: Option<&Content>| matches!(node, Some(Content::Block(_)));
+        let mut index = 0;
+        while index < content.len() {
+            let space = matches!(
+                &content[index],
+                Content::Inline(ParaContent::Text(text)) if text.trim().is_empty()
+            );
+            if space
+                && (is_block(index.checked_sub(1).and_then(|i| content.get(i)))
+                    || is_block(content.get(index + 1)))
+            {
+                content.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+
+        for node in &content {
+            if let Content::Block(
+                FragmentContent::Heading(_)
+                | FragmentContent::BlockXRef(_)
+                | FragmentContent::Table(_),
+            ) = node
+            {
+                self.loss(LossKind::Flattened);
+            }
+        }
+        let mut content = mixed_content(content);
+        trim_mixed_content(&mut content);
+        content
+    }
+
+    /// Converts the content of an element as character styles,
+    /// reporting images that are replaced by their alternate text.
+    fn char_styles(&mut self, element: ElementRef, context: Context) -> Vec<CharacterStyle> {
+        let mut styles = vec![];
+        for inline in para_content(self.children(element, context)) {
+            if let ParaContent::Image(_) = inline {
+                self.path.push("img".to_string());
+                self.loss(LossKind::Flattened);
+                self.path.pop();
+            }
+            styles.extend(char_style(inline));
+        }
+        styles
+    }
+
+    /// Returns an anchor for the id on an element, if it has one.
+    fn id_anchor(&mut self, element: &Element) -> Vec<ParaContent> {
+        element
+            .id()
+            .map(|id| {
+                ParaContent::Anchor(Anchor {
+                    name: id.to_string(),
+                })
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn labelled_inline(&mut self, element: ElementRef, context: Context) -> Vec<Content> {
+        let label = self.label(element.value());
+        self.unused_attrs(element.value(), &["class"]);
+        vec![Content::Inline(ParaContent::Inline(Inline::new(
+            label,
+            self.char_styles(element, context),
+        )))]
+    }
+
+    /// Converts a link to an xref if its URL is relative, a link if it is absolute,
+    /// or an anchor if it has no URL.
+    fn anchor(&mut self, element: ElementRef, context: Context) -> Vec<Content> {
+        let el = element.value();
+        let Some(href) = el.attr("href") else {
+            let mut content: Vec<Content> = el
+                .attr("name")
+                .or(el.id())
+                .map(|name| {
+                    Content::Inline(ParaContent::Anchor(Anchor {
+                        name: name.to_string(),
+                    }))
+                })
+                .into_iter()
+                .collect();
+            // Only one name is kept, so the id is lost if there is also a name.
+            match el.attr("name") {
+                Some(_) => self.unused_attrs(el, &["name"]),
+                None => self.unused_attrs(el, &["id"]),
+            }
+            content.extend(self.children(element, context));
+            return content;
+        };
+        if is_absolute(href) {
+            // PSML links have no title.
+            self.unused_attrs(el, &["href"]);
+            return vec![Content::Inline(ParaContent::Link(
+                Link::new(self.char_styles(element, context)).with_href(href.to_string()),
+            ))];
+        }
+
+        self.unused_attrs(el, &["href", "title"]);
+        if element.children().any(|child| child.value().is_element()) {
+            self.loss(LossKind::Flattened);
+        }
+        let text = collapse_whitespace(&element.text().collect::<String>())
+            .trim()
+            .to_string();
+        let (path, frag) = href.split_once('#').unwrap_or((href, ""));
+        let mut xref = XRef::href(path.to_string())
+            .with_title(el.attr("title").map(str::to_string))
+            .with_content(text);
+        if path.is_empty() {
+            xref.href = None;
+        }
+        if !frag.is_empty() {
+            xref.frag_id = frag.to_string();
+        }
+        if !xref.content.is_empty() {
+            xref = xref.with_display(XRefDisplayKind::Manual);
+        }
+        vec![Content::Inline(ParaContent::XRef(xref))]
+    }
+
+    fn image(&mut self, element: &Element) -> Option<Image> {
+        let Some(src) = element.attr("src") else {
+            self.loss(LossKind::Dropped);
+            return None;
+        };
+        let mut size = [None, None];
+        for (i, attr) in ["width", "height"].into_iter().enumerate() {
+            if let Some(value) = element.attr(attr) {
+                size[i] = value.trim_end_matches("px").parse().ok();
+                if size[i].is_none() {
+                    self.loss(LossKind::Attribute {
+                        name: attr.to_string(),
+                    });
+                }
+            }
+        }
+        self.unused_attrs(element, &["src", "alt", "width", "height"]);
+        Some(
+            Image::from_src(src.to_string())
+                .with_alt(element.attr("alt").map(str::to_string))
+                .with_size(size[0], size[1]),
+        )
+    }
+
+    fn list(&mut self, element: ElementRef, name: &str) -> FragmentContent {
+        let ordered = name == "ol";
+        self.unused_attrs(
+            element.value(),
+            if ordered { &["start", "type"] } else { &[] },
+        );
+        let mut items = vec![];
+        for child in element.child_elements() {
+            let child_name = child.value().name().to_string();
+            self.path.push(child_name.clone());
+            if child_name == "li" {
+                self.unused_attrs(child.value(), &[]);
+                let content = self.children(child, Context::Mixed);
+                items.push(ListItem::new(self.mixed_content(content)));
+            } else {
+                self.loss(LossKind::Dropped);
+            }
+            self.path.pop();
+        }
+
+        if !ordered {
+            return FragmentContent::List(List::new(items));
+        }
+        FragmentContent::NumberedList(NumberedList {
+            start: element.attr("start").and_then(|start| start.parse().ok()),
+            list_type: element.attr("type").and_then(|kind| match kind {
+                "1" => Some(NumberedListKind::Arabic),
+                "A" => Some(NumberedListKind::UpperAlpha),
+                "a" => Some(NumberedListKind::LowerAlpha),
+                "I" => Some(NumberedListKind::UpperRoman),
+                "i" => Some(NumberedListKind::LowerRoman),
+                _ => None,
+            }),
+            ..NumberedList::new(items)
+        })
+    }
+
+    fn table(&mut self, element: ElementRef) -> Table {
+        let mut table = Table {
+            caption: None,
+            role: None,
+            summary: element.attr("summary").map(str::to_string),
+            height: None,
+            width: None,
+            cols: vec![],
+            rows: vec![],
+        };
+        self.unused_attrs(element.value(), &["summary"]);
+
+        for child in element.child_elements() {
+            let name = child.value().name().to_string();
+            self.path.push(name.clone());
+            match name.as_str() {
+                "caption" => {
+                    self.unused_attrs(child.value(), &[]);
+                    if child.child_elements().next().is_some() {
+                        self.loss(LossKind::Flattened);
+                    }
+                    let caption = collapse_whitespace(&child.text().collect::<String>());
+                    table.caption = Some(TableCaption {
+                        caption: caption.trim().to_string(),
+                    });
+                }
+                "colgroup" => {
+                    self.unused_attrs(child.value(), &[]);
+                    for col in child.child_elements() {
+                        self.path.push("col".to_string());
+                        table.cols.extend(self.columns(col.value()));
+                        self.path.pop();
+                    }
+                }
+                "col" => table.cols.extend(self.columns(child.value())),
+                "thead" | "tbody" | "tfoot" => {
+                    self.unused_attrs(child.value(), &[]);
+                    let part = match name.as_str() {
+                        "thead" => Some(TablePart::Header),
+                        "tfoot" => Some(TablePart::Footer),
+                        _ => None,
+                    };
+                    for row in child.child_elements() {
+                        self.path.push(row.value().name().to_string());
+                        table.rows.extend(self.table_row(row, part.clone()));
+                        self.path.pop();
+                    }
+                }
+                "tr" => table.rows.extend(self.table_row(child, None)),
+                _ => self.loss(LossKind::Dropped),
+            }
+            self.path.pop();
+        }
+
+        let width = table
+            .rows
+            .iter()
+            .map(|row| {
+                row.cells
+                    .iter()
+                    .map(|cell| cell.cell().colspan.unwrap_or(1))
+                    .sum::<u64>() as usize
+            })
+            .max()
+            .unwrap_or(0);
+        if table.cols.len() < width {
+            table.cols.resize(width, TableColumn::default());
+        }
+        table
+    }
+
+    /// Converts a `col` element to as many columns as it spans.
+    fn columns(&mut self, element: &Element) -> Vec<TableColumn> {
+        self.unused_attrs(element, &["span", "width", "align"]);
+        let span = element
+            .attr("span")
+            .and_then(|span| span.parse().ok())
+            .unwrap_or(1);
+        let col = TableColumn {
+            width: element.attr("width").map(str::to_string),
+            align: element.attr("align").and_then(alignment),
+            ..Default::default()
+        };
+        vec![col; span]
+    }
+
+    fn table_row(&mut self, element: ElementRef, part: Option<TablePart>) -> Option<TableRow> {
+        if element.value().name() != "tr" {
+            self.loss(LossKind::Dropped);
+            return None;
+        }
+        self.unused_attrs(element.value(), &["align"]);
+
+        let mut row = TableRow {
+            part,
+            align: element.attr("align").and_then(alignment),
+            ..Default::default()
+        };
+        for cell in element.child_elements() {
+            let name = cell.value().name().to_string();
+            self.path.push(name.clone());
+            if name == "td" || name == "th" {
+                self.unused_attrs(cell.value(), &["colspan", "rowspan", "align"]);
+                let content = self.children(cell, Context::Mixed);
+                let content = self.mixed_content(content);
+                let cell = TableCell {
+                    align: cell.attr("align").and_then(alignment),
+                    colspan: cell.attr("colspan").and_then(|span| span.parse().ok()),
+                    rowspan: cell.attr("rowspan").and_then(|span| span.parse().ok()),
+                    ..TableCell::new(content)
+                };
+                row.cells.push(match name.as_str() {
+                    "th" => TableRowContent::HeaderCell(cell),
+                    _ => TableRowContent::Cell(cell),
+                });
+            } else {
+                self.loss(LossKind::Dropped);
+            }
+            self.path.pop();
+        }
+        Some(row)
+    }
+}
+
+fn alignment(align: &str) -> Option<Alignment> {
+    match align.to_ascii_lowercase().as_str() {
+        "left" => Some(Alignment::Left),
+        "center" => Some(Alignment::Center),
+        "right" => Some(Alignment::Right),
+        "justify" => Some(Alignment::Justify),
+        _ => None,
+    }
+}
+
+/// Returns true if the URL has a scheme or is protocol relative.
+fn is_absolute(url: &str) -> bool {
+    if url.starts_with("//") {
+        return true;
+    }
+    match url.split_once(':') {
+        Some((scheme, _)) => {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Replaces each run of whitespace with a single space.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut space = false;
+    for char in text.chars() {
+        if char.is_whitespace() {
+            if !space {
+                collapsed.push(' ');
+            }
+            space = true;
+        } else {
+            collapsed.push(char);
+            space = false;
+        }
+    }
+    collapsed
+}
+
+/// Adds text to the content, merging it with preceding text.
+fn push_text(content: &mut Vec<Content>, text: String) {
+    if let Some(Content::Inline(ParaContent::Text(prev))) = content.last_mut() {
+        if prev.ends_with(' ') && text.starts_with(' ') {
+            prev.push_str(&text[1..]);
+        } else {
+            prev.push_str(&text);
+        }
+    } else if !text.is_empty() {
+        content.push(Content::Inline(ParaContent::Text(text)));
+    }
+}
+
+/// Generates a function removing whitespace at the start and end of the text in some content.
+macro_rules! trim_content {
+    ($name:ident, $ty:ident) => {
+        fn $name(content: &mut Vec<$ty>) {
+            if let Some($ty::Text(text)) = content.first_mut() {
+                *text = text.trim_start().to_string();
+            }
+            if let Some($ty::Text(text)) = content.last_mut() {
+                *text = text.trim_end().to_string();
+            }
+            content.retain(|node| !matches!(node, $ty::Text(text) if text.is_empty()));
+        }
+    };
+}
+
+trim_content!(trim_para_content, ParaContent);
+trim_content!(trim_char_styles, CharacterStyle);
+trim_content!(trim_mixed_content, MixedContent);
@@ -0,0 +1,970 @@
+//! Conversion between CommonMark and PSML.
+
+use pulldown_cmark::{Alignment as MdAlignment, Event, Options, Parser, Tag};
+
+use super::{char_styles, fragment_content, mixed_content, para_content, Content};
+use crate::{
+    extract::PlainText,
+    model::{
+        href_resolver, BlockXRef, Document, Fragment, FragmentContent, List, ListItem,
+        MixedContent, NumberedList, Property, PropertyValue, Section, SectionContent, Table,
+        TableCell, TableColumn, TablePart, TableRow, TableRowContent, XRef, XRefResolver,
+    },
+    text::{
+        Alignment, Bold, CharacterStyle, Heading, Image, Italic, Link, Monospace, Para,
+        ParaContent, Subscript, Superscript,
+    },
+};
+
+/// Where to start new fragments when converting a Markdown document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentSplit {
+    /// Put all content in a section in a single fragment.
+    Section,
+    /// Start a new fragment at each heading of this level or above.
+    Heading(u8),
+}
+
+/// Options for converting Markdown to a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkdownOptions {
+    /// Where to start new fragments.
+    pub split: FragmentSplit,
+    /// Start a new section at each heading of this level or above.
+    /// If none, all content is put in a single section with the id `content`.
+    pub section_level: Option<u8>,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            split: FragmentSplit::Heading(6),
+            section_level: None,
+        }
+    }
+}
+
+/// Parses CommonMark into fragment content.
+/// Raw HTML, thematic breaks and task list markers are dropped.
+pub fn to_content(markdown: &str) -> Vec<FragmentContent> {
+    let mut builder = Builder::new();
+    for event in Parser::new_ext(markdown, parser_options()) {
+        builder.event(event);
+    }
+    builder.finish()
+}
+
+/// Parses CommonMark into a document, splitting the content into sections and fragments.
+/// Fragments are given sequential numeric ids.
+pub fn to_document(markdown: &str, options: &MarkdownOptions) -> Document {
+    let mut doc = Document::default();
+    let mut section: Option<Section> = None;
+    let mut fragment: Option<Fragment> = None;
+    let mut frag_count = 0;
+
+    for content in to_content(markdown) {
+        if let FragmentContent::Heading(heading) = &content {
+            let new_section = options
+                .section_level
+                .is_some_and(|level| heading.level <= level);
+            let new_fragment = match options.split {
+                FragmentSplit::Section => new_section,
+                FragmentSplit::Heading(level) => heading.level <= level,
+            };
+
+            if new_fragment {
+                if let Some(frag) = fragment.take() {
+                    section
+                        .get_or_insert_with(|| new_doc_section(&doc, options))
+                        .content
+                        .push(SectionContent::Fragment(frag));
+                }
+            }
+            if new_section {
+                if let Some(sect) = section.take() {
+                    doc.sections.push(sect);
+                }
+                let mut sect = new_doc_section(&doc, options);
+                sect.title = Some(heading.plain_text());
+                section = Some(sect);
+            }
+        }
+
+        fragment
+            .get_or_insert_with(|| {
+                frag_count += 1;
+                Fragment::new(frag_count.to_string())
+            })
+            .content
+            .push(content);
+    }
+
+    if let Some(frag) = fragment {
+        section
+            .get_or_insert_with(|| new_doc_section(&doc, options))
+            .content
+            .push(SectionContent::Fragment(frag));
+    }
+    if let Some(sect) = section {
+        doc.sections.push(sect);
+    }
+    doc
+}
+
+fn parser_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_SUPERSCRIPT
+        | Options::ENABLE_SUBSCRIPT
+}
+
+/// Creates the next section for a document.
+fn new_doc_section(doc: &Document, options: &MarkdownOptions) -> Section {
+    match options.section_level {
+        None => Section::new("content".to_string()),
+        Some(_) => Section::new(format!("section-{}", doc.sections.len() + 1)),
+    }
+}
+
+/// A converted node waiting to be added to its parent.
+enum Node {
+    Block(FragmentContent),
+    Inline(ParaContent),
+    Item(ListItem),
+    Row(TableRow),
+    Cell(TableCell),
+}
+
+/// A Markdown element that has been started but not ended.
+enum Frame {
+    Root,
+    Paragraph,
+    Heading(u8),
+    BlockQuote,
+    CodeBlock,
+    List(Option<u64>),
+    Item,
+    Table(Vec<MdAlignment>),
+    TableHead,
+    TableRow,
+    TableCell,
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Superscript,
+    Subscript,
+    Link(String),
+    Image(String),
+    /// Element whose content is dropped.
+    Ignored,
+}
+
+/// Builds PSML content from a stream of Markdown events.
+struct Builder {
+    stack: Vec<(Frame, Vec<Node>)>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            stack: vec![(Frame::Root, vec![])],
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(_) => self.end(),
+            Event::Text(text) => self.push(Node::Inline(ParaContent::Text(text.into_string()))),
+            Event::Code(code) => self.push(Node::Inline(ParaContent::Monospace(Monospace::text(
+                code.into_string(),
+            )))),
+            Event::SoftBreak => self.push(Node::Inline(ParaContent::Text(" ".to_string()))),
+            Event::HardBreak => self.push(Node::Inline(ParaContent::Br)),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        let frame = match tag {
+            Tag::Paragraph => Frame::Paragraph,
+            Tag::Heading { level, .. } => Frame::Heading(level as u8),
+            Tag::BlockQuote(_) => Frame::BlockQuote,
+            Tag::CodeBlock(_) => Frame::CodeBlock,
+            Tag::List(start) => Frame::List(start),
+            Tag::Item => Frame::Item,
+            Tag::Table(alignments) => Frame::Table(alignments),
+            Tag::TableHead => Frame::TableHead,
+            Tag::TableRow => Frame::TableRow,
+            Tag::TableCell => Frame::TableCell,
+            Tag::Emphasis => Frame::Emphasis,
+            Tag::Strong => Frame::Strong,
+            Tag::Strikethrough => Frame::Strikethrough,
+            Tag::Superscript => Frame::Superscript,
+            Tag::Subscript => Frame::Subscript,
+            Tag::Link { dest_url, .. } => Frame::Link(dest_url.into_string()),
+            Tag::Image { dest_url, .. } => Frame::Image(dest_url.into_string()),
+            _ => Frame::Ignored,
+        };
+        self.stack.push((frame, vec![]));
+    }
+
+    fn end(&mut self) {
+        let Some((frame, nodes)) = self.stack.pop() else {
+            return;
+        };
+
+        match frame {
+            Frame::Root | Frame::Ignored => {}
+            Frame::Paragraph => self.push(Node::Block(FragmentContent::Para(Para::new(
+                para_content(contents(nodes)),
+            )))),
+            Frame::Heading(level) => self.push(Node::Block(FragmentContent::Heading(Heading {
+                level,
+                content: char_styles(contents(nodes)),
+            }))),
+            Frame::BlockQuote => self.push(Node::Block(FragmentContent::Block {
+                label: None,
+                child: fragment_content(contents(nodes)),
+            })),
+            Frame::CodeBlock => {
+                let mut child = char_styles(contents(nodes));
+                if let Some(CharacterStyle::Text(text)) = child.last_mut() {
+                    if text.ends_with('\n') {
+                        text.pop();
+                    }
+                }
+                self.push(Node::Block(FragmentContent::Preformat { child }))
+            }
+            Frame::List(start) => {
+                let items = nodes
+                    .into_iter()
+                    .filter_map(|node| match node {
+                        Node::Item(item) => Some(item),
+                        _ => None,
+                    })
+                    .collect();
+                self.push(Node::Block(match start {
+                    None => FragmentContent::List(List::new(items)),
+                    Some(start) => FragmentContent::NumberedList(NumberedList {
+                        start: (start != 1).then_some(start),
+                        ..NumberedList::new(items)
+                    }),
+                }))
+            }
+            Frame::Item => self.push(Node::Item(ListItem::new(mixed_content(contents(nodes))))),
+            Frame::Table(alignments) => self.push(Node::Block(FragmentContent::Table(Table {
+                caption: None,
+                role: None,
+                summary: None,
+                height: None,
+                width: None,
+                cols: alignments
+                    .into_iter()
+                    .map(|align| TableColumn {
+                        align: alignment(align),
+                        ..Default::default()
+                    })
+                    .collect(),
+                rows: nodes
+                    .into_iter()
+                    .filter_map(|node| match node {
+                        Node::Row(row) => Some(row),
+                        _ => None,
+                    })
+                    .collect(),
+            }))),
+            Frame::TableHead | Frame::TableRow => {
+                let header = matches!(frame, Frame::TableHead);
+                self.push(Node::Row(TableRow {
+                    part: header.then_some(TablePart::Header),
+                    cells: nodes
+                        .into_iter()
+                        .filter_map(|node| match node {
+                            Node::Cell(cell) if header => Some(TableRowContent::HeaderCell(cell)),
+                            Node::Cell(cell) => Some(TableRowContent::Cell(cell)),
+                            _ => None,
+                        })
+                        .collect(),
+                    ..Default::default()
+                }))
+            }
+            Frame::TableCell => {
+                self.push(Node::Cell(TableCell::new(mixed_content(contents(nodes)))))
+            }
+            Frame::Emphasis => self.push(Node::Inline(ParaContent::Italic(Italic::new(
+                char_styles(contents(nodes)),
+            )))),
+            Frame::Strong => self.push(Node::Inline(ParaContent::Bold(Bold::new(char_styles(
+                contents(nodes),
+            ))))),
+            Frame::Superscript => self.push(Node::Inline(ParaContent::Superscript(
+                Superscript::new(char_styles(contents(nodes))),
+            ))),
+            Frame::Subscript => self.push(Node::Inline(ParaContent::Subscript(Subscript::new(
+                char_styles(contents(nodes)),
+            )))),
+            // PSML has no strikethrough, so only the content is kept.
+            Frame::Strikethrough => {
+                for node in nodes {
+                    self.push(node);
+                }
+            }
+            Frame::Link(href) => self.push(Node::Inline(ParaContent::Link(
+                Link::new(char_styles(contents(nodes))).with_href(href),
+            ))),
+            Frame::Image(src) => {
+                let alt = Para::new(para_content(contents(nodes))).plain_text();
+                self.push(Node::Inline(ParaContent::Image(
+                    Image::from_src(src).with_alt((!alt.is_empty()).then_some(alt)),
+                )))
+            }
+        }
+    }
+
+    /// Adds a node to the innermost open element, merging adjacent text.
+    fn push(&mut self, node: Node) {
+        let Some((frame, nodes)) = self.stack.last_mut() else {
+            return;
+        };
+        if matches!(frame, Frame::Ignored) {
+            return;
+        }
+
+        if let Node::Inline(ParaContent::Text(text)) = &node {
+            if let Some(Node::Inline(ParaContent::Text(prev))) = nodes.last_mut() {
+                prev.push_str(text);
+                return;
+            }
+        }
+        nodes.push(node);
+    }
+
+    fn finish(mut self) -> Vec<FragmentContent> {
+        while self.stack.len() > 1 {
+            self.end();
+        }
+        self.stack
+            .pop()
+            .map(|(_, nodes)| fragment_content(contents(nodes)))
+            .unwrap_or_default()
+    }
+}
+
+fn alignment(align: MdAlignment) -> Option<Alignment> {
+    match align {
+        MdAlignment::None => None,
+        MdAlignment::Left => Some(Alignment::Left),
+        MdAlignment::Center => Some(Alignment::Center),
+        MdAlignment::Right => Some(Alignment::Right),
+    }
+}
+
+/// Collects the inline and block nodes.
+fn contents(nodes: Vec<Node>) -> Vec<Content> {
+    nodes
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Block(block) => Some(Content::Block(block)),
+            Node::Inline(inline) => Some(Content::Inline(inline)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Rendering
+
+/// How properties fragments are rendered to Markdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PropertiesStyle {
+    /// Collect the properties from every properties fragment into YAML front-matter.
+    #[default]
+    FrontMatter,
+    /// Render each properties fragment as a table of property titles and values.
+    Table,
+    /// Leave properties out of the output.
+    Omit,
+}
+
+/// Options for rendering PSML as Markdown.
+#[derive(Clone, Copy)]
+pub struct RenderOptions<'r> {
+    /// How to render properties fragments.
+    pub properties: PropertiesStyle,
+    /// Resolves the URLs for xrefs, block xrefs and images.
+    pub resolver: &'r dyn XRefResolver,
+}
+
+impl Default for RenderOptions<'static> {
+    fn default() -> Self {
+        Self {
+            properties: PropertiesStyle::default(),
+            resolver: &href_resolver,
+        }
+    }
+}
+
+/// Renders a document as GitHub flavoured Markdown.
+/// Media fragments are not rendered.
+pub fn from_document(doc: &Document, options: &RenderOptions) -> String {
+    let renderer = Renderer::new(options);
+    let mut blocks = vec![];
+
+    if options.properties == PropertiesStyle::FrontMatter {
+        let properties: Vec<&Property> = doc
+            .sections
+            .iter()
+            .flat_map(|section| &section.content)
+            .filter_map(|content| match content {
+                SectionContent::PropertiesFragment(frag) => Some(&frag.properties),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        if !properties.is_empty() {
+            blocks.push(renderer.front_matter(&properties));
+        }
+    }
+
+    for section in &doc.sections {
+        for content in &section.content {
+            match content {
+                SectionContent::Fragment(frag) => blocks.push(renderer.blocks(&frag.content)),
+                SectionContent::PropertiesFragment(frag) => {
+                    if options.properties == PropertiesStyle::Table && !frag.properties.is_empty() {
+                        blocks.push(renderer.properties_table(&frag.properties))
+                    }
+                }
+                SectionContent::XRefFragment(frag) => {
+                    blocks.extend(frag.xrefs.iter().map(|xref| renderer.block_xref(xref)))
+                }
+                SectionContent::Media(_) | SectionContent::Title { .. } => {}
+            }
+        }
+    }
+
+    finish_markdown(blocks)
+}
+
+/// Renders fragment content as GitHub flavoured Markdown.
+pub fn from_content(content: &[FragmentContent], options: &RenderOptions) -> String {
+    finish_markdown(vec![Renderer::new(options).blocks(content)])
+}
+
+fn finish_markdown(blocks: Vec<String>) -> String {
+    let mut markdown = join_blocks(blocks);
+    if !markdown.is_empty() {
+        markdown.push('\n');
+    }
+    markdown
+}
+
+/// Rendered Markdown for a node.
+enum Piece {
+    Inline(String),
+    Block(String),
+}
+
+/// Content that can be rendered as Markdown.
+trait ToMarkdown {
+    fn to_markdown(&self, renderer: &Renderer) -> Piece;
+}
+
+#[derive(Clone, Copy)]
+struct Renderer<'o, 'r> {
+    options: &'o RenderOptions<'r>,
+    /// Whether the content is being rendered inside a table cell.
+    in_table: bool,
+}
+
+impl<'o, 'r> Renderer<'o, 'r> {
+    fn new(options: &'o RenderOptions<'r>) -> Self {
+        Self {
+            options,
+            in_table: false,
+        }
+    }
+
+    /// Renders block content, separating blocks with blank lines.
+    fn blocks(&self, content: &[FragmentContent]) -> String {
+        join_blocks(content.iter().map(|block| self.block(block)))
+    }
+
+    fn block(&self, content: &FragmentContent) -> String {
+        match content {
+            FragmentContent::Text(text) => escape_block_start(&escape(text.trim())),
+            FragmentContent::Heading(heading) => format!(
+                "{} {}",
+                "#".repeat(heading.level.clamp(1, 6) as usize),
+                self.inlines(&heading.content)
+            ),
+            FragmentContent::Block { child, .. } => quote(&self.blocks(child)),
+            FragmentContent::BlockXRef(xref) => self.block_xref(xref),
+            FragmentContent::Para(para) => self.para(para),
+            FragmentContent::Preformat { child } => code_block(&child.plain_text()),
+            FragmentContent::Image(image) => self.image(image),
+            FragmentContent::Table(table) => self.table(table),
+            FragmentContent::List(list) => self.list(&list.items, None),
+            FragmentContent::NumberedList(nlist) => {
+                self.list(&nlist.items, Some(nlist.start.unwrap_or(1)))
+            }
+        }
+    }
+
+    /// Renders inline content with no separators.
+    fn inlines<T: ToMarkdown>(&self, content: &[T]) -> String {
+        content
+            .iter()
+            .map(|node| match node.to_markdown(self) {
+                Piece::Inline(text) | Piece::Block(text) => text,
+            })
+            .collect()
+    }
+
+    /// Renders a mix of inline and block content, treating each run of inline content as a paragraph.
+    /// Blocks are joined with the separator.
+    fn mixed<T: ToMarkdown>(&self, content: &[T], separator: &str) -> String {
+        let mut blocks = vec![];
+        let mut para = String::new();
+        for node in content {
+            match node.to_markdown(self) {
+                Piece::Inline(text) => para.push_str(&text),
+                Piece::Block(block) => {
+                    blocks.push(escape_block_start(std::mem::take(&mut para).trim()));
+                    blocks.push(block);
+                }
+            }
+        }
+        blocks.push(escape_block_start(para.trim()));
+        blocks
+            .into_iter()
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+
+    fn para(&self, para: &Para) -> String {
+        let indent = para.indent.unwrap_or(0) as usize;
+        let mut text = self.inlines(&para.content).trim().to_string();
+        if let Some(prefix) = &para.prefix {
+            text = format!("{} {text}", escape(prefix));
+        } else if para.numbered == Some(true) {
+            return format!("{}1. {text}", "   ".repeat(indent));
+        }
+        format!("{}{}", "&emsp;".repeat(indent), escape_block_start(&text))
+    }
+
+    /// Renders character styles wrapped in a Markdown delimiter,
+    /// keeping surrounding whitespace outside the delimiters.
+    fn delimited(&self, delimiter: &str, content: &[CharacterStyle]) -> String {
+        let text = self.inlines(content);
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return text;
+        }
+        let start = text.len() - text.trim_start().len();
+        let end = start + trimmed.len();
+        format!(
+            "{}{delimiter}{trimmed}{delimiter}{}",
+            &text[..start],
+            &text[end..]
+        )
+    }
+
+    /// Renders character styles wrapped in an HTML element.
+    fn tagged(&self, tag: &str, content: &[CharacterStyle]) -> String {
+        format!("<{tag}>{}</{tag}>", self.inlines(content))
+    }
+
+    fn line_break(&self) -> String {
+        if self.in_table {
+            "<br>".to_string()
+        } else {
+            "\\\n".to_string()
+        }
+    }
+
+    fn link(&self, link: &Link) -> String {
+        let text = self.inlines(&link.content);
+        match &link.href {
+            Some(href) => format!("[{text}]({})", link_destination(href)),
+            None => text,
+        }
+    }
+
+    fn xref(&self, xref: &XRef) -> String {
+        let text = [Some(&xref.content), xref.title.as_ref(), xref.href.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(xref.docid.as_ref())
+            .chain(xref.uriid.as_ref())
+            .find(|text| !text.is_empty())
+            .map(|text| escape(text))
+            .unwrap_or_default();
+        match self.options.resolver.resolve(&xref.into()) {
+            Some(url) => format!("[{text}]({})", link_destination(&url)),
+            None => text,
+        }
+    }
+
+    fn block_xref(&self, xref: &BlockXRef) -> String {
+        let text = [
+            &xref.title,
+            &xref.urititle,
+            &xref.href,
+            &xref.docid,
+            &xref.uriid,
+        ]
+        .into_iter()
+        .flatten()
+        .find(|text| !text.is_empty())
+        .map(|text| escape(text))
+        .unwrap_or_default();
+        match self.options.resolver.resolve(&xref.into()) {
+            Some(url) => format!("[{text}]({})", link_destination(&url)),
+            None => text,
+        }
+    }
+
+    fn image(&self, image: &Image) -> String {
+        let alt = escape(image.alt().unwrap_or(""));
+        match self.options.resolver.resolve(&image.target()) {
+            Some(url) => format!("![{alt}]({})", link_destination(&url)),
+            None => alt,
+        }
+    }
+
+    /// Renders list items with a bullet, or numbers counting from `start`.
+    /// The list is loose if any item contains a paragraph.
+    fn list(&self, items: &[ListItem], start: Option<u64>) -> String {
+        let loose = items.iter().any(|item| {
+            item.content
+                .iter()
+                .any(|content| matches!(content, MixedContent::Para(_)))
+        });
+        let separator = if loose { "\n\n" } else { "\n" };
+
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let marker = match start {
+                    Some(start) => format!("{}. ", start + i as u64),
+                    None => "- ".to_string(),
+                };
+                let indent = " ".repeat(marker.len());
+                let body = self.mixed(&item.content, separator);
+                let mut lines = body.lines();
+                let mut rendered = format!("{marker}{}", lines.next().unwrap_or(""));
+                for line in lines {
+                    rendered.push('\n');
+                    if !line.is_empty() {
+                        rendered.push_str(&indent);
+                        rendered.push_str(line);
+                    }
+                }
+                rendered
+            })
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+
+    fn table(&self, table: &Table) -> String {
+        let renderer = Renderer {
+            in_table: true,
+            ..*self
+        };
+        let mut rows: Vec<(bool, Vec<String>)> = table
+            .rows
+            .iter()
+            .map(|row| {
+                let header = row.part == Some(TablePart::Header)
+                    || (!row.cells.is_empty()
+                        && row
+                            .cells
+                            .iter()
+                            .all(|cell| matches!(cell, TableRowContent::HeaderCell(_))));
+                let cells = row
+                    .cells
+                    .iter()
+                    .map(|cell| renderer.table_cell(cell.cell()))
+                    .collect();
+                (header, cells)
+            })
+            .collect();
+
+        let columns = rows
+            .iter()
+            .map(|(_, cells)| cells.len())
+            .chain([table.cols.len()])
+            .max()
+            .unwrap_or(0);
+        let header = match rows.first() {
+            Some((true, _)) => rows.remove(0).1,
+            _ => vec![],
+        };
+
+        let mut lines = vec![table_line(header, columns)];
+        lines.push(table_line(
+            (0..columns)
+                .map(|i| {
+                    match table.cols.get(i).and_then(|col| col.align.as_ref()) {
+                        Some(Alignment::Left) => ":--",
+                        Some(Alignment::Center) => ":-:",
+                        Some(Alignment::Right) => "--:",
+                        _ => "---",
+                    }
+                    .to_string()
+                })
+                .collect(),
+            columns,
+        ));
+        lines.extend(
+            rows.into_iter()
+                .map(|(_, cells)| table_line(cells, columns)),
+        );
+
+        let rendered = lines.join("\n");
+        match &table.caption {
+            Some(caption) if !caption.caption.trim().is_empty() => {
+                format!("{}\n\n{rendered}", escape(caption.caption.trim()))
+            }
+            _ => rendered,
+        }
+    }
+
+    /// Renders the content of a table cell on a single line.
+    fn table_cell(&self, cell: &TableCell) -> String {
+        self.mixed(&cell.content, "\n")
+            .lines()
+            .collect::<Vec<&str>>()
+            .join("<br>")
+            .replace('|', "\\|")
+    }
+
+    fn property_values(&self, property: &Property) -> Vec<String> {
+        let mut values: Vec<String> = property.attr_value.iter().cloned().collect();
+        values.extend(property.values.iter().map(|value| match value {
+            PropertyValue::XRef(xref) => match xref.content.is_empty() {
+                true => xref.title.clone().unwrap_or_default(),
+                false => xref.content.clone(),
+            },
+            PropertyValue::Markup(markup) => self.inlines(&markup.content),
+            PropertyValue::Link(text)
+            | PropertyValue::Markdown(text)
+            | PropertyValue::Value(text) => text.clone(),
+        }));
+        values
+    }
+
+    /// Renders properties as YAML front-matter.
+    /// Values of properties with the same name are merged into one list.
+    fn front_matter(&self, properties: &[&Property]) -> String {
+        let mut entries: Vec<(&str, bool, Vec<String>)> = vec![];
+        for property in properties {
+            let values = self.property_values(property);
+            let multiple = property.multiple == Some(true) || values.len() > 1;
+            match entries
+                .iter_mut()
+                .find(|(name, _, _)| *name == property.name)
+            {
+                Some((_, entry_multiple, entry_values)) => {
+                    *entry_multiple = true;
+                    entry_values.extend(values);
+                }
+                None => entries.push((&property.name, multiple, values)),
+            }
+        }
+
+        let mut lines = vec!["---".to_string()];
+        for (name, multiple, values) in entries {
+            match (multiple, values.first()) {
+                (true, None) => lines.push(format!("{name}: []")),
+                (true, Some(_)) => {
+                    lines.push(format!("{name}:"));
+                    lines.extend(
+                        values
+                            .iter()
+                            .map(|value| format!("  - {}", yaml_string(value))),
+                    );
+                }
+                (false, value) => lines.push(format!(
+                    "{name}: {}",
+                    yaml_string(value.map(String::as_str).unwrap_or(""))
+                )),
+            }
+        }
+        lines.push("---".to_string());
+        lines.join("\n")
+    }
+
+    fn properties_table(&self, properties: &[Property]) -> String {
+        let mut lines = vec![
+            table_line(vec!["Property".to_string(), "Value".to_string()], 2),
+            table_line(vec!["---".to_string(), "---".to_string()], 2),
+        ];
+        lines.extend(properties.iter().map(|property| {
+            let title = property.title.as_deref().unwrap_or(&property.name);
+            let values = self
+                .property_values(property)
+                .iter()
+                .map(|value| escape(value))
+                .collect::<Vec<String>>()
+                .join(", ");
+            table_line(
+                vec![
+                    escape(title).replace('|', "\\|"),
+                    values.replace('|', "\\|"),
+                ],
+                2,
+            )
+        }));
+        lines.join("\n")
+    }
+}
+
+/// Implements [`ToMarkdown`] for a content enum,
+/// rendering the character style variants and then any extra match arms.
+macro_rules! impl_to_markdown {
+    ($ty:ident, $renderer:ident { $($arm:tt)* }) => {
+        impl ToMarkdown for $ty {
+            fn to_markdown(&self, $renderer: &Renderer) -> Piece {
+                match self {
+                    $ty::Text(text) => Piece::Inline(escape(text)),
+                    $ty::Bold(bold) => Piece::Inline($renderer.delimited("**", &bold.content)),
+                    $ty::Italic(italic) => Piece::Inline($renderer.delimited("*", &italic.content)),
+                    $ty::Underline(underline) => {
+                        Piece::Inline($renderer.tagged("u", &underline.content))
+                    }
+                    $ty::Subscript(sub) => Piece::Inline($renderer.tagged("sub", &sub.content)),
+                    $ty::Superscript(sup) => Piece::Inline($renderer.tagged("sup", &sup.content)),
+                    $ty::Monospace(mono) => Piece::Inline(code_span(&mono.plain_text())),
+                    $ty::XRef(xref) => Piece::Inline($renderer.xref(xref)),
+                    $ty::Link(link) => Piece::Inline($renderer.link(link)),
+                    $ty::Inline(inline) => Piece::Inline($renderer.inlines(&inline.content)),
+                    $ty::Anchor(anchor) => {
+                        Piece::Inline(format!("<a id=\"{}\"></a>", escape(&anchor.name)))
+                    }
+                    $ty::Placeholder(placeholder) => Piece::Inline(escape(&placeholder.content)),
+                    $ty::Br => Piece::Inline($renderer.line_break()),
+                    $($arm)*
+                }
+            }
+        }
+    };
+}
+
+impl_to_markdown!(CharacterStyle, renderer {});
+
+impl_to_markdown!(ParaContent, renderer {
+    ParaContent::Image(image) => Piece::Inline(renderer.image(image)),
+});
+
+impl_to_markdown!(MixedContent, renderer {
+    MixedContent::Image(image) => Piece::Inline(renderer.image(image)),
+    MixedContent::Para(para) => Piece::Block(renderer.para(para)),
+    MixedContent::Block { child, .. } => Piece::Block(quote(&renderer.blocks(child))),
+    MixedContent::Preformat { child } => Piece::Block(code_block(&child.plain_text())),
+    MixedContent::List(list) => Piece::Block(renderer.list(&list.items, None)),
+    MixedContent::NumberedList(nlist) => {
+        Piece::Block(renderer.list(&nlist.items, Some(nlist.start.unwrap_or(1))))
+    }
+});
+
+/// Joins non-empty blocks with blank lines.
+fn join_blocks(blocks: impl IntoIterator<Item = String>) -> String {
+    blocks
+        .into_iter()
+        .filter(|block| !block.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+/// Escapes characters that Markdown would interpret as inline markup.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        if matches!(char, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+/// Escapes text at the start of a block that Markdown would interpret as a block marker.
+fn escape_block_start(text: &str) -> String {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let ends_marker = |rest: &str| rest.is_empty() || rest.starts_with([' ', '\t']);
+    let marker = match text.chars().next() {
+        Some('#') if ends_marker(text.trim_start_matches('#')) => Some(0),
+        Some('-' | '+') if ends_marker(&text[1..]) || text.starts_with("--") => Some(0),
+        Some('|') => Some(0),
+        Some(c) if c.is_ascii_digit() => match text[digits..].chars().next() {
+            Some('.' | ')') if ends_marker(&text[digits + 1..]) => Some(digits),
+            _ => None,
+        },
+        _ => None,
+    };
+    match marker {
+        Some(i) => format!("{}\\{}", &text[..i], &text[i..]),
+        None => text.to_string(),
+    }
+}
+
+/// Prefixes each line with a blockquote marker.
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| match line {
+            "" => ">".to_string(),
+            line => format!("> {line}"),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Returns the length of the longest run of backticks in the text.
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+fn code_span(code: &str) -> String {
+    let code = code.replace('\n', " ");
+    let fence = "`".repeat(longest_backtick_run(&code) + 1);
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{fence} {code} {fence}")
+    } else {
+        format!("{fence}{code}{fence}")
+    }
+}
+
+fn code_block(code: &str) -> String {
+    let fence = "`".repeat(3.max(longest_backtick_run(code) + 1));
+    format!("{fence}\n{code}\n{fence}")
+}
+
+/// Wraps a link destination in angle brackets if it contains characters that would end it.
+fn link_destination(url: &str) -> String {
+    if url.contains([' ', '(', ')']) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_string()
+    }
+}
+
+/// Renders a row of a GFM table, padding it to the number of columns.
+fn table_line(mut cells: Vec<String>, columns: usize) -> String {
+    cells.resize(columns, String::new());
+    format!("| {} |", cells.join(" | "))
+}
+
+/// Quotes a string as a YAML scalar.
+fn yaml_string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
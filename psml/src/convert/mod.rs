@@ -0,0 +1,117 @@
+//! Conversion between PSML and other formats.
+
+use crate::{
+    model::{FragmentContent, MixedContent},
+    text::{CharacterStyle, Para, ParaContent},
+};
+
+pub mod html;
+pub mod markdown;
+
+/// Inline or block content converted from another format.
+pub(crate) enum Content {
+    Inline(ParaContent),
+    Block(FragmentContent),
+}
+
+/// Collects block nodes, wrapping any loose inline nodes in paragraphs.
+pub(crate) fn fragment_content(content: Vec<Content>) -> Vec<FragmentContent> {
+    let mut blocks = vec![];
+    let mut inlines = vec![];
+    for node in content {
+        match node {
+            Content::Block(block) => {
+                if !inlines.is_empty() {
+                    blocks.push(FragmentContent::Para(Para::new(std::mem::take(
+                        &mut inlines,
+                    ))));
+                }
+                blocks.push(block);
+            }
+            Content::Inline(inline) => inlines.push(inline),
+        }
+    }
+    if !inlines.is_empty() {
+        blocks.push(FragmentContent::Para(Para::new(inlines)));
+    }
+    blocks
+}
+
+/// Collects the inline content, dropping any blocks.
+pub(crate) fn para_content(content: Vec<Content>) -> Vec<ParaContent> {
+    content
+        .into_iter()
+        .filter_map(|node| match node {
+            Content::Inline(inline) => Some(inline),
+            Content::Block(_) => None,
+        })
+        .collect()
+}
+
+/// Collects the inline content as character styles, replacing images with their alternate text.
+pub(crate) fn char_styles(content: Vec<Content>) -> Vec<CharacterStyle> {
+    para_content(content)
+        .into_iter()
+        .filter_map(char_style)
+        .collect()
+}
+
+/// Converts inline content to a character style, replacing an image with its alternate text.
+pub(crate) fn char_style(inline: ParaContent) -> Option<CharacterStyle> {
+    Some(match inline {
+        ParaContent::Text(text) => CharacterStyle::Text(text),
+        ParaContent::Bold(bold) => CharacterStyle::Bold(bold),
+        ParaContent::Italic(italic) => CharacterStyle::Italic(italic),
+        ParaContent::Underline(underline) => CharacterStyle::Underline(underline),
+        ParaContent::Subscript(sub) => CharacterStyle::Subscript(sub),
+        ParaContent::Superscript(sup) => CharacterStyle::Superscript(sup),
+        ParaContent::Monospace(mono) => CharacterStyle::Monospace(mono),
+        ParaContent::XRef(xref) => CharacterStyle::XRef(Box::new(xref)),
+        ParaContent::Link(link) => CharacterStyle::Link(link),
+        ParaContent::Inline(inline) => CharacterStyle::Inline(inline),
+        ParaContent::Anchor(anchor) => CharacterStyle::Anchor(anchor),
+        ParaContent::Placeholder(placeholder) => CharacterStyle::Placeholder(placeholder),
+        ParaContent::Br => CharacterStyle::Br,
+        ParaContent::Image(image) => CharacterStyle::Text(image.alt()?.to_string()),
+    })
+}
+
+/// Collects content for an element that allows both inline and block content.
+pub(crate) fn mixed_content(content: Vec<Content>) -> Vec<MixedContent> {
+    content
+        .into_iter()
+        .map(|node| match node {
+            Content::Inline(inline) => match inline {
+                ParaContent::Text(text) => MixedContent::Text(text),
+                ParaContent::Bold(bold) => MixedContent::Bold(bold),
+                ParaContent::Italic(italic) => MixedContent::Italic(italic),
+                ParaContent::Underline(underline) => MixedContent::Underline(underline),
+                ParaContent::Subscript(sub) => MixedContent::Subscript(sub),
+                ParaContent::Superscript(sup) => MixedContent::Superscript(sup),
+                ParaContent::Monospace(mono) => MixedContent::Monospace(mono),
+                ParaContent::XRef(xref) => MixedContent::XRef(Box::new(xref)),
+                ParaContent::Link(link) => MixedContent::Link(link),
+                ParaContent::Inline(inline) => MixedContent::Inline(inline),
+                ParaContent::Anchor(anchor) => MixedContent::Anchor(anchor),
+                ParaContent::Placeholder(placeholder) => MixedContent::Placeholder(placeholder),
+                ParaContent::Br => MixedContent::Br,
+                ParaContent::Image(image) => MixedContent::Image(image),
+            },
+            Content::Block(block) => match block {
+                FragmentContent::Text(text) => MixedContent::Text(text),
+                FragmentContent::Para(para) => MixedContent::Para(para),
+                FragmentContent::Image(image) => MixedContent::Image(image),
+                FragmentContent::Block { label, child } => MixedContent::Block { label, child },
+                FragmentContent::Preformat { child } => MixedContent::Preformat { child },
+                FragmentContent::List(list) => MixedContent::List(list),
+                FragmentContent::NumberedList(nlist) => MixedContent::NumberedList(nlist),
+                other @ (FragmentContent::Heading(_)
+                | FragmentContent::BlockXRef(_)
+                | FragmentContent::Table(_)) => MixedContent::Block {
+                    label: None,
+                    child: vec![other],
+                },
+            },
+        })
+        .collect()
+}
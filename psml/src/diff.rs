@@ -0,0 +1,431 @@
+//! Structural differences between PSML documents.
+//!
+//! Sections and fragments are matched by ID. Fragments that were modified
+//! also have an inline diff of their PSML, split into tags, words and whitespace.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::PsmlResult,
+    model::{Document, Section, SectionContent},
+    xml::serialise,
+};
+
+/// The location of a section or fragment in a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    /// ID of the section.
+    pub section: String,
+    /// Index of the section in the document, or of the fragment in its section.
+    pub index: usize,
+}
+
+/// A difference between two documents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    SectionAdded {
+        section: String,
+        index: usize,
+    },
+    SectionRemoved {
+        section: String,
+    },
+    /// The section is in a different position relative to the other sections.
+    SectionMoved {
+        section: String,
+        from: usize,
+        to: usize,
+    },
+    /// The attributes or title of the section changed.
+    SectionModified {
+        section: String,
+    },
+    FragmentAdded {
+        fragment: String,
+        to: Position,
+    },
+    FragmentRemoved {
+        fragment: String,
+        from: Position,
+    },
+    /// The fragment is in a different section, or in a different position
+    /// relative to the other fragments in its section.
+    FragmentMoved {
+        fragment: String,
+        from: Position,
+        to: Position,
+    },
+    /// The content or attributes of the fragment changed.
+    FragmentModified {
+        fragment: String,
+        section: String,
+        edits: Vec<Edit>,
+    },
+}
+
+/// A span of PSML in an inline diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Equal(String),
+    Insert(String),
+    Delete(String),
+}
+
+/// A fragment to upload to apply a modification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentUpdate<'a> {
+    pub section: &'a str,
+    pub fragment: &'a str,
+    /// The PSML of the fragment in the new document.
+    pub psml: String,
+}
+
+/// Returns the changes that turn `old` into `new`, in the order of `new` with removals last.
+pub fn diff(old: &Document, new: &Document) -> PsmlResult<Vec<Change>> {
+    let mut changes = vec![];
+
+    let old_sections: HashMap<&str, (usize, &Section)> = old
+        .sections
+        .iter()
+        .enumerate()
+        .map(|(index, section)| (section.id.as_str(), (index, section)))
+        .collect();
+    let section_ids: Vec<&str> = new.sections.iter().map(|s| s.id.as_str()).collect();
+    let new_sections: HashSet<&str> = section_ids.iter().copied().collect();
+    let unmoved_sections = unmoved(
+        &old.sections
+            .iter()
+            .map(|s| s.id.as_str())
+            .filter(|id| new_sections.contains(id))
+            .collect::<Vec<_>>(),
+        &section_ids
+            .iter()
+            .copied()
+            .filter(|id| old_sections.contains_key(id))
+            .collect::<Vec<_>>(),
+    );
+
+    let old_fragments = fragments(old);
+    let new_fragments = fragments(new);
+
+    for (index, section) in new.sections.iter().enumerate() {
+        match old_sections.get(section.id.as_str()) {
+            None => changes.push(Change::SectionAdded {
+                section: section.id.clone(),
+                index,
+            }),
+            Some((from, old_section)) => {
+                if !unmoved_sections.contains(section.id.as_str()) {
+                    changes.push(Change::SectionMoved {
+                        section: section.id.clone(),
+                        from: *from,
+                        to: index,
+                    });
+                }
+                if !same_attributes(old_section, section) {
+                    changes.push(Change::SectionModified {
+                        section: section.id.clone(),
+                    });
+                }
+            }
+        }
+
+        let old_ids: Vec<&str> = old_sections
+            .get(section.id.as_str())
+            .map(|(_, s)| fragment_ids(s))
+            .unwrap_or_default();
+        let new_ids = fragment_ids(section);
+        let old_set: HashSet<&str> = old_ids.iter().copied().collect();
+        let new_set: HashSet<&str> = new_ids.iter().copied().collect();
+        let unmoved_fragments = unmoved(
+            &old_ids
+                .iter()
+                .copied()
+                .filter(|id| new_set.contains(id))
+                .collect::<Vec<_>>(),
+            &new_ids
+                .iter()
+                .copied()
+                .filter(|id| old_set.contains(id))
+                .collect::<Vec<_>>(),
+        );
+
+        for id in &new_ids {
+            let (to, new_fragment) = &new_fragments[id];
+            let Some((from, old_fragment)) = old_fragments.get(id) else {
+                changes.push(Change::FragmentAdded {
+                    fragment: id.to_string(),
+                    to: to.clone(),
+                });
+                continue;
+            };
+            if !unmoved_fragments.contains(id) {
+                changes.push(Change::FragmentMoved {
+                    fragment: id.to_string(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+            if old_fragment != new_fragment {
+                changes.push(Change::FragmentModified {
+                    fragment: id.to_string(),
+                    section: section.id.clone(),
+                    edits: inline_diff(
+                        &serialise(old_fragment, None, None)?,
+                        &serialise(new_fragment, None, None)?,
+                    ),
+                });
+            }
+        }
+    }
+
+    for section in &old.sections {
+        for id in fragment_ids(section) {
+            if !new_fragments.contains_key(id) {
+                changes.push(Change::FragmentRemoved {
+                    fragment: id.to_string(),
+                    from: old_fragments[id].0.clone(),
+                });
+            }
+        }
+    }
+    for section in &old.sections {
+        if !new_sections.contains(section.id.as_str()) {
+            changes.push(Change::SectionRemoved {
+                section: section.id.clone(),
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Returns the PSML of each fragment in `new` that was modified,
+/// for uploading to the server copy of `old`.
+pub fn fragment_updates<'a>(
+    changes: &'a [Change],
+    new: &Document,
+) -> PsmlResult<Vec<FragmentUpdate<'a>>> {
+    let new_fragments = fragments(new);
+    changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::FragmentModified {
+                fragment, section, ..
+            } => Some((section, fragment)),
+            _ => None,
+        })
+        .map(|(section, fragment)| {
+            Ok(FragmentUpdate {
+                section,
+                fragment,
+                psml: serialise(new_fragments[fragment.as_str()].1, None, None)?,
+            })
+        })
+        .collect()
+}
+
+/// Returns the ID of a fragment, or none for a section title.
+pub(crate) fn fragment_id(content: &SectionContent) -> Option<&str> {
+    match content {
+        SectionContent::Fragment(fragment) => Some(&fragment.id),
+        SectionContent::PropertiesFragment(fragment) => Some(&fragment.id),
+        SectionContent::XRefFragment(fragment) => Some(&fragment.id),
+        SectionContent::Media(fragment) => Some(&fragment.id),
+        SectionContent::Title { .. } => None,
+    }
+}
+
+fn fragment_ids(section: &Section) -> Vec<&str> {
+    section.content.iter().filter_map(fragment_id).collect()
+}
+
+/// Returns the position and content of each fragment in a document by ID.
+fn fragments(document: &Document) -> HashMap<&str, (Position, &SectionContent)> {
+    let mut fragments = HashMap::new();
+    for section in &document.sections {
+        let mut index = 0;
+        for content in &section.content {
+            if let Some(id) = fragment_id(content) {
+                let position = Position {
+                    section: section.id.clone(),
+                    index,
+                };
+                fragments.insert(id, (position, content));
+                index += 1;
+            }
+        }
+    }
+    fragments
+}
+
+/// Returns true if the sections are the same apart from their fragments.
+fn same_attributes(old: &Section, new: &Section) -> bool {
+    let titles = |section: &Section| -> Vec<SectionContent> {
+        section
+            .content
+            .iter()
+            .filter(|content| fragment_id(content).is_none())
+            .cloned()
+            .collect()
+    };
+    Section {
+        content: titles(old),
+        ..old.clone()
+    } == Section {
+        content: titles(new),
+        ..new.clone()
+    }
+}
+
+/// Returns the items of `new` that are in the same order in `old`.
+/// Both must contain the same items.
+fn unmoved<'a>(old: &[&'a str], new: &[&'a str]) -> HashSet<&'a str> {
+    lcs(old, new)
+        .into_iter()
+        .filter_map(|op| match op {
+            Op::Equal(_, j) => Some(new[j]),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the edits that turn the PSML `old` into `new`.
+pub fn inline_diff(old: &str, new: &str) -> Vec<Edit> {
+    let old = tokens(old);
+    let new = tokens(new);
+
+    let mut edits: Vec<Edit> = vec![];
+    for op in lcs(&old, &new) {
+        let edit = match op {
+            Op::Equal(i, _) => Edit::Equal(old[i].to_string()),
+            Op::Delete(i) => Edit::Delete(old[i].to_string()),
+            Op::Insert(j) => Edit::Insert(new[j].to_string()),
+        };
+        // Adjacent tokens with the same edit are merged into one span.
+        let merged = match (edits.last_mut(), &edit) {
+            (Some(Edit::Equal(last)), Edit::Equal(text))
+            | (Some(Edit::Insert(last)), Edit::Insert(text))
+            | (Some(Edit::Delete(last)), Edit::Delete(text)) => {
+                last.push_str(text);
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            edits.push(edit);
+        }
+    }
+    edits
+}
+
+/// Splits PSML into tags, words and runs of whitespace.
+pub(crate) fn tokens(xml: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut chars = xml.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let end = if c == '<' {
+            xml[i..]
+                .find('>')
+                .map(|end| i + end + 1)
+                .unwrap_or(xml.len())
+        } else {
+            let space = c.is_whitespace();
+            let mut end = xml.len();
+            while let Some(&(j, next)) = chars.peek() {
+                if next == '<' || next.is_whitespace() != space {
+                    end = j;
+                    break;
+                }
+                chars.next();
+            }
+            end
+        };
+        tokens.push(&xml[i..end]);
+        while chars.peek().is_some_and(|&(j, _)| j < end) {
+            chars.next();
+        }
+    }
+    tokens
+}
+
+pub(crate) enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Returns the operations of a longest common subsequence diff, in order.
+/// Uses Hirschberg's algorithm, so memory is linear in the length of the input.
+pub(crate) fn lcs<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    hirschberg(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        (prefix, prefix),
+        &mut ops,
+    );
+    ops.extend((0..suffix).map(|k| Op::Equal(old.len() - suffix + k, new.len() - suffix + k)));
+    ops
+}
+
+/// Appends the operations of a longest common subsequence diff of `a` and `b`,
+/// whose first items are at `offset` in the old and new input.
+fn hirschberg<T: PartialEq>(a: &[T], b: &[T], offset: (usize, usize), ops: &mut Vec<Op>) {
+    let (i, j) = offset;
+    match a {
+        [] => ops.extend((0..b.len()).map(|k| Op::Insert(j + k))),
+        [item] => match b.iter().position(|other| other == item) {
+            Some(k) => {
+                ops.extend((0..k).map(|k| Op::Insert(j + k)));
+                ops.push(Op::Equal(i, j + k));
+                ops.extend((k + 1..b.len()).map(|k| Op::Insert(j + k)));
+            }
+            None => {
+                ops.push(Op::Delete(i));
+                ops.extend((0..b.len()).map(|k| Op::Insert(j + k)));
+            }
+        },
+        _ if b.is_empty() => ops.extend((0..a.len()).map(|k| Op::Delete(i + k))),
+        _ => {
+            // Split `b` where the LCS of the first half of `a` with its start
+            // and the second half with its end is longest.
+            let mid = a.len() / 2;
+            let forward = lcs_lengths(a[..mid].iter(), b.iter());
+            let backward = lcs_lengths(a[mid..].iter().rev(), b.iter().rev());
+            let split = (0..=b.len())
+                .max_by_key(|&k| (forward[k] + backward[b.len() - k], std::cmp::Reverse(k)))
+                .unwrap_or_default();
+            hirschberg(&a[..mid], &b[..split], (i, j), ops);
+            hirschberg(&a[mid..], &b[split..], (i + mid, j + split), ops);
+        }
+    }
+}
+
+/// Returns the length of the LCS of `a` with each prefix of `b`, by the length of the prefix.
+fn lcs_lengths<'a, T: PartialEq + 'a>(
+    a: impl Iterator<Item = &'a T>,
+    b: impl Iterator<Item = &'a T> + Clone,
+) -> Vec<usize> {
+    let mut lengths = vec![0; b.clone().count() + 1];
+    for x in a {
+        let mut diagonal = 0;
+        for (k, y) in b.clone().enumerate() {
+            let above = lengths[k + 1];
+            lengths[k + 1] = match x == y {
+                true => diagonal + 1,
+                false => above.max(lengths[k]),
+            };
+            diagonal = above;
+        }
+    }
+    lengths
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use quick_xml::{events::Event, DeError, Reader};
+use thiserror::Error;
+
+use crate::validate::Violation;
+
+#[derive(Debug, Error)]
+pub enum PsmlError {
+    #[error("failed while parsing PSML at line {line}, column {column} ({path}): {source}")]
+    ParseError {
+        line: usize,
+        column: usize,
+        /// Path to the element being parsed when the error occurred, e.g. `/document/section[@id='body']/fragment`.
+        path: String,
+        #[source]
+        source: DeError,
+    },
+    #[error("failed while serialising PSML: {0}")]
+    SerialiseError(#[source] DeError),
+    #[error("failed while reading or writing PSML: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("failed while reading {path}: {source}")]
+    FileError {
+        path: PathBuf,
+        #[source]
+        source: Box<PsmlError>,
+    },
+    #[error("failed while streaming PSML: {msg}")]
+    StreamError { msg: String },
+    #[error("invalid value for property {name}: {msg}")]
+    PropertyError { name: String, msg: String },
+    #[error("invalid label {label:?}: labels may only contain letters, digits, '_' and '-'")]
+    LabelError { label: String },
+    #[error("{msg} {id:?}")]
+    FragmentError { id: String, msg: String },
+    #[error("failed to compose document: {msg}")]
+    ComposeError { msg: String },
+    #[error(
+        "invalid PSML document: {}",
+        .violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    InvalidDocument { violations: Vec<Violation> },
+}
+
+pub type PsmlResult<T> = Result<T, PsmlError>;
+
+impl PsmlError {
+    /// Creates a parse error for a failure at byte `offset` in `xml`.
+    pub(crate) fn parse(xml: &str, offset: usize, source: DeError) -> Self {
+        let mut offset = offset.min(xml.len());
+        while !xml.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let before = &xml[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = match before.rfind('\n') {
+            Some(idx) => before[idx + 1..].chars().count() + 1,
+            None => before.chars().count() + 1,
+        };
+
+        PsmlError::ParseError {
+            line,
+            column,
+            path: element_path(before),
+            source,
+        }
+    }
+}
+
+/// Returns the path to the innermost element left open at the end of `xml`.
+fn element_path(xml: &str) -> String {
+    let mut reader = Reader::from_str(xml);
+    let mut stack = Vec::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(start)) => {
+                let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                let id = start
+                    .try_get_attribute("id")
+                    .ok()
+                    .flatten()
+                    .and_then(|attr| attr.unescape_value().ok().map(|id| id.into_owned()));
+                stack.push(match id {
+                    Some(id) => format!("{name}[@id='{id}']"),
+                    None => name,
+                });
+            }
+            Ok(Event::End(_)) => {
+                stack.pop();
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+
+    format!("/{}", stack.join("/"))
+}
@@ -0,0 +1,223 @@
+//! Plain text extraction from PSML content.
+
+use crate::{
+    model::{
+        Document, Fragment, FragmentContent, ListItem, MediaFragment, PropertiesFragment, Property,
+        PropertyValue, Section, SectionContent, Table, TableRow, XRef,
+    },
+    text::{CharacterStyle, Heading, Monospace, Para, Placeholder},
+    visit::{self, Visitor},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Options for extracting plain text.
+pub struct TextOptions {
+    /// Whether to include the values of properties in properties fragments,
+    /// one property per line prefixed by its title.
+    pub properties: bool,
+    /// IDs of the sections to extract text from.
+    /// If none, text is extracted from all sections.
+    pub sections: Option<Vec<String>>,
+}
+
+/// Content that can be flattened to plain text.
+pub trait PlainText {
+    /// Returns the text in this content with the given options.
+    fn plain_text_with(&self, options: &TextOptions) -> String;
+
+    /// Returns the text in this content with the default options.
+    fn plain_text(&self) -> String {
+        self.plain_text_with(&TextOptions::default())
+    }
+}
+
+/// Visitor that collects text, separating blocks with newlines.
+struct TextExtractor<'o> {
+    options: &'o TextOptions,
+    text: String,
+}
+
+impl<'o> TextExtractor<'o> {
+    fn new(options: &'o TextOptions) -> Self {
+        Self {
+            options,
+            text: String::new(),
+        }
+    }
+
+    /// Ends the current line, if there is one.
+    fn end_block(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+
+    fn finish(self) -> String {
+        self.text.trim_end().to_string()
+    }
+}
+
+impl<'a> Visitor<'a> for TextExtractor<'_> {
+    fn visit_section(&mut self, node: &'a Section) {
+        if let Some(sections) = &self.options.sections {
+            if !sections.contains(&node.id) {
+                return;
+            }
+        }
+
+        if let Some(title) = &node.content_title {
+            self.visit_section_title(title);
+        }
+        visit::walk_section(self, node);
+    }
+
+    fn visit_section_title(&mut self, title: &'a str) {
+        self.end_block();
+        self.text.push_str(title);
+        self.end_block();
+    }
+
+    fn visit_section_content(&mut self, node: &'a SectionContent) {
+        self.end_block();
+        visit::walk_section_content(self, node);
+        self.end_block();
+    }
+
+    fn visit_properties_fragment(&mut self, node: &'a PropertiesFragment) {
+        if self.options.properties {
+            visit::walk_properties_fragment(self, node);
+        }
+    }
+
+    fn visit_property(&mut self, node: &'a Property) {
+        let values = property_values(node);
+        if values.is_empty() {
+            return;
+        }
+
+        self.end_block();
+        self.text
+            .push_str(node.title.as_deref().unwrap_or(&node.name));
+        self.text.push_str(": ");
+        self.text.push_str(&values.join(", "));
+        self.end_block();
+    }
+
+    fn visit_media_fragment(&mut self, _node: &'a MediaFragment) {}
+
+    fn visit_block(&mut self, label: Option<&'a str>, child: &'a [FragmentContent]) {
+        self.end_block();
+        visit::walk_block(self, label, child);
+        self.end_block();
+    }
+
+    fn visit_preformat(&mut self, child: &'a [CharacterStyle]) {
+        self.end_block();
+        visit::walk_preformat(self, child);
+        self.end_block();
+    }
+
+    fn visit_heading(&mut self, node: &'a Heading) {
+        self.end_block();
+        visit::walk_heading(self, node);
+        self.end_block();
+    }
+
+    fn visit_para(&mut self, node: &'a Para) {
+        self.end_block();
+        if let Some(prefix) = &node.prefix {
+            self.text.push_str(prefix);
+            self.text.push(' ');
+        }
+        visit::walk_para(self, node);
+        self.end_block();
+    }
+
+    fn visit_table(&mut self, node: &'a Table) {
+        self.end_block();
+        if let Some(caption) = &node.caption {
+            if !caption.caption.is_empty() {
+                self.text.push_str(&caption.caption);
+                self.end_block();
+            }
+        }
+        visit::walk_table(self, node);
+    }
+
+    fn visit_table_row(&mut self, node: &'a TableRow) {
+        let cells = node
+            .cells
+            .iter()
+            .map(|cell| {
+                let mut extractor = TextExtractor::new(self.options);
+                extractor.visit_table_cell(cell.cell());
+                extractor
+                    .finish()
+                    .split_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+            })
+            .collect::<Vec<String>>();
+
+        self.end_block();
+        self.text.push_str(&cells.join("\t"));
+        self.text.push('\n');
+    }
+
+    fn visit_list_item(&mut self, node: &'a ListItem) {
+        self.end_block();
+        visit::walk_list_item(self, node);
+        self.end_block();
+    }
+
+    fn visit_text(&mut self, text: &'a str) {
+        self.text.push_str(text);
+    }
+
+    fn visit_xref(&mut self, node: &'a XRef) {
+        self.text.push_str(&node.content);
+    }
+
+    fn visit_placeholder(&mut self, node: &'a Placeholder) {
+        self.text.push_str(&node.content);
+    }
+
+    fn visit_br(&mut self) {
+        self.text.push('\n');
+    }
+}
+
+/// Returns the text of each value of the property.
+fn property_values(property: &Property) -> Vec<String> {
+    let mut values: Vec<String> = property.attr_value.iter().cloned().collect();
+    values.extend(property.values.iter().map(|value| match value {
+        PropertyValue::XRef(xref) => xref.content.clone(),
+        PropertyValue::Markup(markup) => markup.content.plain_text(),
+        PropertyValue::Link(text) | PropertyValue::Markdown(text) | PropertyValue::Value(text) => {
+            text.clone()
+        }
+    }));
+    values
+}
+
+macro_rules! impl_plain_text {
+    ($ty:ty, $visit:ident) => {
+        impl PlainText for $ty {
+            fn plain_text_with(&self, options: &TextOptions) -> String {
+                let mut extractor = TextExtractor::new(options);
+                extractor.$visit(self);
+                extractor.finish()
+            }
+        }
+    };
+}
+
+impl_plain_text!(Document, visit_document);
+impl_plain_text!(Section, visit_section);
+impl_plain_text!(SectionContent, visit_section_content);
+impl_plain_text!(Fragment, visit_fragment);
+impl_plain_text!(Para, visit_para);
+impl_plain_text!(Heading, visit_heading);
+impl_plain_text!(Table, visit_table);
+impl_plain_text!(Monospace, visit_monospace);
+impl_plain_text!([CharacterStyle], visit_preformat);
@@ -0,0 +1,256 @@
+//! Finding, numbering and rearranging the fragments of a document by ID.
+
+use std::collections::HashMap;
+
+use crate::{
+    diff::fragment_id,
+    error::{PsmlError, PsmlResult},
+    model::{Document, Fragments, Section, SectionContent},
+    toc::TocPart,
+    xrefs::{self, is_internal, NewTarget},
+};
+
+/// Where to put a fragment in a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentPosition<'a> {
+    /// Before the fragment with the ID.
+    Before(&'a str),
+    /// After the fragment with the ID.
+    After(&'a str),
+    /// At the start of the section with the ID.
+    First(&'a str),
+    /// At the end of the section with the ID.
+    Last(&'a str),
+}
+
+pub(crate) fn fragment_id_mut(content: &mut SectionContent) -> Option<&mut String> {
+    match content {
+        SectionContent::Fragment(fragment) => Some(&mut fragment.id),
+        SectionContent::PropertiesFragment(fragment) => Some(&mut fragment.id),
+        SectionContent::XRefFragment(fragment) => Some(&mut fragment.id),
+        SectionContent::Media(fragment) => Some(&mut fragment.id),
+        SectionContent::Title { .. } => None,
+    }
+}
+
+fn into_fragments(content: SectionContent) -> Option<Fragments> {
+    match content {
+        SectionContent::Fragment(fragment) => Some(Fragments::Fragment(fragment)),
+        SectionContent::PropertiesFragment(fragment) => Some(Fragments::Properties(fragment)),
+        SectionContent::XRefFragment(fragment) => Some(Fragments::Xref(fragment)),
+        SectionContent::Media(fragment) => Some(Fragments::Media(fragment)),
+        SectionContent::Title { .. } => None,
+    }
+}
+
+fn from_fragments(fragment: Fragments) -> SectionContent {
+    match fragment {
+        Fragments::Fragment(fragment) => SectionContent::Fragment(fragment),
+        Fragments::Properties(fragment) => SectionContent::PropertiesFragment(fragment),
+        Fragments::Xref(fragment) => SectionContent::XRefFragment(fragment),
+        Fragments::Media(fragment) => SectionContent::Media(fragment),
+    }
+}
+
+/// Adds one to a number written in decimal digits.
+fn increment(digits: &str) -> String {
+    let mut digits = digits.as_bytes().to_vec();
+    for digit in digits.iter_mut().rev() {
+        if *digit == b'9' {
+            *digit = b'0';
+        } else {
+            *digit += 1;
+            return String::from_utf8(digits).unwrap_or_default();
+        }
+    }
+    digits.insert(0, b'1');
+    String::from_utf8(digits).unwrap_or_default()
+}
+
+fn error(id: &str, msg: &str) -> PsmlError {
+    PsmlError::FragmentError {
+        id: id.to_string(),
+        msg: msg.to_string(),
+    }
+}
+
+impl Document {
+    /// Returns the IDs of all fragments, in document order.
+    pub fn fragment_ids(&self) -> Vec<&str> {
+        self.sections
+            .iter()
+            .flat_map(|section| section.content.iter().filter_map(fragment_id))
+            .collect()
+    }
+
+    /// Returns the index of the section and the index in its content of the fragment with the ID.
+    fn locate_fragment(&self, id: &str) -> Option<(usize, usize)> {
+        self.sections.iter().enumerate().find_map(|(i, section)| {
+            let j = section
+                .content
+                .iter()
+                .position(|content| fragment_id(content) == Some(id))?;
+            Some((i, j))
+        })
+    }
+
+    /// Returns the fragment with the ID in any section.
+    pub fn find_fragment(&self, id: &str) -> Option<&SectionContent> {
+        let (i, j) = self.locate_fragment(id)?;
+        Some(&self.sections[i].content[j])
+    }
+
+    /// Returns the fragment with the ID in any section mutably.
+    pub fn find_fragment_mut(&mut self, id: &str) -> Option<&mut SectionContent> {
+        let (i, j) = self.locate_fragment(id)?;
+        Some(&mut self.sections[i].content[j])
+    }
+
+    /// Returns the section containing the fragment with the ID.
+    pub fn fragment_section(&self, id: &str) -> Option<&Section> {
+        let (i, _) = self.locate_fragment(id)?;
+        Some(&self.sections[i])
+    }
+
+    /// Returns one more than the largest numeric fragment ID, or 1 if there are none.
+    /// IDs of any number of digits are counted.
+    pub fn next_fragment_id(&self) -> String {
+        let max = self
+            .fragment_ids()
+            .into_iter()
+            .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+            .map(|id| match id.trim_start_matches('0') {
+                "" => "0",
+                digits => digits,
+            })
+            .max_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)))
+            .unwrap_or("0");
+        increment(max)
+    }
+
+    /// Gives the fragments consecutive numeric IDs in document order, starting at `first`,
+    /// and updates the locators, the table of contents and the xrefs within this document
+    /// that target them.
+    /// Returns the new ID of each fragment by its old ID.
+    ///
+    /// An xref is within this document if it has no target, or targets this document's
+    /// uriid or docid.
+    pub fn renumber_fragments(&mut self, first: u64) -> HashMap<String, String> {
+        let mut ids = HashMap::new();
+        // Counted in a wider type so IDs after u64::MAX do not overflow.
+        let mut next = u128::from(first);
+        for section in &mut self.sections {
+            for id in section.content.iter_mut().filter_map(fragment_id_mut) {
+                let new = next.to_string();
+                next += 1;
+                ids.insert(std::mem::replace(id, new.clone()), new);
+            }
+        }
+
+        for locator in &mut self.frag_info {
+            if let Some(new) = locator.fragment_id.as_ref().and_then(|id| ids.get(id)) {
+                locator.fragment_id = Some(new.clone());
+            }
+        }
+
+        fn renumber_parts(parts: &mut [TocPart], ids: &HashMap<String, String>) {
+            for part in parts {
+                if let Some(new) = ids.get(&part.fragment) {
+                    part.fragment = new.clone();
+                }
+                renumber_parts(&mut part.parts, ids);
+            }
+        }
+        if let Some(toc) = &mut self.toc {
+            renumber_parts(&mut toc.tree.parts, &ids);
+        }
+
+        let uriid = self.uriid().map(str::to_string);
+        let docid = self.docid().map(str::to_string);
+        xrefs::rewrite(std::iter::once(&mut *self), |target| {
+            if !is_internal(target, uriid.as_deref(), docid.as_deref()) {
+                return None;
+            }
+            let frag = ids.get(target.frag)?;
+            Some(NewTarget {
+                frag: frag.clone(),
+                ..target.into()
+            })
+        });
+        ids
+    }
+
+    /// Removes the fragment with the ID and returns it.
+    pub fn remove_fragment(&mut self, id: &str) -> Option<Fragments> {
+        let (i, j) = self.locate_fragment(id)?;
+        into_fragments(self.sections[i].content.remove(j))
+    }
+
+    /// Inserts a fragment at the position.
+    /// Returns an error if a fragment with the same ID exists,
+    /// or the fragment or section to insert relative to does not.
+    pub fn insert_fragment(
+        &mut self,
+        fragment: Fragments,
+        position: FragmentPosition,
+    ) -> PsmlResult<()> {
+        let content = from_fragments(fragment);
+        let id = fragment_id(&content).unwrap_or_default();
+        if self.locate_fragment(id).is_some() {
+            return Err(error(id, "duplicate fragment id"));
+        }
+
+        let (i, j) = self.resolve_position(position)?;
+        self.sections[i].content.insert(j, content);
+        Ok(())
+    }
+
+    /// Moves the fragment with the ID to the position.
+    /// Returns an error if either fragment or the section does not exist,
+    /// or the fragment would be moved relative to itself.
+    pub fn move_fragment(&mut self, id: &str, position: FragmentPosition) -> PsmlResult<()> {
+        if let FragmentPosition::Before(anchor) | FragmentPosition::After(anchor) = position {
+            if anchor == id {
+                return Err(error(id, "cannot move a fragment relative to itself"));
+            }
+        }
+        self.resolve_position(position)?;
+        let fragment = self
+            .remove_fragment(id)
+            .ok_or_else(|| error(id, "no fragment with id"))?;
+        self.insert_fragment(fragment, position)
+    }
+
+    /// Returns the section index and content index to insert a fragment at.
+    fn resolve_position(&self, position: FragmentPosition) -> PsmlResult<(usize, usize)> {
+        let section = |id: &str| {
+            self.sections
+                .iter()
+                .position(|section| section.id == id)
+                .ok_or_else(|| error(id, "no section with id"))
+        };
+        match position {
+            FragmentPosition::Before(id) => self
+                .locate_fragment(id)
+                .ok_or_else(|| error(id, "no fragment with id")),
+            FragmentPosition::After(id) => self
+                .locate_fragment(id)
+                .map(|(i, j)| (i, j + 1))
+                .ok_or_else(|| error(id, "no fragment with id")),
+            FragmentPosition::First(id) => {
+                let i = section(id)?;
+                // Keep the section title before the fragments.
+                let j = self.sections[i]
+                    .content
+                    .iter()
+                    .take_while(|content| fragment_id(content).is_none())
+                    .count();
+                Ok((i, j))
+            }
+            FragmentPosition::Last(id) => {
+                let i = section(id)?;
+                Ok((i, self.sections[i].content.len()))
+            }
+        }
+    }
+}
@@ -1,4 +1,31 @@
+pub mod builder;
+pub mod check;
+pub mod convert;
+pub mod diff;
+pub mod error;
+pub mod extract;
+pub mod fragments;
+pub mod lossless;
+pub mod merge;
 pub mod model;
+pub mod properties;
+pub mod render;
+pub mod split;
+pub mod stream;
 #[cfg(test)]
 pub mod tests;
 pub mod text;
+pub mod toc;
+pub mod validate;
+pub mod visit;
+pub mod visit_mut;
+mod xml;
+pub mod xrefs;
+
+pub use error::{PsmlError, PsmlResult};
+#[cfg(feature = "derive")]
+pub use psml_derive::PsmlProperties;
+pub use xml::{
+    from_reader, from_str, to_string, to_string_with_options, to_writer, to_writer_with_options,
+    WriteOptions,
+};
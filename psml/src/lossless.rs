@@ -0,0 +1,697 @@
+//! Lossless reading and writing of PSML documents.
+//!
+//! The model in [`crate::model`] does not cover the whole PSML spec, and serde will either drop
+//! or fail on anything it does not recognise. Reading a document through this module instead
+//! captures every unrecognised element and attribute, every comment in the document element,
+//! and any text the model drops as an [`Extension`], which is re-emitted verbatim when the
+//! document is written back out.
+
+use std::collections::HashMap;
+
+use quick_xml::{
+    escape::{escape, partial_escape},
+    events::{BytesStart, Event},
+    DeError, Reader,
+};
+
+use crate::{
+    error::{PsmlError, PsmlResult},
+    model::Document,
+    xml::{is_mixed, serialise},
+};
+
+/// A node in an element the model does not recognise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnknownNode {
+    Element(UnknownElement),
+    Text(String),
+    CData(String),
+    Comment(String),
+}
+
+/// An element the model does not recognise, with all of its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownElement {
+    /// Qualified name of the element.
+    pub name: String,
+    /// Attributes on the element, in document order.
+    pub attrs: Vec<(String, String)>,
+    /// Child nodes of the element.
+    pub children: Vec<UnknownNode>,
+}
+
+impl UnknownElement {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the attribute with the given name.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns an iterator over the child elements.
+    fn child_elements(&self) -> impl Iterator<Item = &UnknownElement> {
+        self.children.iter().filter_map(|node| match node {
+            UnknownNode::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// Returns the nth child element with the given name.
+    fn nth_child(&self, name: &str, n: usize) -> Option<&UnknownElement> {
+        self.child_elements()
+            .filter(|element| element.name == name)
+            .nth(n)
+    }
+
+    /// Returns the index in `children` of the nth child element with the given name.
+    fn nth_child_index(&self, name: &str, n: usize) -> Option<usize> {
+        self.children
+            .iter()
+            .enumerate()
+            .filter(
+                |(_, node)| matches!(node, UnknownNode::Element(element) if element.name == name),
+            )
+            .nth(n)
+            .map(|(idx, _)| idx)
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push('<');
+        out.push_str(&self.name);
+        for (key, value) in &self.attrs {
+            out.push(' ');
+            out.push_str(key);
+            out.push_str("=\"");
+            out.push_str(&escape(value));
+            out.push('"');
+        }
+
+        if self.children.is_empty() {
+            out.push_str("/>");
+            return;
+        }
+
+        out.push('>');
+        for child in &self.children {
+            match child {
+                UnknownNode::Element(element) => element.write(out),
+                UnknownNode::Text(text) => out.push_str(&partial_escape(text)),
+                UnknownNode::CData(text) => {
+                    out.push_str("<![CDATA[");
+                    out.push_str(text);
+                    out.push_str("]]>");
+                }
+                UnknownNode::Comment(text) => {
+                    out.push_str("<!--");
+                    out.push_str(text);
+                    out.push_str("-->");
+                }
+            }
+        }
+        out.push_str("</");
+        out.push_str(&self.name);
+        out.push('>');
+    }
+}
+
+/// An element name and its index among siblings with the same name.
+pub type PathStep = (String, usize);
+
+/// Content that was not recognised by the model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionNode {
+    /// An attribute on the element at the extension path.
+    Attribute { name: String, value: String },
+    /// A child of the element at the extension path.
+    Element {
+        /// The recognised sibling element this one followed.
+        /// If none, the element came before any recognised sibling elements.
+        after: Option<PathStep>,
+        /// Number of characters of modelled text between `after`, or the start of the parent,
+        /// and the element.
+        offset: usize,
+        element: UnknownElement,
+    },
+    /// A comment in the element at the extension path.
+    Comment {
+        /// The recognised sibling element this one followed.
+        /// If none, the comment came before any recognised sibling elements.
+        after: Option<PathStep>,
+        /// Number of characters of modelled text between `after`, or the start of the parent,
+        /// and the comment.
+        offset: usize,
+        text: String,
+    },
+    /// Content of the element at the extension path that the model drops as a whole,
+    /// such as text in a table row, with any unknown elements and comments in it.
+    Content {
+        /// The recognised sibling element the content followed.
+        /// If none, the content came before any recognised sibling elements.
+        after: Option<PathStep>,
+        nodes: Vec<UnknownNode>,
+    },
+}
+
+/// Unrecognised content and its location in the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    /// Path from the document element to the parent of this extension.
+    pub path: Vec<PathStep>,
+    pub node: ExtensionNode,
+}
+
+/// A PSML document along with any content the model does not recognise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LosslessDocument {
+    pub document: Document,
+    pub extensions: Vec<Extension>,
+}
+
+/// Element names allowed as children of elements whose content is deserialized as an enum.
+/// Any other child would fail deserialization and must be removed first.
+/// Returns none for elements whose unknown children are simply ignored by serde.
+fn known_children(parent: &str) -> Option<&'static [&'static str]> {
+    const CHAR_STYLES: &[&str] = &[
+        "bold",
+        "italic",
+        "underline",
+        "subscript",
+        "superscript",
+        "monospace",
+        "xref",
+        "link",
+        "inline",
+        "anchor",
+        "placeholder",
+        "br",
+    ];
+
+    match parent {
+        "section" => Some(&[
+            "title",
+            "fragment",
+            "properties-fragment",
+            "xref-fragment",
+            "media-fragment",
+        ]),
+        "fragment" | "block" => Some(&[
+            "heading",
+            "block",
+            "blockxref",
+            "para",
+            "preformat",
+            "image",
+            "table",
+            "list",
+            "nlist",
+        ]),
+        "heading" | "preformat" | "bold" | "italic" | "underline" | "subscript" | "superscript"
+        | "monospace" | "link" | "inline" => Some(CHAR_STYLES),
+        "para" => Some(&[
+            "bold",
+            "italic",
+            "underline",
+            "subscript",
+            "superscript",
+            "monospace",
+            "xref",
+            "link",
+            "inline",
+            "anchor",
+            "placeholder",
+            "br",
+            "image",
+        ]),
+        "item" | "cell" | "hcell" => Some(&[
+            "bold",
+            "italic",
+            "underline",
+            "subscript",
+            "superscript",
+            "monospace",
+            "xref",
+            "link",
+            "inline",
+            "anchor",
+            "placeholder",
+            "br",
+            "image",
+            "para",
+            "block",
+            "preformat",
+            "list",
+            "nlist",
+        ]),
+        "row" => Some(&["cell", "hcell"]),
+        "property" => Some(&["xref", "link", "markdown", "markup", "value"]),
+        "xref" | "placeholder" | "caption" => Some(&[]),
+        _ => None,
+    }
+}
+
+/// Creates an empty element from a start tag.
+fn element_from_start(start: &BytesStart) -> Result<UnknownElement, DeError> {
+    let mut element = UnknownElement::new(String::from_utf8(start.name().as_ref().to_vec())?);
+    for attr in start.attributes() {
+        let attr = attr?;
+        element.attrs.push((
+            String::from_utf8(attr.key.as_ref().to_vec())?,
+            attr.unescape_value()?.into_owned(),
+        ));
+    }
+    Ok(element)
+}
+
+/// Parses an XML string into a tree.
+fn parse_tree(xml: &str) -> PsmlResult<UnknownElement> {
+    let mut reader = Reader::from_str(xml);
+    read_tree(&mut reader).map_err(|err| PsmlError::parse(xml, reader.buffer_position(), err))
+}
+
+/// Reads the next element from the reader into a tree.
+fn read_tree(reader: &mut Reader<&[u8]>) -> Result<UnknownElement, DeError> {
+    let mut stack: Vec<UnknownElement> = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => stack.push(element_from_start(&start)?),
+            Event::Empty(start) => {
+                let element = element_from_start(&start)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(UnknownNode::Element(element)),
+                    None => return Ok(element),
+                }
+            }
+            Event::End(_) => {
+                let element = stack
+                    .pop()
+                    .ok_or_else(|| DeError::Custom("unmatched end tag".to_string()))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(UnknownNode::Element(element)),
+                    None => return Ok(element),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent
+                        .children
+                        .push(UnknownNode::Text(text.unescape()?.into_owned()));
+                }
+            }
+            Event::CData(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(UnknownNode::CData(String::from_utf8(
+                        text.into_inner().to_vec(),
+                    )?));
+                }
+            }
+            Event::Comment(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(UnknownNode::Comment(String::from_utf8(
+                        text.into_inner().to_vec(),
+                    )?));
+                }
+            }
+            Event::Decl(_) | Event::PI(_) | Event::DocType(_) => {}
+            Event::Eof => return Err(DeError::UnexpectedEof),
+        }
+    }
+}
+
+/// Returns whether the character is whitespace the deserializer trims.
+fn is_xml_whitespace(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+/// Returns whether the text has anything other than whitespace.
+fn has_text(text: &str) -> bool {
+    !text.trim_matches(is_xml_whitespace).is_empty()
+}
+
+/// Returns a copy of the tree with children that would fail deserialization removed.
+fn strip_unknown(element: &UnknownElement) -> UnknownElement {
+    // Markup in media fragments is modelled as their content.
+    if element.name == "media-fragment" {
+        return element.clone();
+    }
+    let known = known_children(&element.name);
+    let is_removed = |node: Option<&UnknownNode>| match (node, known) {
+        (Some(UnknownNode::Element(child)), Some(names)) => !names.contains(&child.name.as_str()),
+        _ => false,
+    };
+    let is_element = |node: Option<&UnknownNode>| matches!(node, Some(UnknownNode::Element(_)));
+    let mixed = is_mixed(&element.name)
+        || element.children.iter().any(|node| match node {
+            UnknownNode::Text(text) => has_text(text),
+            UnknownNode::CData(_) => true,
+            _ => false,
+        });
+
+    // Text in elements whose content is only elements would fail deserialization.
+    let drops_text = matches!(
+        element.name.as_str(),
+        "section" | "table" | "row" | "property"
+    );
+
+    let mut children = vec![];
+    for (idx, node) in element.children.iter().enumerate() {
+        let before = idx.checked_sub(1).and_then(|idx| element.children.get(idx));
+        let after = element.children.get(idx + 1);
+        match node {
+            UnknownNode::Text(_) | UnknownNode::CData(_) if drops_text => {}
+            UnknownNode::Element(child) => {
+                if !is_removed(Some(node)) {
+                    children.push(UnknownNode::Element(strip_unknown(child)));
+                }
+            }
+            // Whitespace the deserializer keeps next to an element would be at the edge of the
+            // text once the element is removed, so is kept as CDATA, which is never trimmed.
+            UnknownNode::Text(text) if is_removed(before) || is_removed(after) => {
+                let trimmed = text.trim_matches(is_xml_whitespace);
+                if trimmed.is_empty() {
+                    if mixed && is_element(before) && is_element(after) {
+                        children.push(UnknownNode::CData(text.clone()));
+                    }
+                    continue;
+                }
+                let start = match is_removed(before) {
+                    true => text.len() - text.trim_start_matches(is_xml_whitespace).len(),
+                    false => 0,
+                };
+                let end = match is_removed(after) {
+                    true => text.trim_end_matches(is_xml_whitespace).len(),
+                    false => text.len(),
+                };
+                if start > 0 {
+                    children.push(UnknownNode::CData(text[..start].to_string()));
+                }
+                children.push(UnknownNode::Text(text[start..end].to_string()));
+                if end < text.len() {
+                    children.push(UnknownNode::CData(text[end..].to_string()));
+                }
+            }
+            other => children.push(other.clone()),
+        }
+    }
+
+    UnknownElement {
+        name: element.name.clone(),
+        attrs: element.attrs.clone(),
+        children,
+    }
+}
+
+/// Records everything in `original` that is missing from `modelled` as extensions.
+fn collect_extensions(
+    original: &UnknownElement,
+    modelled: &UnknownElement,
+    path: &mut Vec<PathStep>,
+    extensions: &mut Vec<Extension>,
+) {
+    for (name, value) in &original.attrs {
+        if modelled.attr(name).is_none() {
+            extensions.push(Extension {
+                path: path.clone(),
+                node: ExtensionNode::Attribute {
+                    name: name.clone(),
+                    value: value.clone(),
+                },
+            });
+        }
+    }
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut after = None;
+    // Comments are only modelled as part of the markup in media fragments.
+    let mut comments = 0;
+    let modelled_comments = modelled
+        .children
+        .iter()
+        .filter(|node| matches!(node, UnknownNode::Comment(_)))
+        .count();
+    // Nodes since the last recognised element, their text, and the unknown elements and comments
+    // in it with their offsets.
+    let mut run = Run::default();
+    for node in &original.children {
+        let child = match node {
+            UnknownNode::Text(text) | UnknownNode::CData(text) => {
+                run.text.push_str(text);
+                run.nodes.push(node);
+                continue;
+            }
+            UnknownNode::Comment(_) => {
+                comments += 1;
+                if comments > modelled_comments {
+                    run.unknown.push((run.text.chars().count(), node));
+                }
+                run.nodes.push(node);
+                continue;
+            }
+            UnknownNode::Element(child) => child,
+        };
+        let count = counts.entry(&child.name).or_default();
+        let idx = *count;
+        *count += 1;
+
+        match modelled.nth_child(&child.name, idx) {
+            Some(modelled_child) => {
+                run.push_unknown(modelled, &after, path, extensions);
+                path.push((child.name.clone(), idx));
+                collect_extensions(child, modelled_child, path, extensions);
+                path.pop();
+                after = Some((child.name.clone(), idx));
+            }
+            None => {
+                run.unknown.push((run.text.chars().count(), node));
+                run.nodes.push(node);
+            }
+        }
+    }
+    run.push_unknown(modelled, &after, path, extensions);
+}
+
+/// The nodes of an element between two recognised children.
+#[derive(Default)]
+struct Run<'a> {
+    nodes: Vec<&'a UnknownNode>,
+    text: String,
+    /// Unknown elements and comments, with the number of characters of text before them.
+    unknown: Vec<(usize, &'a UnknownNode)>,
+}
+
+impl Run<'_> {
+    /// Records the unknown nodes found in the text after a recognised element as extensions,
+    /// with their offsets in the original text moved to the text as modelled,
+    /// and clears the run.
+    /// The deserializer may trim whitespace from the edges of the text, or drop all of it.
+    fn push_unknown(
+        &mut self,
+        modelled: &UnknownElement,
+        after: &Option<PathStep>,
+        path: &[PathStep],
+        extensions: &mut Vec<Extension>,
+    ) {
+        let modelled_text = text_after(modelled, after.as_ref());
+        if modelled_text.is_empty()
+            && has_text(&self.text)
+            && !modelled.children.iter().any(|node| match node {
+                UnknownNode::Text(text) | UnknownNode::CData(text) => has_text(text),
+                _ => false,
+            })
+        {
+            extensions.push(Extension {
+                path: path.to_vec(),
+                node: ExtensionNode::Content {
+                    after: after.clone(),
+                    nodes: self.nodes.drain(..).cloned().collect(),
+                },
+            });
+            self.text.clear();
+            self.unknown.clear();
+            return;
+        }
+
+        let trimmed = match modelled_text.is_empty() {
+            true => 0,
+            false => self
+                .text
+                .find(&modelled_text)
+                .map_or(0, |idx| self.text[..idx].chars().count()),
+        };
+        let len = modelled_text.chars().count();
+        for (offset, node) in self.unknown.drain(..) {
+            let (after, offset) = (after.clone(), offset.saturating_sub(trimmed).min(len));
+            let node = match node {
+                UnknownNode::Comment(text) => ExtensionNode::Comment {
+                    after,
+                    offset,
+                    text: text.clone(),
+                },
+                UnknownNode::Element(element) => ExtensionNode::Element {
+                    after,
+                    offset,
+                    element: element.clone(),
+                },
+                UnknownNode::Text(_) | UnknownNode::CData(_) => continue,
+            };
+            extensions.push(Extension {
+                path: path.to_vec(),
+                node,
+            });
+        }
+        self.nodes.clear();
+        self.text.clear();
+    }
+}
+
+/// Returns the text between a child element, or the start of the element, and the next child.
+fn text_after(element: &UnknownElement, after: Option<&PathStep>) -> String {
+    let start = match after {
+        Some((name, idx)) => match element.nth_child_index(name, *idx) {
+            Some(anchor) => anchor + 1,
+            None => return String::new(),
+        },
+        None => 0,
+    };
+    element.children[start..]
+        .iter()
+        .map_while(|node| match node {
+            UnknownNode::Text(text) | UnknownNode::CData(text) => Some(text.as_str()),
+            UnknownNode::Comment(_) => Some(""),
+            UnknownNode::Element(_) => None,
+        })
+        .collect()
+}
+
+/// Returns the element at the path below `root`, if it exists.
+fn find_mut<'a>(root: &'a mut UnknownElement, path: &[PathStep]) -> Option<&'a mut UnknownElement> {
+    let mut current = root;
+    for (name, idx) in path {
+        let child_idx = current.nth_child_index(name, *idx)?;
+        current = match &mut current.children[child_idx] {
+            UnknownNode::Element(element) => element,
+            _ => unreachable!("index of child element points to element"),
+        };
+    }
+    Some(current)
+}
+
+/// Inserts the extensions into the tree.
+/// Extensions whose location no longer exists in the tree are skipped.
+fn insert_extensions(root: &mut UnknownElement, extensions: &[Extension]) {
+    // Number of elements already inserted after each anchor, so consecutive unknown siblings
+    // keep their order.
+    let mut inserted: HashMap<(&[PathStep], Option<&PathStep>), usize> = HashMap::new();
+
+    for extension in extensions {
+        let Some(parent) = find_mut(root, &extension.path) else {
+            continue;
+        };
+
+        let (after, offset, nodes) = match &extension.node {
+            ExtensionNode::Attribute { name, value } => {
+                if parent.attr(name).is_none() {
+                    parent.attrs.push((name.clone(), value.clone()));
+                }
+                continue;
+            }
+            ExtensionNode::Element {
+                after,
+                offset,
+                element,
+            } => (after, *offset, vec![UnknownNode::Element(element.clone())]),
+            ExtensionNode::Comment {
+                after,
+                offset,
+                text,
+            } => (after, *offset, vec![UnknownNode::Comment(text.clone())]),
+            ExtensionNode::Content { after, nodes } => (after, 0, nodes.clone()),
+        };
+
+        let mut idx = match after {
+            Some((name, idx)) => match parent.nth_child_index(name, *idx) {
+                Some(anchor) => anchor + 1,
+                None => parent.children.len(),
+            },
+            None => 0,
+        };
+
+        // Skip the text before the nodes, and any elements already inserted at or
+        // before the same offset, so consecutive unknown siblings keep their order.
+        let inserted = inserted
+            .entry((extension.path.as_slice(), after.as_ref()))
+            .or_default();
+        let mut remaining = offset;
+        let mut skipped = 0;
+        while idx < parent.children.len() {
+            match &mut parent.children[idx] {
+                UnknownNode::Text(text) | UnknownNode::CData(text) => {
+                    let len = text.chars().count();
+                    if remaining == 0 {
+                        break;
+                    }
+                    if remaining < len {
+                        let split = text
+                            .char_indices()
+                            .nth(remaining)
+                            .map_or(text.len(), |(idx, _)| idx);
+                        let rest = text.split_off(split);
+                        parent.children.insert(idx + 1, UnknownNode::Text(rest));
+                        idx += 1;
+                        break;
+                    }
+                    remaining -= len;
+                }
+                UnknownNode::Comment(_) => {}
+                UnknownNode::Element(_) if skipped < *inserted => skipped += 1,
+                UnknownNode::Element(_) => break,
+            }
+            idx += 1;
+        }
+
+        *inserted += nodes
+            .iter()
+            .filter(|node| matches!(node, UnknownNode::Element(_)))
+            .count();
+        parent.children.splice(idx..idx, nodes);
+    }
+}
+
+/// Reads a document, capturing any content not recognised by the model as extensions.
+/// Errors in content recognised by the model are located relative to the document with the
+/// unrecognised content removed.
+pub fn from_str(xml: &str) -> PsmlResult<LosslessDocument> {
+    let original = parse_tree(xml)?;
+
+    let mut stripped = String::new();
+    strip_unknown(&original).write(&mut stripped);
+    let document = crate::from_str(&stripped)?;
+
+    let modelled = parse_tree(&serialise(&document, None, None)?)?;
+    let mut extensions = Vec::new();
+    collect_extensions(&original, &modelled, &mut Vec::new(), &mut extensions);
+
+    Ok(LosslessDocument {
+        document,
+        extensions,
+    })
+}
+
+/// Writes a document, re-emitting its extensions in their original locations.
+pub fn to_string(document: &LosslessDocument) -> PsmlResult<String> {
+    let mut tree = parse_tree(&serialise(&document.document, None, None)?)?;
+    insert_extensions(&mut tree, &document.extensions);
+
+    let mut out = String::new();
+    tree.write(&mut out);
+    Ok(out)
+}
@@ -0,0 +1,631 @@
+//! Three-way merge of PSML documents.
+//!
+//! Fragments are merged by ID, and properties in properties fragments by name,
+//! counting repeated names in order.
+//! A change made on one side only is taken from that side. When both sides changed
+//! the same fragment or property differently, the conflict is reported and our version is kept.
+//!
+//! The sections and fragments of the merged document are in our order.
+//! Sections and fragments added by them are placed after the item preceding them in their document.
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use serde::Serialize;
+
+use crate::{
+    diff::{fragment_id, lcs, tokens, Op},
+    error::PsmlResult,
+    model::{Document, PropertiesFragment, Property, Section, SectionContent},
+    xml::serialise,
+};
+
+/// Namespace of the Diff-X markup PageSeeder uses to compare versions.
+pub const DIFFX_NAMESPACE: &str = "http://www.topologi.com/2005/Diff-X";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Options for merging documents.
+pub struct MergeOptions {
+    /// Whether to include compare markup of our and their version in conflicts.
+    pub compare: bool,
+}
+
+/// The result of merging documents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    /// The merged document, with our version of anything in conflict.
+    pub document: Document,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A change made differently on both sides.
+/// A version is none if that side removed it, or if it was added by both sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// The metadata or attributes of the document.
+    Document,
+    /// The attributes or title of a section.
+    Section { section: String },
+    Fragment {
+        fragment: String,
+        base: Option<SectionContent>,
+        ours: Option<SectionContent>,
+        theirs: Option<SectionContent>,
+        /// Our version compared with theirs, if requested.
+        compare: Option<String>,
+    },
+    /// A fragment moved to a different section by each side.
+    Move {
+        fragment: String,
+        /// The section we moved it to.
+        ours: String,
+        /// The section they moved it to.
+        theirs: String,
+    },
+    Property {
+        fragment: String,
+        name: String,
+        base: Option<Property>,
+        ours: Option<Property>,
+        theirs: Option<Property>,
+        /// Our version compared with theirs, if requested.
+        compare: Option<String>,
+    },
+}
+
+/// The outcome of merging one item.
+enum Resolution<T> {
+    Merged(Option<T>),
+    Conflict,
+}
+
+/// Merges a single item that may have been added, modified or removed on each side.
+fn merge_item<T: Clone + PartialEq>(
+    base: Option<&T>,
+    ours: Option<&T>,
+    theirs: Option<&T>,
+) -> Resolution<T> {
+    if ours == theirs || theirs == base {
+        Resolution::Merged(ours.cloned())
+    } else if ours == base {
+        Resolution::Merged(theirs.cloned())
+    } else {
+        Resolution::Conflict
+    }
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs`.
+pub fn merge(
+    base: &Document,
+    ours: &Document,
+    theirs: &Document,
+    options: &MergeOptions,
+) -> PsmlResult<Merge> {
+    let mut merger = Merger {
+        options,
+        conflicts: vec![],
+    };
+    let document = merger.document(base, ours, theirs)?;
+    Ok(Merge {
+        document,
+        conflicts: merger.conflicts,
+    })
+}
+
+struct Merger<'o> {
+    options: &'o MergeOptions,
+    conflicts: Vec<Conflict>,
+}
+
+impl Merger<'_> {
+    fn document(
+        &mut self,
+        base: &Document,
+        ours: &Document,
+        theirs: &Document,
+    ) -> PsmlResult<Document> {
+        let sections =
+            |doc: &Document| -> Vec<String> { doc.sections.iter().map(|s| s.id.clone()).collect() };
+        let ids = ordered_ids(&sections(base), &sections(ours), &sections(theirs));
+
+        let base_fragments = fragments(base);
+        let our_fragments = fragments(ours);
+        let their_fragments = fragments(theirs);
+        let homes = self.homes(&base_fragments, &our_fragments, &their_fragments);
+
+        let mut merged = Document {
+            sections: vec![],
+            ..self.attributes(base, ours, theirs)
+        };
+        for id in ids {
+            let (base_section, our_section, their_section) = (
+                base.get_section(&id),
+                ours.get_section(&id),
+                theirs.get_section(&id),
+            );
+            let Some((mut section, removed)) =
+                self.section(base_section, our_section, their_section)
+            else {
+                continue;
+            };
+
+            let fragment_ids = |section: Option<&Section>| -> Vec<String> {
+                section
+                    .map(|s| s.content.iter().filter_map(fragment_id).map(str::to_string))
+                    .into_iter()
+                    .flatten()
+                    .collect()
+            };
+            let ids = ordered_ids(
+                &fragment_ids(base_section),
+                &fragment_ids(our_section),
+                &fragment_ids(their_section),
+            );
+            for id in ids {
+                // Fragments moved to another section by either side are merged there.
+                let (base_fragment, our_fragment, their_fragment) = (
+                    base_fragments.get(&id),
+                    our_fragments.get(&id),
+                    their_fragments.get(&id),
+                );
+                if homes.get(&id) != Some(&section.id) {
+                    continue;
+                }
+                let fragment = self.fragment(
+                    &id,
+                    base_fragment.map(|(_, c)| c),
+                    our_fragment.map(|(_, c)| c),
+                    their_fragment.map(|(_, c)| c),
+                )?;
+                section.content.extend(fragment);
+            }
+            // A section removed by one side is kept if the other side added or kept fragments in it.
+            if !removed || section.content.iter().any(|c| fragment_id(c).is_some()) {
+                merged.sections.push(section);
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Returns the section each fragment on either side is merged in.
+    /// A fragment moved by one side is merged in its new section,
+    /// and one moved to different sections by both sides in ours.
+    fn homes(
+        &mut self,
+        base: &HashMap<String, (String, SectionContent)>,
+        ours: &HashMap<String, (String, SectionContent)>,
+        theirs: &HashMap<String, (String, SectionContent)>,
+    ) -> HashMap<String, String> {
+        let mut ids: Vec<&String> = ours.keys().chain(theirs.keys()).collect();
+        ids.sort();
+        ids.dedup();
+
+        let mut homes = HashMap::new();
+        for id in ids {
+            let (base, ours, theirs) = (
+                base.get(id).map(|(section, _)| section),
+                ours.get(id).map(|(section, _)| section),
+                theirs.get(id).map(|(section, _)| section),
+            );
+            let home = match merge_item(base, ours, theirs) {
+                Resolution::Merged(home) => home.or(ours.or(theirs).cloned()),
+                Resolution::Conflict => match (ours, theirs) {
+                    (Some(ours), Some(theirs)) if base.is_some() => {
+                        self.conflicts.push(Conflict::Move {
+                            fragment: id.clone(),
+                            ours: ours.clone(),
+                            theirs: theirs.clone(),
+                        });
+                        Some(ours.clone())
+                    }
+                    // Added to different sections by each side, or removed by one side,
+                    // which is checked for conflicts when merging the fragment.
+                    _ => ours.or(theirs).cloned(),
+                },
+            };
+            if let Some(home) = home {
+                homes.insert(id.clone(), home);
+            }
+        }
+        homes
+    }
+
+    /// Merges everything in the documents except the sections.
+    fn attributes(&mut self, base: &Document, ours: &Document, theirs: &Document) -> Document {
+        let strip = |doc: &Document| Document {
+            sections: vec![],
+            ..doc.clone()
+        };
+        let (base, ours, theirs) = (strip(base), strip(ours), strip(theirs));
+        match merge_item(Some(&base), Some(&ours), Some(&theirs)) {
+            Resolution::Merged(merged) => merged.unwrap_or(ours),
+            Resolution::Conflict => {
+                self.conflicts.push(Conflict::Document);
+                ours
+            }
+        }
+    }
+
+    /// Merges the attributes and titles of a section, without its fragments.
+    /// Returns whether the section was removed by one side, if any side has it.
+    fn section(
+        &mut self,
+        base: Option<&Section>,
+        ours: Option<&Section>,
+        theirs: Option<&Section>,
+    ) -> Option<(Section, bool)> {
+        let strip = |section: &Section| Section {
+            content: section
+                .content
+                .iter()
+                .filter(|content| fragment_id(content).is_none())
+                .cloned()
+                .collect(),
+            ..section.clone()
+        };
+        let (base, ours, theirs) = (base.map(strip), ours.map(strip), theirs.map(strip));
+        match merge_item(base.as_ref(), ours.as_ref(), theirs.as_ref()) {
+            Resolution::Merged(Some(merged)) => Some((merged, false)),
+            Resolution::Merged(None) => ours.or(theirs).map(|section| (section, true)),
+            Resolution::Conflict => {
+                let ours = ours.or(theirs)?;
+                self.conflicts.push(Conflict::Section {
+                    section: ours.id.clone(),
+                });
+                Some((ours, false))
+            }
+        }
+    }
+
+    fn fragment(
+        &mut self,
+        id: &str,
+        base: Option<&SectionContent>,
+        ours: Option<&SectionContent>,
+        theirs: Option<&SectionContent>,
+    ) -> PsmlResult<Option<SectionContent>> {
+        if let (
+            Some(SectionContent::PropertiesFragment(base)),
+            Some(SectionContent::PropertiesFragment(ours)),
+            Some(SectionContent::PropertiesFragment(theirs)),
+        ) = (base, ours, theirs)
+        {
+            if let Some(merged) = self.properties_fragment(base, ours, theirs)? {
+                return Ok(Some(SectionContent::PropertiesFragment(merged)));
+            }
+        }
+
+        match merge_item(base, ours, theirs) {
+            Resolution::Merged(merged) => Ok(merged),
+            Resolution::Conflict => {
+                self.conflicts.push(Conflict::Fragment {
+                    fragment: id.to_string(),
+                    base: base.cloned(),
+                    ours: ours.cloned(),
+                    theirs: theirs.cloned(),
+                    compare: self.compare(ours, theirs)?,
+                });
+                Ok(ours.cloned())
+            }
+        }
+    }
+
+    /// Merges properties fragments property by property,
+    /// or returns none if the fragments themselves conflict.
+    fn properties_fragment(
+        &mut self,
+        base: &PropertiesFragment,
+        ours: &PropertiesFragment,
+        theirs: &PropertiesFragment,
+    ) -> PsmlResult<Option<PropertiesFragment>> {
+        let strip = |fragment: &PropertiesFragment| PropertiesFragment {
+            properties: vec![],
+            ..fragment.clone()
+        };
+        let Resolution::Merged(Some(mut merged)) =
+            merge_item(Some(&strip(base)), Some(&strip(ours)), Some(&strip(theirs)))
+        else {
+            return Ok(None);
+        };
+
+        // Properties are keyed by name and occurrence, as a name may be repeated.
+        let keys = |fragment: &PropertiesFragment| -> Vec<(String, usize)> {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            fragment
+                .properties
+                .iter()
+                .map(|p| {
+                    let count = counts.entry(&p.name).or_default();
+                    *count += 1;
+                    (p.name.clone(), *count - 1)
+                })
+                .collect()
+        };
+        let property = |fragment: &'_ PropertiesFragment, (name, n): &(String, usize)| {
+            fragment
+                .properties
+                .iter()
+                .filter(|p| &p.name == name)
+                .nth(*n)
+                .cloned()
+        };
+        for key in ordered_ids(&keys(base), &keys(ours), &keys(theirs)) {
+            let (base, ours, theirs) = (
+                property(base, &key),
+                property(ours, &key),
+                property(theirs, &key),
+            );
+            match merge_item(base.as_ref(), ours.as_ref(), theirs.as_ref()) {
+                Resolution::Merged(property) => merged.properties.extend(property),
+                Resolution::Conflict => {
+                    let compare = self.compare(ours.as_ref(), theirs.as_ref())?;
+                    merged.properties.extend(ours.clone());
+                    self.conflicts.push(Conflict::Property {
+                        fragment: merged.id.clone(),
+                        name: key.0,
+                        base,
+                        ours,
+                        theirs,
+                        compare,
+                    });
+                }
+            }
+        }
+        Ok(Some(merged))
+    }
+
+    /// Returns compare markup for our and their version, if requested.
+    fn compare<T: Serialize>(
+        &self,
+        ours: Option<&T>,
+        theirs: Option<&T>,
+    ) -> PsmlResult<Option<String>> {
+        if !self.options.compare {
+            return Ok(None);
+        }
+        let psml = |value: Option<&T>| value.map(|value| serialise(value, None, None)).transpose();
+        Ok(Some(compare_markup(
+            &psml(ours)?.unwrap_or_default(),
+            &psml(theirs)?.unwrap_or_default(),
+        )))
+    }
+}
+
+/// Returns the ID of the section and the content of each fragment in a document by ID.
+fn fragments(document: &Document) -> HashMap<String, (String, SectionContent)> {
+    let mut fragments = HashMap::new();
+    for section in &document.sections {
+        for content in &section.content {
+            if let Some(id) = fragment_id(content) {
+                fragments.insert(id.to_string(), (section.id.clone(), content.clone()));
+            }
+        }
+    }
+    fragments
+}
+
+/// Returns the IDs in our order, with IDs only added by them
+/// inserted after the ID preceding them in their order.
+/// IDs removed by either side are kept so the removal can be checked for conflicts.
+fn ordered_ids<T: Clone + Eq + Hash>(base: &[T], ours: &[T], theirs: &[T]) -> Vec<T> {
+    fn insert_missing<T: Clone + Eq + Hash>(ids: Vec<T>, order: &[T]) -> Vec<T> {
+        let positions: HashMap<&T, usize> = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (id, index))
+            .collect();
+        let mut seen: HashSet<&T> = positions.keys().copied().collect();
+        // Missing IDs by the position of the ID they follow, or None for the start.
+        let mut inserted: HashMap<Option<usize>, Vec<T>> = HashMap::new();
+        let mut after = None;
+        for id in order {
+            match positions.get(id) {
+                Some(&position) => after = Some(position),
+                None if seen.insert(id) => inserted.entry(after).or_default().push(id.clone()),
+                None => {}
+            }
+        }
+
+        let mut out = inserted.remove(&None).unwrap_or_default();
+        for (index, id) in ids.iter().enumerate() {
+            out.push(id.clone());
+            out.extend(inserted.remove(&Some(index)).unwrap_or_default());
+        }
+        out
+    }
+
+    let ids = insert_missing(ours.to_vec(), base);
+    insert_missing(ids, theirs)
+}
+
+/// Marks up the differences between two versions of a PSML element
+/// with Diff-X, as PageSeeder does when comparing versions.
+///
+/// Elements are compared by their start tag. An element only in one version is kept whole,
+/// with a `dfx:delete` or `dfx:insert` attribute on it and each element in it, so the result
+/// stays well-formed when an element is renamed or its attributes change.
+/// Text only in `ours` is wrapped in `<dfx:del>` and text only in `theirs` in `<dfx:ins>`.
+pub fn compare_markup(ours: &str, theirs: &str) -> String {
+    let mut writer = CompareWriter::default();
+    writer.compare(&markup_nodes(ours), &markup_nodes(theirs), true);
+    writer.flush();
+    writer.out
+}
+
+/// How a node only in one version is marked up: the element wrapping its text,
+/// and the attribute on its elements.
+type Change = (&'static str, &'static str);
+
+const DELETED: Change = ("dfx:del", "dfx:delete");
+const INSERTED: Change = ("dfx:ins", "dfx:insert");
+
+/// A node of serialised PSML being compared.
+enum MarkupNode<'a> {
+    /// A word, a run of whitespace, or any other token that is not an element.
+    Token(&'a str),
+    /// An element, with an empty end tag if the start tag is empty.
+    Element {
+        start: &'a str,
+        children: Vec<MarkupNode<'a>>,
+        end: &'a str,
+    },
+}
+
+impl MarkupNode<'_> {
+    /// Returns the token, or the start tag of an element.
+    fn key(&self) -> &str {
+        match self {
+            MarkupNode::Token(token) => token,
+            MarkupNode::Element { start, .. } => start,
+        }
+    }
+}
+
+/// Parses serialised PSML into nodes.
+/// End tags without a start tag are kept as tokens, and elements left open are closed.
+fn markup_nodes(xml: &str) -> Vec<MarkupNode<'_>> {
+    let mut open: Vec<(&str, Vec<MarkupNode>)> = vec![];
+    let mut nodes = vec![];
+    for token in tokens(xml) {
+        if token.starts_with("</") {
+            if let Some((start, parent)) = open.pop() {
+                let children = std::mem::replace(&mut nodes, parent);
+                nodes.push(MarkupNode::Element {
+                    start,
+                    children,
+                    end: token,
+                });
+                continue;
+            }
+        } else if token.starts_with('<') && !token.starts_with("<!") && !token.starts_with("<?") {
+            if token.ends_with("/>") {
+                nodes.push(MarkupNode::Element {
+                    start: token,
+                    children: vec![],
+                    end: "",
+                });
+            } else {
+                open.push((token, std::mem::take(&mut nodes)));
+            }
+            continue;
+        }
+        nodes.push(MarkupNode::Token(token));
+    }
+    while let Some((start, parent)) = open.pop() {
+        let children = std::mem::replace(&mut nodes, parent);
+        nodes.push(MarkupNode::Element {
+            start,
+            children,
+            end: "",
+        });
+    }
+    nodes
+}
+
+/// Writes compare markup, wrapping each run of changed text in one element.
+#[derive(Default)]
+struct CompareWriter {
+    out: String,
+    /// Changed text not written yet, and how it changed.
+    text: String,
+    change: Option<Change>,
+}
+
+impl CompareWriter {
+    /// Writes the differences between two lists of sibling nodes.
+    fn compare(&mut self, ours: &[MarkupNode], theirs: &[MarkupNode], root: bool) {
+        let ours_keys: Vec<&str> = ours.iter().map(MarkupNode::key).collect();
+        let theirs_keys: Vec<&str> = theirs.iter().map(MarkupNode::key).collect();
+        for op in lcs(&ours_keys, &theirs_keys) {
+            match op {
+                Op::Equal(i, j) => match (&ours[i], &theirs[j]) {
+                    (
+                        MarkupNode::Element {
+                            start,
+                            children: ours,
+                            end,
+                        },
+                        MarkupNode::Element {
+                            children: theirs, ..
+                        },
+                    ) => {
+                        self.tag(start, None, root);
+                        self.compare(ours, theirs, false);
+                        self.tag(end, None, false);
+                    }
+                    (node, _) => self.node(node, None, root),
+                },
+                Op::Delete(i) => self.node(&ours[i], Some(DELETED), root),
+                Op::Insert(j) => self.node(&theirs[j], Some(INSERTED), root),
+            }
+        }
+    }
+
+    /// Writes a node and its content, marked up as changed if it is.
+    fn node(&mut self, node: &MarkupNode, change: Option<Change>, root: bool) {
+        match node {
+            MarkupNode::Token(token) if token.starts_with('<') => self.tag(token, None, root),
+            MarkupNode::Token(text) => match change {
+                Some(change) => {
+                    if self.change != Some(change) {
+                        self.flush();
+                        self.change = Some(change);
+                    }
+                    self.text.push_str(text);
+                }
+                None => {
+                    self.flush();
+                    self.out.push_str(text);
+                }
+            },
+            MarkupNode::Element {
+                start,
+                children,
+                end,
+            } => {
+                self.tag(start, change, root);
+                for child in children {
+                    self.node(child, change, false);
+                }
+                self.tag(end, None, false);
+            }
+        }
+    }
+
+    /// Writes a tag, with the attribute for the change on start tags
+    /// and the Diff-X namespace on start tags of root elements.
+    fn tag(&mut self, tag: &str, change: Option<Change>, root: bool) {
+        self.flush();
+        let body = match tag.strip_suffix("/>").or(tag.strip_suffix('>')) {
+            Some(body) if !tag.starts_with("</") && tag.starts_with('<') => body,
+            _ => {
+                self.out.push_str(tag);
+                return;
+            }
+        };
+        let name_end = body.find([' ', '\t', '\n', '/']).unwrap_or(body.len());
+        self.out.push_str(&body[..name_end]);
+        if root {
+            self.out
+                .push_str(&format!(" xmlns:dfx=\"{DIFFX_NAMESPACE}\""));
+        }
+        self.out.push_str(&body[name_end..]);
+        if let Some((_, attr)) = change {
+            self.out.push_str(&format!(" {attr}=\"true\""));
+        }
+        self.out.push_str(&tag[body.len()..]);
+    }
+
+    /// Writes the changed text not written yet.
+    fn flush(&mut self) {
+        if let Some((wrapper, _)) = self.change.take() {
+            if !self.text.is_empty() {
+                self.out
+                    .push_str(&format!("<{wrapper}>{}</{wrapper}>", self.text));
+                self.text.clear();
+            }
+        }
+    }
+}
@@ -1,11 +1,20 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{borrow::Cow, collections::HashMap};
 
-use crate::text::CharacterStyle;
+use crate::{
+    error::{PsmlError, PsmlResult},
+    text::CharacterStyle,
+    toc::Toc,
+};
 
-use super::text::{Alignment, Heading, Image, Para};
+use super::text::{
+    Alignment, Anchor, Bold, Heading, Image, Inline, Italic, Link, Monospace, Para, Placeholder,
+    Subscript, Superscript, Underline,
+};
 
 // XRef
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -53,7 +62,7 @@ pub struct XRef {
     #[serde(rename = "@href", skip_serializing_if = "Option::is_none")]
     /// Destination href.#
     pub href: Option<String>,
-    #[serde(rename = "$text")]
+    #[serde(rename = "$text", default)]
     /// Text content to display instead of xref.
     pub content: String,
     #[serde(rename = "@config", skip_serializing_if = "Option::is_none")]
@@ -65,9 +74,13 @@ pub struct XRef {
     #[serde(rename = "@frag")]
     /// ID of fragment to link to.
     pub frag_id: String,
-    #[serde(rename = "@labels", skip_serializing_if = "Option::is_none")]
-    /// Comma separated xref labels.
-    pub labels: Option<String>,
+    #[serde(
+        rename = "@labels",
+        default,
+        skip_serializing_if = "LabelSet::is_empty"
+    )]
+    /// Xref labels.
+    pub labels: LabelSet,
     #[serde(rename = "@level", skip_serializing_if = "Option::is_none")]
     /// Level for heading numbering of target document (1-5).
     pub level: Option<String>,
@@ -89,14 +102,14 @@ impl XRef {
     /// Returns a default xref to the given uriid.
     pub fn uriid(uriid: String) -> XRef {
         XRef {
-            uriid: None,
+            uriid: Some(uriid),
             docid: None,
-            href: Some(uriid),
+            href: None,
             content: String::new(),
             config: None,
             display: XRefDisplayKind::Document,
             frag_id: "default".to_string(),
-            labels: None,
+            labels: LabelSet::new(),
             level: None,
             reverselink: true,
             reversetitle: None,
@@ -115,7 +128,7 @@ impl XRef {
             config: None,
             display: XRefDisplayKind::Document,
             frag_id: "default".to_string(),
-            labels: None,
+            labels: LabelSet::new(),
             level: None,
             reverselink: true,
             reversetitle: None,
@@ -134,7 +147,7 @@ impl XRef {
             config: None,
             display: XRefDisplayKind::Document,
             frag_id: "default".to_string(),
-            labels: None,
+            labels: LabelSet::new(),
             level: None,
             reverselink: true,
             reversetitle: None,
@@ -159,12 +172,64 @@ impl XRef {
     }
 }
 
+/// The destination of an xref, block xref or image, for resolving to a URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XRefTarget<'a> {
+    pub href: Option<&'a str>,
+    pub docid: Option<&'a str>,
+    pub uriid: Option<&'a str>,
+    /// ID of the target fragment, `default` for the whole document.
+    pub frag: &'a str,
+}
+
+/// Resolves the URLs that xrefs are rendered as links to.
+pub trait XRefResolver {
+    /// Returns the URL for the target, or none to render the xref as text.
+    fn resolve(&self, target: &XRefTarget) -> Option<String>;
+}
+
+impl<F: Fn(&XRefTarget) -> Option<String>> XRefResolver for F {
+    fn resolve(&self, target: &XRefTarget) -> Option<String> {
+        self(target)
+    }
+}
+
+/// Resolves targets with an href to that href, with any fragment ID appended as the URL fragment.
+pub fn href_resolver(target: &XRefTarget) -> Option<String> {
+    let href = target.href?;
+    match target.frag {
+        "" | "default" => Some(href.to_string()),
+        frag => Some(format!("{href}#{frag}")),
+    }
+}
+
+impl<'a> From<&'a XRef> for XRefTarget<'a> {
+    fn from(xref: &'a XRef) -> Self {
+        XRefTarget {
+            href: xref.href.as_deref(),
+            docid: xref.docid.as_deref(),
+            uriid: xref.uriid.as_deref(),
+            frag: &xref.frag_id,
+        }
+    }
+}
+
+impl<'a> From<&'a BlockXRef> for XRefTarget<'a> {
+    fn from(xref: &'a BlockXRef) -> Self {
+        XRefTarget {
+            href: xref.href.as_deref(),
+            docid: xref.docid.as_deref(),
+            uriid: xref.uriid.as_deref(),
+            frag: &xref.frag,
+        }
+    }
+}
+
 // Property
 
 /// Property datatype attribute values.
-/// Does not support custom datatypes - they will be converted to "string".
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(from = "String", into = "String")]
 pub enum PropertyDatatype {
     String,
     Date,
@@ -173,6 +238,57 @@ pub enum PropertyDatatype {
     Link,
     Markdown,
     Markup,
+    /// A datatype defined by a document template.
+    Custom(String),
+}
+
+impl PropertyDatatype {
+    /// Returns the value of the datatype attribute.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::String => "string",
+            Self::Date => "date",
+            Self::Datetime => "datetime",
+            Self::XRef => "xref",
+            Self::Link => "link",
+            Self::Markdown => "markdown",
+            Self::Markup => "markup",
+            Self::Custom(datatype) => datatype,
+        }
+    }
+}
+
+impl From<String> for PropertyDatatype {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "string" => Self::String,
+            "date" => Self::Date,
+            "datetime" => Self::Datetime,
+            "xref" => Self::XRef,
+            "link" => Self::Link,
+            "markdown" => Self::Markdown,
+            "markup" => Self::Markup,
+            _ => Self::Custom(value),
+        }
+    }
+}
+
+impl From<PropertyDatatype> for String {
+    fn from(value: PropertyDatatype) -> Self {
+        match value {
+            PropertyDatatype::Custom(datatype) => datatype,
+            other => other.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename = "markup")]
+/// Rich text value of a markup property.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-markup.html
+pub struct Markup {
+    #[serde(rename = "$value", default)]
+    pub content: Vec<CharacterStyle>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -181,7 +297,7 @@ pub enum PropertyValue {
     XRef(Box<XRef>),
     Link(String),
     Markdown(String),
-    Markup(String),
+    Markup(Markup),
     Value(String),
 }
 
@@ -227,6 +343,30 @@ lazy_static! {
     static ref PROPERTY_BAD_NAME: Regex = Regex::new(r"(^-|[^a-zA-Z0-9_-]+)").unwrap();
 }
 
+/// Format of date property values.
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%d";
+/// Format of datetime property values written by this crate.
+pub(crate) const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// Parses a date property value.
+pub(crate) fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value.trim(), DATE_FORMAT)
+        .map_err(|err| format!("{value:?} is not a date: {err}"))
+}
+
+/// Parses a datetime property value.
+/// The date and time may be separated with `T` or a space,
+/// and values without a timezone offset are in UTC.
+pub(crate) fn parse_datetime(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    let normalised = value.trim().replacen(' ', "T", 1);
+    DateTime::parse_from_rfc3339(&normalised)
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&normalised, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|datetime| datetime.and_utc().fixed_offset())
+        })
+        .map_err(|err| format!("{value:?} is not a datetime: {err}"))
+}
+
 impl Property {
     pub fn with_value(name: String, title: String, value: PropertyValue) -> Self {
         let datatype = value.datatype();
@@ -240,10 +380,152 @@ impl Property {
         }
     }
 
+    /// Creates a property with a single value in the value attribute.
+    pub fn with_attr_value(
+        name: String,
+        title: String,
+        datatype: PropertyDatatype,
+        value: String,
+    ) -> Self {
+        Property {
+            name,
+            title: Some(title),
+            values: vec![],
+            datatype: match datatype {
+                PropertyDatatype::String => None,
+                datatype => Some(datatype),
+            },
+            multiple: None,
+            attr_value: Some(value),
+        }
+    }
+
+    /// Creates a property with multiple values.
+    pub fn with_values(
+        name: String,
+        title: String,
+        datatype: PropertyDatatype,
+        values: Vec<PropertyValue>,
+    ) -> Self {
+        Property {
+            name,
+            title: Some(title),
+            values,
+            datatype: Some(datatype),
+            multiple: Some(true),
+            attr_value: None,
+        }
+    }
+
+    pub fn string(name: String, title: String, value: String) -> Self {
+        Self::with_attr_value(name, title, PropertyDatatype::String, value)
+    }
+
+    pub fn date(name: String, title: String, value: NaiveDate) -> Self {
+        let value = value.format(DATE_FORMAT).to_string();
+        Self::with_attr_value(name, title, PropertyDatatype::Date, value)
+    }
+
+    pub fn datetime(name: String, title: String, value: DateTime<FixedOffset>) -> Self {
+        let value = value.format(DATETIME_FORMAT).to_string();
+        Self::with_attr_value(name, title, PropertyDatatype::Datetime, value)
+    }
+
+    pub fn markup(name: String, title: String, content: Vec<CharacterStyle>) -> Self {
+        Self::with_value(name, title, PropertyValue::Markup(Markup { content }))
+    }
+
+    /// Creates a property with a datatype defined by a document template.
+    pub fn custom(name: String, title: String, datatype: String, value: String) -> Self {
+        Self::with_attr_value(name, title, PropertyDatatype::Custom(datatype), value)
+    }
+
     /// Replaces characters in `name` that are illegal for a PSML Property name.
     pub fn sanitize_name<'a>(name: &'a str, repl: &str) -> Cow<'a, str> {
         PROPERTY_BAD_NAME.replace_all(name, repl)
     }
+
+    /// Returns the number of values in this property.
+    pub fn value_count(&self) -> usize {
+        self.attr_value.iter().count() + self.values.len()
+    }
+
+    /// Returns the value attribute and the text of each value element.
+    pub fn text_values(&self) -> Vec<&str> {
+        let mut values: Vec<&str> = self.attr_value.iter().map(String::as_str).collect();
+        values.extend(self.values.iter().filter_map(|value| match value {
+            PropertyValue::Value(text) => Some(text.as_str()),
+            _ => None,
+        }));
+        values
+    }
+
+    fn error(&self, msg: String) -> PsmlError {
+        PsmlError::PropertyError {
+            name: self.name.clone(),
+            msg,
+        }
+    }
+
+    /// Parses the text values of this property as dates in the format `YYYY-MM-DD`.
+    pub fn date_values(&self) -> PsmlResult<Vec<NaiveDate>> {
+        self.text_values()
+            .into_iter()
+            .map(|value| parse_date(value).map_err(|msg| self.error(msg)))
+            .collect()
+    }
+
+    /// Parses the text values of this property as datetimes.
+    /// Values may separate the date and time with `T` or a space,
+    /// and values without a timezone offset are in UTC.
+    pub fn datetime_values(&self) -> PsmlResult<Vec<DateTime<FixedOffset>>> {
+        self.text_values()
+            .into_iter()
+            .map(|value| parse_datetime(value).map_err(|msg| self.error(msg)))
+            .collect()
+    }
+
+    /// Returns the content of each markup value of this property,
+    /// parsing text values as PSML inline markup.
+    /// Ampersands in text values are read as text, as entities were unescaped when parsing.
+    pub fn markup_values(&self) -> PsmlResult<Vec<Vec<CharacterStyle>>> {
+        let parse = |text: &str| {
+            let markup = format!("<markup>{}</markup>", text.replace('&', "&amp;"));
+            crate::xml::deserialize::<Markup>(&markup)
+                .map(|markup| markup.content)
+                .map_err(|err| self.error(format!("{text:?} is not markup: {err}")))
+        };
+        let mut values = vec![];
+        if let Some(text) = &self.attr_value {
+            values.push(parse(text)?);
+        }
+        for value in &self.values {
+            match value {
+                PropertyValue::Markup(markup) => values.push(markup.content.clone()),
+                PropertyValue::Value(text) => values.push(parse(text)?),
+                other => {
+                    return Err(
+                        self.error(format!("{} value is not markup", other.datatype().as_str()))
+                    )
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Checks that a property with multiple values is marked as multiple,
+    /// and that multiple values are not in the value attribute.
+    pub fn check_multiple(&self) -> PsmlResult<()> {
+        match (self.multiple == Some(true), self.value_count()) {
+            (false, count) if count > 1 => {
+                Err(self.error(format!("{count} values but property is not multiple")))
+            }
+            (true, _) if self.attr_value.is_some() => Err(self.error(
+                "multiple property has a value attribute instead of value elements".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 // Fragments
@@ -259,10 +541,10 @@ pub struct PropertiesFragment {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// Template type for the fragment.
     pub frag_type: Option<String>,
-    #[serde(rename = "@labels")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@labels", default)]
+    #[serde(skip_serializing_if = "LabelSet::is_empty")]
     /// Labels on this fragment.
-    pub labels: Option<String>,
+    pub labels: LabelSet,
     #[serde(rename = "property", default)]
     /// Properties in this fragment.
     pub properties: Vec<Property>,
@@ -277,7 +559,7 @@ impl PropertiesFragment {
         PropertiesFragment {
             id,
             frag_type: None,
-            labels: None,
+            labels: LabelSet::new(),
             properties: vec![],
             attrs: HashMap::new(),
         }
@@ -307,7 +589,7 @@ pub struct BlockXRef {
     #[serde(rename = "@uriid", skip_serializing_if = "Option::is_none")]
     pub uriid: Option<String>,
 
-    #[serde(rename = "@archived")]
+    #[serde(rename = "@archived", skip_serializing_if = "Option::is_none")]
     pub archived: Option<bool>,
     #[serde(rename = "@config", skip_serializing_if = "Option::is_none")]
     pub config: Option<String>,
@@ -321,8 +603,12 @@ pub struct BlockXRef {
     pub frag: String,
     #[serde(rename = "@id", skip_serializing_if = "Option::is_none")]
     pub id: Option<String>,
-    #[serde(rename = "@labels", skip_serializing_if = "Option::is_none")]
-    pub labels: Option<String>,
+    #[serde(
+        rename = "@labels",
+        default,
+        skip_serializing_if = "LabelSet::is_empty"
+    )]
+    pub labels: LabelSet,
     #[serde(rename = "@level", skip_serializing_if = "Option::is_none")]
     pub level: Option<u8>,
     #[serde(rename = "@mediatype", skip_serializing_if = "Option::is_none")]
@@ -341,8 +627,12 @@ pub struct BlockXRef {
     pub unresolved: Option<bool>,
     #[serde(rename = "@urititle", skip_serializing_if = "Option::is_none")]
     pub urititle: Option<String>,
-    #[serde(rename = "@urilabels", skip_serializing_if = "Option::is_none")]
-    pub urilabels: Option<String>,
+    #[serde(
+        rename = "@urilabels",
+        default,
+        skip_serializing_if = "LabelSet::is_empty"
+    )]
+    pub urilabels: LabelSet,
 }
 
 impl BlockXRef {
@@ -376,11 +666,13 @@ pub struct XRefFragment {
     /// ID of the fragment.
     pub id: String,
     #[serde(rename = "@type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// Template type for the fragment.
     pub frag_type: Option<String>,
-    #[serde(rename = "@labels")]
+    #[serde(rename = "@labels", default)]
+    #[serde(skip_serializing_if = "LabelSet::is_empty")]
     /// Labels on this fragment.
-    pub labels: String,
+    pub labels: LabelSet,
     #[serde(rename = "blockxref", default)]
     pub xrefs: Vec<BlockXRef>,
     #[serde(flatten)]
@@ -394,7 +686,7 @@ impl XRefFragment {
         XRefFragment {
             id,
             frag_type: None,
-            labels: String::new(),
+            labels: LabelSet::new(),
             xrefs: Vec::new(),
             attrs: HashMap::new(),
         }
@@ -415,10 +707,152 @@ impl XRefFragment {
     }
 }
 
+/// How the content of a media fragment is written in PSML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaEncoding {
+    /// Escaped text.
+    #[default]
+    Text,
+    /// A CDATA section.
+    CData,
+    /// XML markup, such as inline SVG. The content must be well-formed XML.
+    Markup,
+}
+
+impl MediaEncoding {
+    fn is_text(&self) -> bool {
+        *self == MediaEncoding::Text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "MediaFragmentXml", into = "MediaFragmentXml")]
+/// A PSML media fragment.
+/// The content is kept as text along with how it was written, so CDATA sections and markup
+/// are written back unchanged.
+///
+/// Serde cannot write CDATA sections or markup, so it writes the content as text and any other
+/// encoding in a `psml-encoding` attribute, which is not part of PSML. [`crate::to_string`]
+/// writes the content in its encoding without the attribute, and [`crate::from_str`] reads it
+/// back, so only those should be used for PSML files. Reading markup content with serde
+/// directly is an error.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-media-fragment.html
+pub struct MediaFragment {
+    /// ID of the fragment.
+    pub id: String,
+    /// Template type for the fragment.
+    pub frag_type: Option<String>,
+    /// Labels on this fragment.
+    pub labels: LabelSet,
+    /// Media type of the content, e.g. "image/svg+xml".
+    pub mediatype: String,
+    /// Raw content of the fragment.
+    pub content: String,
+    /// How the content is written.
+    pub encoding: MediaEncoding,
+}
+
+/// A media fragment as serde sees it, with the content as text.
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "media-fragment")]
+struct MediaFragmentXml {
+    #[serde(rename = "@id")]
+    id: String,
+    #[serde(rename = "@type", skip_serializing_if = "Option::is_none")]
+    frag_type: Option<String>,
+    #[serde(
+        rename = "@labels",
+        default,
+        skip_serializing_if = "LabelSet::is_empty"
+    )]
+    labels: LabelSet,
+    #[serde(rename = "@mediatype")]
+    mediatype: String,
+    #[serde(
+        rename = "@psml-encoding",
+        default,
+        skip_serializing_if = "MediaEncoding::is_text"
+    )]
+    encoding: MediaEncoding,
+    /// Read as a value rather than text so that unexpected markup is an error, not dropped.
+    #[serde(rename = "$value", default)]
+    content: String,
+}
+
+impl From<MediaFragmentXml> for MediaFragment {
+    fn from(fragment: MediaFragmentXml) -> Self {
+        MediaFragment {
+            id: fragment.id,
+            frag_type: fragment.frag_type,
+            labels: fragment.labels,
+            mediatype: fragment.mediatype,
+            content: fragment.content,
+            encoding: fragment.encoding,
+        }
+    }
+}
+
+impl From<MediaFragment> for MediaFragmentXml {
+    fn from(fragment: MediaFragment) -> Self {
+        MediaFragmentXml {
+            id: fragment.id,
+            frag_type: fragment.frag_type,
+            labels: fragment.labels,
+            mediatype: fragment.mediatype,
+            encoding: fragment.encoding,
+            content: fragment.content,
+        }
+    }
+}
+
+impl MediaFragment {
+    /// Creates a new empty fragment with the given id and media type.
+    pub fn new(id: String, mediatype: String) -> MediaFragment {
+        MediaFragment {
+            id,
+            frag_type: None,
+            labels: LabelSet::new(),
+            mediatype,
+            content: String::new(),
+            encoding: MediaEncoding::Text,
+        }
+    }
+
+    /// Sets the content of the fragment and returns it.
+    pub fn with_content(self, content: String) -> MediaFragment {
+        MediaFragment { content, ..self }
+    }
+
+    /// Sets how the content is written and returns the fragment.
+    pub fn with_encoding(self, encoding: MediaEncoding) -> MediaFragment {
+        MediaFragment { encoding, ..self }
+    }
+
+    /// Sets the content of the fragment to the base64 encoding of `data` and returns it.
+    pub fn with_base64(self, data: &[u8]) -> MediaFragment {
+        MediaFragment {
+            content: BASE64_STANDARD.encode(data),
+            ..self
+        }
+    }
+
+    /// Decodes the content of the fragment as base64.
+    /// Whitespace in the content is ignored.
+    pub fn decode_base64(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        let content: String = self
+            .content
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        BASE64_STANDARD.decode(content)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct TableCaption {
     #[serde(rename = "$text", default)]
-    caption: String,
+    pub caption: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -451,12 +885,71 @@ pub struct TableRow {
     pub part: Option<TablePart>,
     #[serde(rename = "@role", skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
-    #[serde(rename = "cell", default)]
-    pub cells: Vec<TableCell>,
+    #[serde(rename = "$value", default)]
+    pub cells: Vec<TableRowContent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TableRowContent {
+    #[serde(rename = "cell")]
+    Cell(TableCell),
+    #[serde(rename = "hcell")]
+    /// A header cell.
+    HeaderCell(TableCell),
+}
+
+impl TableRowContent {
+    /// Returns the wrapped cell regardless of whether it is a header cell.
+    pub fn cell(&self) -> &TableCell {
+        match self {
+            Self::Cell(cell) | Self::HeaderCell(cell) => cell,
+        }
+    }
+
+    /// Returns the wrapped cell mutably regardless of whether it is a header cell.
+    pub fn cell_mut(&mut self) -> &mut TableCell {
+        match self {
+            Self::Cell(cell) | Self::HeaderCell(cell) => cell,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// Content of table cells and list items, which may mix text, inline elements and blocks.
+pub enum MixedContent {
+    #[serde(rename = "$text")]
+    Text(String),
+    Bold(Bold),
+    Italic(Italic),
+    Underline(Underline),
+    Subscript(Subscript),
+    Superscript(Superscript),
+    Monospace(Monospace),
+    XRef(Box<XRef>),
+    Link(Link),
+    Inline(Inline),
+    Anchor(Anchor),
+    Placeholder(Placeholder),
+    Br,
+    Image(Image),
+    Para(Para),
+    Block {
+        #[serde(rename = "@label", skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(rename = "$value", default)]
+        child: Vec<FragmentContent>,
+    },
+    Preformat {
+        #[serde(rename = "$value", default)]
+        child: Vec<CharacterStyle>,
+    },
+    List(List),
+    #[serde(rename = "nlist")]
+    NumberedList(NumberedList),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
-#[serde(rename = "cell")]
 pub struct TableCell {
     #[serde(rename = "@align", skip_serializing_if = "Option::is_none")]
     pub align: Option<Alignment>,
@@ -467,12 +960,25 @@ pub struct TableCell {
     #[serde(rename = "@rowspan", skip_serializing_if = "Option::is_none")]
     pub rowspan: Option<u64>,
     #[serde(rename = "$value", default)]
-    pub content: Vec<CharacterStyle>,
-    // TODO extend content to include images etc.
+    pub content: Vec<MixedContent>,
+}
+
+impl TableCell {
+    pub fn new(content: Vec<MixedContent>) -> Self {
+        Self {
+            content,
+            ..Default::default()
+        }
+    }
+
+    pub fn text(text: String) -> Self {
+        Self::new(vec![MixedContent::Text(text)])
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Table {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub caption: Option<TableCaption>,
     #[serde(rename = "@role", skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
@@ -490,6 +996,18 @@ pub struct Table {
 
 impl Table {
     pub fn basic(cols: usize, cells: Vec<Vec<String>>, caption: String) -> Self {
+        Self::rich(
+            cols,
+            cells
+                .into_iter()
+                .map(|row| row.into_iter().map(TableCell::text).collect())
+                .collect(),
+            caption,
+        )
+    }
+
+    /// Creates a table from rows of cells with arbitrary content.
+    pub fn rich(cols: usize, cells: Vec<Vec<TableCell>>, caption: String) -> Self {
         Table {
             caption: Some(TableCaption { caption }),
             role: None,
@@ -500,13 +1018,7 @@ impl Table {
             rows: cells
                 .into_iter()
                 .map(|row| TableRow {
-                    cells: row
-                        .into_iter()
-                        .map(|cell| TableCell {
-                            content: vec![CharacterStyle::Text(cell)],
-                            ..Default::default()
-                        })
-                        .collect(),
+                    cells: row.into_iter().map(TableRowContent::Cell).collect(),
                     ..Default::default()
                 })
                 .collect(),
@@ -514,6 +1026,104 @@ impl Table {
     }
 }
 
+// List
+
+/// Bullet style for an unordered list.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListKind {
+    None,
+    Disc,
+    Circle,
+    Square,
+}
+
+/// Numbering style for a numbered list.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberedListKind {
+    Arabic,
+    UpperAlpha,
+    LowerAlpha,
+    UpperRoman,
+    LowerRoman,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename = "item")]
+/// An item in a list or numbered list.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-item.html
+pub struct ListItem {
+    #[serde(rename = "$value", default)]
+    /// Contents of the item.
+    pub content: Vec<MixedContent>,
+}
+
+impl ListItem {
+    pub fn new(content: Vec<MixedContent>) -> Self {
+        Self { content }
+    }
+
+    pub fn text(text: String) -> Self {
+        Self {
+            content: vec![MixedContent::Text(text)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename = "list")]
+/// A PSML unordered list.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-list.html
+pub struct List {
+    #[serde(rename = "@role", skip_serializing_if = "Option::is_none")]
+    /// Custom role for the list.
+    pub role: Option<String>,
+    #[serde(rename = "@type", skip_serializing_if = "Option::is_none")]
+    /// Bullet style of the list.
+    pub list_type: Option<ListKind>,
+    #[serde(rename = "item", default)]
+    /// Items in the list.
+    pub items: Vec<ListItem>,
+}
+
+impl List {
+    pub fn new(items: Vec<ListItem>) -> Self {
+        Self {
+            items,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename = "nlist")]
+/// A PSML numbered list.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-nlist.html
+pub struct NumberedList {
+    #[serde(rename = "@role", skip_serializing_if = "Option::is_none")]
+    /// Custom role for the list.
+    pub role: Option<String>,
+    #[serde(rename = "@start", skip_serializing_if = "Option::is_none")]
+    /// Number of the first item in the list.
+    pub start: Option<u64>,
+    #[serde(rename = "@type", skip_serializing_if = "Option::is_none")]
+    /// Numbering style of the list.
+    pub list_type: Option<NumberedListKind>,
+    #[serde(rename = "item", default)]
+    /// Items in the list.
+    pub items: Vec<ListItem>,
+}
+
+impl NumberedList {
+    pub fn new(items: Vec<ListItem>) -> Self {
+        Self {
+            items,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum FragmentContent {
@@ -521,6 +1131,8 @@ pub enum FragmentContent {
     Text(String),
     Heading(Heading),
     Block {
+        #[serde(rename = "@label", skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
         #[serde(rename = "$value", default)]
         child: Vec<FragmentContent>,
     },
@@ -532,6 +1144,9 @@ pub enum FragmentContent {
     },
     Image(Image),
     Table(Table),
+    List(List),
+    #[serde(rename = "nlist")]
+    NumberedList(NumberedList),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -545,10 +1160,10 @@ pub struct Fragment {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// Template type for the fragment.
     pub frag_type: Option<String>,
-    #[serde(rename = "@labels")]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "@labels", default)]
+    #[serde(skip_serializing_if = "LabelSet::is_empty")]
     /// Labels on this fragment.
-    pub labels: Option<String>,
+    pub labels: LabelSet,
     #[serde(rename = "$value", default)]
     /// Contents of the fragment.
     pub content: Vec<FragmentContent>,
@@ -558,6 +1173,7 @@ pub struct Fragment {
 
     // TODO ^ wait till $value and flatten can be used together ^
     // https://github.com/tafia/quick-xml/issues/326
+    // Until then other attributes are only kept when reading with `crate::lossless`.
 }
 
 impl Fragment {
@@ -566,7 +1182,7 @@ impl Fragment {
         Fragment {
             id,
             frag_type: None,
-            labels: None,
+            labels: LabelSet::new(),
             content: vec![],
             // attrs: HashMap::new(),
         }
@@ -593,7 +1209,7 @@ pub enum Fragments {
     #[serde(rename = "xref-fragment")]
     Xref(XRefFragment),
     #[serde(rename = "media-fragment")]
-    Media(()),
+    Media(MediaFragment),
 }
 
 // Section
@@ -607,7 +1223,7 @@ pub enum SectionContent {
     #[serde(rename = "xref-fragment")]
     XRefFragment(XRefFragment),
     #[serde(rename = "media-fragment")]
-    Media(()),
+    Media(MediaFragment),
     #[serde(rename = "title")]
     Title {
         #[serde(rename = "$text", default)]
@@ -711,6 +1327,9 @@ pub struct Description {
 /// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-uri.html
 pub struct URIDescriptor {
     // Attributes
+    #[serde(rename = "@id", skip_serializing_if = "Option::is_none")]
+    /// URI ID of this document.
+    pub id: Option<String>,
     #[serde(rename = "@docid", skip_serializing_if = "Option::is_none")]
     /// Docid of this document.
     pub docid: Option<String>,
@@ -730,7 +1349,7 @@ pub struct URIDescriptor {
     pub description: Option<Description>,
     #[serde(skip_serializing_if = "Option::is_none")]
     /// Labels on the document.
-    pub labels: Option<Labels>,
+    pub labels: Option<LabelSet>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
@@ -745,13 +1364,138 @@ pub struct DocumentInfo {
     pub publication: Option<Publication>,
 }
 
-// TODO change this to vec of strings with custom deserializer.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
-/// A comma-separated list of label values for a document, note or fragment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize, Default)]
+#[serde(from = "String", into = "String")]
+/// A set of labels on a document, note, fragment, xref or image, in the order they were added.
+/// Written in PSML as a comma-separated list.
 /// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-labels.html
-pub struct Labels {
-    #[serde(rename = "$value", default)]
-    pub value: String,
+pub struct LabelSet {
+    labels: Vec<String>,
+}
+
+lazy_static! {
+    /// Matches a valid label.
+    static ref LABEL: Regex = Regex::new(r"^[a-zA-Z0-9_][a-zA-Z0-9_-]*$").unwrap();
+}
+
+impl LabelSet {
+    /// Creates an empty label set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a comma-separated list of labels, ignoring whitespace and empty labels.
+    /// Returns an error if any label is invalid.
+    pub fn parse(labels: &str) -> PsmlResult<Self> {
+        let set = Self::from(labels.to_string());
+        let invalid = set.invalid().next().map(str::to_string);
+        match invalid {
+            Some(label) => Err(PsmlError::LabelError { label }),
+            None => Ok(set),
+        }
+    }
+
+    /// Returns true if the label is made of ASCII letters, digits, '_' and '-',
+    /// and does not start with '-'.
+    pub fn is_valid(label: &str) -> bool {
+        LABEL.is_match(label)
+    }
+
+    /// Returns the labels that are not valid, which are kept when reading PSML.
+    pub fn invalid(&self) -> impl Iterator<Item = &str> {
+        self.iter().filter(|label| !Self::is_valid(label))
+    }
+
+    /// Adds a label, returning false if it was already in the set.
+    /// Returns an error if the label is invalid.
+    pub fn add(&mut self, label: &str) -> PsmlResult<bool> {
+        if !Self::is_valid(label) {
+            return Err(PsmlError::LabelError {
+                label: label.to_string(),
+            });
+        }
+        if self.contains(label) {
+            return Ok(false);
+        }
+        self.labels.push(label.to_string());
+        Ok(true)
+    }
+
+    /// Removes a label, returning false if it was not in the set.
+    pub fn remove(&mut self, label: &str) -> bool {
+        let len = self.labels.len();
+        self.labels.retain(|other| other != label);
+        self.labels.len() != len
+    }
+
+    pub fn contains(&self, label: &str) -> bool {
+        self.labels.iter().any(|other| other == label)
+    }
+
+    /// Returns the labels in this set followed by the labels only in `other`.
+    pub fn union(&self, other: &LabelSet) -> LabelSet {
+        let mut union = self.clone();
+        for label in other.iter() {
+            if !union.contains(label) {
+                union.labels.push(label.to_string());
+            }
+        }
+        union
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.labels.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+impl std::str::FromStr for LabelSet {
+    type Err = PsmlError;
+
+    fn from_str(labels: &str) -> PsmlResult<Self> {
+        Self::parse(labels)
+    }
+}
+
+impl std::fmt::Display for LabelSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.labels.join(","))
+    }
+}
+
+/// Reads labels without validating them, so that documents with invalid labels can be read.
+impl From<String> for LabelSet {
+    fn from(value: String) -> Self {
+        let mut set = LabelSet::new();
+        for label in value.split(',').map(str::trim) {
+            if !label.is_empty() && !set.contains(label) {
+                set.labels.push(label.to_string());
+            }
+        }
+        set
+    }
+}
+
+impl From<LabelSet> for String {
+    fn from(value: LabelSet) -> Self {
+        value.to_string()
+    }
+}
+
+impl<'a> IntoIterator for &'a LabelSet {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.labels.iter()
+    }
 }
 
 /// Previous document content if different from current (used when doing a compare).
@@ -775,7 +1519,7 @@ pub struct Note {
     /// Date and time this note was modified.
     pub modified: String,
     /// Labels on this note.
-    pub labels: Labels,
+    pub labels: LabelSet,
     /// Content in this note.
     pub content: Content,
 }
@@ -791,9 +1535,10 @@ pub struct Notes {
 /// Metadata relating to a fragment.
 /// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-locator.html
 pub struct Locator {
-    #[serde(rename = "fragment")]
+    #[serde(rename = "fragment", skip_serializing_if = "Option::is_none")]
     /// ID of the fragment.
     pub fragment_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// Notes on this fragment.
     pub notes: Option<Notes>,
 }
@@ -815,6 +1560,16 @@ pub enum DocumentLevel {
 #[serde(rename = "document")]
 /// A PSML document.
 /// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-document.html
+///
+/// # Serialisation
+///
+/// [`crate::to_string`] and [`crate::to_writer`] are the only supported writers of PSML.
+/// The serde implementations are kept for reading and for round trips through serde, but
+/// writing a document with media fragments using a serde serializer directly, such as
+/// `quick_xml::se::to_string`, does **not** produce valid PSML: the content of any media
+/// fragment written as CDATA or markup is escaped, and its encoding added in a
+/// `psml-encoding` attribute that PageSeeder does not accept.
+/// See [`MediaFragment`] for details.
 pub struct Document {
     #[serde(rename = "documentinfo")]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -823,9 +1578,12 @@ pub struct Document {
     #[serde(rename = "fragmentinfo", default)]
     /// Fragment metadata
     pub frag_info: Vec<Locator>,
-    #[serde(rename = "section")]
+    #[serde(rename = "section", default)]
     /// Sections in the document.
     pub sections: Vec<Section>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Table of contents, in processed documents.
+    pub toc: Option<Toc>,
     #[serde(rename = "@type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub doc_type: Option<String>,
@@ -839,8 +1597,6 @@ pub struct Document {
     pub lockstructure: Option<bool>,
 }
 
-// TODO impl toc index
-
 impl Document {
     pub fn docid(&self) -> Option<&str> {
         match &self.doc_info {
@@ -855,6 +1611,11 @@ impl Document {
         }
     }
 
+    /// Returns the URI ID of this document.
+    pub fn uriid(&self) -> Option<&str> {
+        self.doc_info.as_ref()?.uri.as_ref()?.id.as_deref()
+    }
+
     pub fn get_section(&self, id: &str) -> Option<&Section> {
         self.sections.iter().find(|&section| section.id == id)
     }
@@ -870,6 +1631,7 @@ impl Default for Document {
             doc_info: None,
             frag_info: vec![],
             sections: vec![],
+            toc: None,
             doc_type: None,
             edit: None,
             level: DocumentLevel::Portable,
@@ -0,0 +1,289 @@
+//! Conversion between Rust types and properties fragments.
+//!
+//! Implement [`PsmlProperties`] with `#[derive(PsmlProperties)]` when the `derive` feature is enabled.
+//! Each field is a property named after the field, with a value of any [`PropertyType`].
+//! An `Option` field is omitted when none, and a `Vec` field is a property repeated for each value.
+//!
+//! Fields accept these attributes:
+//! - `#[psml(name = "...")]` sets the property name.
+//! - `#[psml(title = "...")]` sets the property title.
+//! - `#[psml(datatype = "...")]` overrides the datatype, e.g. for a template defined datatype.
+//! - `#[psml(multiple)]` makes a `Vec` field one property with multiple values.
+//! - `#[psml(xref = "docid" | "uriid" | "href")]` makes a `String` field an xref to that target.
+//! - `#[psml(skip)]` ignores the field, which is set to its default when reading.
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
+
+use crate::{
+    error::{PsmlError, PsmlResult},
+    model::{
+        parse_date, parse_datetime, Markup, PropertiesFragment, Property, PropertyDatatype,
+        PropertyValue, XRef, DATETIME_FORMAT, DATE_FORMAT,
+    },
+};
+
+/// A type that converts to and from a properties fragment.
+pub trait PsmlProperties: Sized {
+    /// Converts this value to a properties fragment with the given id.
+    fn to_properties_fragment(&self, id: String) -> PropertiesFragment;
+
+    /// Reads a value from the properties in a fragment.
+    fn from_properties_fragment(fragment: &PropertiesFragment) -> PsmlResult<Self>;
+}
+
+/// A type that can be the value of a property.
+pub trait PropertyType: Sized {
+    /// Datatype of properties with values of this type.
+    fn datatype() -> PropertyDatatype;
+
+    fn to_value(&self) -> PropertyValue;
+
+    /// Reads a value, or returns a message describing why it is invalid.
+    fn from_value(value: &PropertyValue) -> Result<Self, String>;
+}
+
+/// Returns the text of a value, or an error if it is not text.
+fn text(value: &PropertyValue) -> Result<&str, String> {
+    match value {
+        PropertyValue::Value(text) => Ok(text),
+        other => Err(format!(
+            "expected text, found {}",
+            other.datatype().as_str()
+        )),
+    }
+}
+
+macro_rules! impl_property_type_parse {
+    ($($ty:ty),*) => {
+        $(
+            impl PropertyType for $ty {
+                fn datatype() -> PropertyDatatype {
+                    PropertyDatatype::String
+                }
+
+                fn to_value(&self) -> PropertyValue {
+                    PropertyValue::Value(self.to_string())
+                }
+
+                fn from_value(value: &PropertyValue) -> Result<Self, String> {
+                    let text = text(value)?;
+                    text.trim()
+                        .parse()
+                        .map_err(|err| format!("{text:?} is not a {}: {err}", stringify!($ty)))
+                }
+            }
+        )*
+    };
+}
+
+impl_property_type_parse!(bool, i8, i16, i32, i64, u8, u16, u32, u64, usize, f32, f64);
+
+impl PropertyType for String {
+    fn datatype() -> PropertyDatatype {
+        PropertyDatatype::String
+    }
+
+    fn to_value(&self) -> PropertyValue {
+        PropertyValue::Value(self.clone())
+    }
+
+    fn from_value(value: &PropertyValue) -> Result<Self, String> {
+        text(value).map(str::to_string)
+    }
+}
+
+impl PropertyType for NaiveDate {
+    fn datatype() -> PropertyDatatype {
+        PropertyDatatype::Date
+    }
+
+    fn to_value(&self) -> PropertyValue {
+        PropertyValue::Value(self.format(DATE_FORMAT).to_string())
+    }
+
+    fn from_value(value: &PropertyValue) -> Result<Self, String> {
+        parse_date(text(value)?)
+    }
+}
+
+impl PropertyType for DateTime<FixedOffset> {
+    fn datatype() -> PropertyDatatype {
+        PropertyDatatype::Datetime
+    }
+
+    fn to_value(&self) -> PropertyValue {
+        PropertyValue::Value(self.format(DATETIME_FORMAT).to_string())
+    }
+
+    fn from_value(value: &PropertyValue) -> Result<Self, String> {
+        parse_datetime(text(value)?)
+    }
+}
+
+impl PropertyType for XRef {
+    fn datatype() -> PropertyDatatype {
+        PropertyDatatype::XRef
+    }
+
+    fn to_value(&self) -> PropertyValue {
+        PropertyValue::XRef(Box::new(self.clone()))
+    }
+
+    fn from_value(value: &PropertyValue) -> Result<Self, String> {
+        match value {
+            PropertyValue::XRef(xref) => Ok(*xref.clone()),
+            other => Err(format!(
+                "expected xref, found {}",
+                other.datatype().as_str()
+            )),
+        }
+    }
+}
+
+impl PropertyType for Markup {
+    fn datatype() -> PropertyDatatype {
+        PropertyDatatype::Markup
+    }
+
+    fn to_value(&self) -> PropertyValue {
+        PropertyValue::Markup(self.clone())
+    }
+
+    fn from_value(value: &PropertyValue) -> Result<Self, String> {
+        match value {
+            PropertyValue::Markup(markup) => Ok(markup.clone()),
+            other => Err(format!(
+                "expected markup, found {}",
+                other.datatype().as_str()
+            )),
+        }
+    }
+}
+
+/// The attribute of an xref that identifies its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XRefKey {
+    Docid,
+    URIID,
+    Href,
+}
+
+/// Returns an xref value to the target.
+pub fn xref_value(key: XRefKey, target: &str) -> PropertyValue {
+    let target = target.to_string();
+    PropertyValue::XRef(Box::new(match key {
+        XRefKey::Docid => XRef::docid(target),
+        XRefKey::URIID => XRef::uriid(target),
+        XRefKey::Href => XRef::href(target),
+    }))
+}
+
+/// Reads the target of an xref value.
+pub fn xref_target(key: XRefKey, value: &PropertyValue) -> Result<String, String> {
+    let xref = XRef::from_value(value)?;
+    match key {
+        XRefKey::Docid => xref.docid,
+        XRefKey::URIID => xref.uriid,
+        XRefKey::Href => xref.href,
+    }
+    .ok_or_else(|| format!("xref has no {key:?} target"))
+}
+
+/// How the values of a field are written as properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cardinality {
+    /// One property for each value.
+    Repeated,
+    /// One property with multiple values.
+    Multiple,
+}
+
+/// Adds properties with the values of a field.
+/// A single text value is written in the value attribute.
+pub fn push_properties(
+    properties: &mut Vec<Property>,
+    name: &str,
+    title: Option<&str>,
+    datatype: PropertyDatatype,
+    values: Vec<PropertyValue>,
+    cardinality: Cardinality,
+) {
+    let property = |values: Vec<PropertyValue>, multiple: Option<bool>| {
+        let mut property = Property {
+            name: name.to_string(),
+            title: title.map(str::to_string),
+            datatype: match datatype {
+                PropertyDatatype::String => None,
+                ref datatype => Some(datatype.clone()),
+            },
+            multiple,
+            attr_value: None,
+            values,
+        };
+        if multiple.is_none() {
+            if let [PropertyValue::Value(text)] = &property.values[..] {
+                property.attr_value = Some(text.clone());
+                property.values.clear();
+            }
+        }
+        property
+    };
+
+    match cardinality {
+        Cardinality::Multiple => properties.push(property(values, Some(true))),
+        Cardinality::Repeated => {
+            properties.extend(values.into_iter().map(|value| property(vec![value], None)))
+        }
+    }
+}
+
+/// Returns the values of all properties with the name, in order.
+pub fn property_values(fragment: &PropertiesFragment, name: &str) -> Vec<PropertyValue> {
+    let mut values = vec![];
+    for property in fragment.properties.iter().filter(|p| p.name == name) {
+        values.extend(property.attr_value.clone().map(PropertyValue::Value));
+        values.extend(property.values.iter().cloned());
+    }
+    values
+}
+
+fn error(name: &str, msg: String) -> PsmlError {
+    PsmlError::PropertyError {
+        name: name.to_string(),
+        msg,
+    }
+}
+
+/// Reads all values of a property.
+pub fn read_all<T>(
+    name: &str,
+    values: Vec<PropertyValue>,
+    read: impl Fn(&PropertyValue) -> Result<T, String>,
+) -> PsmlResult<Vec<T>> {
+    values
+        .iter()
+        .map(|value| read(value).map_err(|msg| error(name, msg)))
+        .collect()
+}
+
+/// Reads the value of a property that may be missing.
+pub fn read_optional<T>(
+    name: &str,
+    values: Vec<PropertyValue>,
+    read: impl Fn(&PropertyValue) -> Result<T, String>,
+) -> PsmlResult<Option<T>> {
+    let mut values = read_all(name, values, read)?;
+    match values.len() {
+        0 | 1 => Ok(values.pop()),
+        count => Err(error(name, format!("expected one value, found {count}"))),
+    }
+}
+
+/// Reads the value of a required property.
+pub fn read_one<T>(
+    name: &str,
+    values: Vec<PropertyValue>,
+    read: impl Fn(&PropertyValue) -> Result<T, String>,
+) -> PsmlResult<T> {
+    read_optional(name, values, read)?.ok_or_else(|| error(name, "missing".to_string()))
+}
@@ -0,0 +1,893 @@
+//! Rendering of PSML documents as HTML5.
+//!
+//! Each method of [`HtmlTemplate`] defaults to calling the matching `write_*` function, which
+//! writes the default HTML for the node and renders its children through the template.
+//! Override a method to change the HTML for a node, and call the `write_*` function from it
+//! to fall back to the default output.
+//!
+//! URLs are only written in `href` and `src` attributes if they are relative or use a scheme
+//! in [`SAFE_SCHEMES`], so documents from untrusted sources cannot add `javascript:` links.
+//! Templates writing their own links must check URLs with [`is_safe_url`].
+
+use crate::{
+    model::{
+        href_resolver, BlockXRef, Document, Fragment, FragmentContent, LabelSet, List, ListItem,
+        ListKind, MediaFragment, MixedContent, NumberedList, NumberedListKind, PropertiesFragment,
+        Property, PropertyValue, Section, SectionContent, Table, TablePart, TableRow,
+        TableRowContent, XRef, XRefFragment, XRefResolver, XRefTarget,
+    },
+    text::{
+        Alignment, Anchor, Bold, CharacterStyle, Heading, Image, Inline, Italic, Link, Monospace,
+        Para, ParaContent, Placeholder, Subscript, Superscript, Underline,
+    },
+};
+
+pub trait HtmlTemplate {
+    /// Returns the resolver for the URLs of xrefs, block xrefs and images.
+    /// Targets it resolves to none are rendered without a link.
+    fn resolver(&self) -> &dyn XRefResolver {
+        &fragment_resolver
+    }
+
+    fn render_document(&mut self, out: &mut String, node: &Document) {
+        write_document(self, out, node)
+    }
+
+    fn render_section(&mut self, out: &mut String, node: &Section) {
+        write_section(self, out, node)
+    }
+
+    fn render_section_content(&mut self, out: &mut String, node: &SectionContent) {
+        write_section_content(self, out, node)
+    }
+
+    fn render_fragment(&mut self, out: &mut String, node: &Fragment) {
+        write_fragment(self, out, node)
+    }
+
+    fn render_properties_fragment(&mut self, out: &mut String, node: &PropertiesFragment) {
+        write_properties_fragment(self, out, node)
+    }
+
+    fn render_property(&mut self, out: &mut String, node: &Property) {
+        write_property(self, out, node)
+    }
+
+    fn render_xref_fragment(&mut self, out: &mut String, node: &XRefFragment) {
+        write_xref_fragment(self, out, node)
+    }
+
+    fn render_block_xref(&mut self, out: &mut String, node: &BlockXRef) {
+        write_block_xref(self, out, node)
+    }
+
+    fn render_media_fragment(&mut self, out: &mut String, node: &MediaFragment) {
+        write_media_fragment(self, out, node)
+    }
+
+    fn render_fragment_content(&mut self, out: &mut String, node: &FragmentContent) {
+        write_fragment_content(self, out, node)
+    }
+
+    fn render_block(&mut self, out: &mut String, label: Option<&str>, child: &[FragmentContent]) {
+        write_block(self, out, label, child)
+    }
+
+    fn render_preformat(&mut self, out: &mut String, child: &[CharacterStyle]) {
+        write_preformat(self, out, child)
+    }
+
+    fn render_heading(&mut self, out: &mut String, node: &Heading) {
+        write_heading(self, out, node)
+    }
+
+    fn render_para(&mut self, out: &mut String, node: &Para) {
+        write_para(self, out, node)
+    }
+
+    fn render_para_content(&mut self, out: &mut String, node: &ParaContent) {
+        write_para_content(self, out, node)
+    }
+
+    fn render_table(&mut self, out: &mut String, node: &Table) {
+        write_table(self, out, node)
+    }
+
+    fn render_table_row(&mut self, out: &mut String, node: &TableRow) {
+        write_table_row(self, out, node)
+    }
+
+    /// Renders a cell, or a header cell.
+    fn render_table_cell(&mut self, out: &mut String, node: &TableRowContent) {
+        write_table_cell(self, out, node)
+    }
+
+    fn render_list(&mut self, out: &mut String, node: &List) {
+        write_list(self, out, node)
+    }
+
+    fn render_numbered_list(&mut self, out: &mut String, node: &NumberedList) {
+        write_numbered_list(self, out, node)
+    }
+
+    fn render_list_item(&mut self, out: &mut String, node: &ListItem) {
+        write_list_item(self, out, node)
+    }
+
+    fn render_mixed_content(&mut self, out: &mut String, node: &MixedContent) {
+        write_mixed_content(self, out, node)
+    }
+
+    fn render_character_style(&mut self, out: &mut String, node: &CharacterStyle) {
+        write_character_style(self, out, node)
+    }
+
+    fn render_text(&mut self, out: &mut String, text: &str) {
+        out.push_str(&escape(text))
+    }
+
+    fn render_bold(&mut self, out: &mut String, node: &Bold) {
+        write_bold(self, out, node)
+    }
+
+    fn render_italic(&mut self, out: &mut String, node: &Italic) {
+        write_italic(self, out, node)
+    }
+
+    fn render_underline(&mut self, out: &mut String, node: &Underline) {
+        write_underline(self, out, node)
+    }
+
+    fn render_subscript(&mut self, out: &mut String, node: &Subscript) {
+        write_subscript(self, out, node)
+    }
+
+    fn render_superscript(&mut self, out: &mut String, node: &Superscript) {
+        write_superscript(self, out, node)
+    }
+
+    fn render_monospace(&mut self, out: &mut String, node: &Monospace) {
+        write_monospace(self, out, node)
+    }
+
+    fn render_link(&mut self, out: &mut String, node: &Link) {
+        write_link(self, out, node)
+    }
+
+    fn render_inline(&mut self, out: &mut String, node: &Inline) {
+        write_inline(self, out, node)
+    }
+
+    fn render_xref(&mut self, out: &mut String, node: &XRef) {
+        write_xref(self, out, node)
+    }
+
+    fn render_anchor(&mut self, out: &mut String, node: &Anchor) {
+        write_anchor(self, out, node)
+    }
+
+    fn render_placeholder(&mut self, out: &mut String, node: &Placeholder) {
+        write_placeholder(self, out, node)
+    }
+
+    fn render_br(&mut self, out: &mut String) {
+        out.push_str("<br>")
+    }
+
+    fn render_image(&mut self, out: &mut String, node: &Image) {
+        write_image(self, out, node)
+    }
+}
+
+/// Template that renders every node with the default HTML, resolving URLs with a resolver.
+#[derive(Clone, Copy)]
+pub struct DefaultTemplate<'r> {
+    pub resolver: &'r dyn XRefResolver,
+}
+
+impl Default for DefaultTemplate<'static> {
+    fn default() -> Self {
+        Self {
+            resolver: &fragment_resolver,
+        }
+    }
+}
+
+impl HtmlTemplate for DefaultTemplate<'_> {
+    fn resolver(&self) -> &dyn XRefResolver {
+        self.resolver
+    }
+}
+
+/// Resolves targets with an empty href, or with no href, docid or uriid, to the HTML ID of
+/// the fragment in this document, and other targets with [`href_resolver`].
+pub fn fragment_resolver(target: &XRefTarget) -> Option<String> {
+    let internal = match target.href {
+        Some(href) => href.is_empty(),
+        None => target.docid.is_none() && target.uriid.is_none(),
+    };
+    match target.frag {
+        "" | "default" => href_resolver(target),
+        frag if internal => Some(format!("#{}", fragment_id(frag))),
+        _ => href_resolver(target),
+    }
+}
+
+/// Renders a document as an HTML `article` element with the default template.
+pub fn to_html(doc: &Document) -> String {
+    to_html_with(doc, &mut DefaultTemplate::default())
+}
+
+/// Renders a document as an HTML `article` element with the given template.
+pub fn to_html_with<T: HtmlTemplate + ?Sized>(doc: &Document, template: &mut T) -> String {
+    let mut out = String::new();
+    template.render_document(&mut out, doc);
+    out
+}
+
+/// Escapes text for use in HTML content or attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Returns the HTML ID of the section with the PSML ID.
+/// Sections and fragments are prefixed differently, as PSML allows them to share IDs.
+pub fn section_id(id: &str) -> String {
+    format!("section-{id}")
+}
+
+/// Returns the HTML ID of the fragment with the PSML ID.
+pub fn fragment_id(id: &str) -> String {
+    format!("fragment-{id}")
+}
+
+/// URL schemes allowed in links and images.
+pub const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto", "tel", "ftp"];
+
+/// Returns whether the URL is relative or uses a scheme in [`SAFE_SCHEMES`].
+pub fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore leading spaces and control characters, and tabs and newlines anywhere.
+    let url: String = url
+        .trim_start_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => SAFE_SCHEMES
+            .iter()
+            .any(|scheme| url[..end].eq_ignore_ascii_case(scheme)),
+        _ => true,
+    }
+}
+
+/// Writes a start tag with the attributes that have a value.
+/// URLs in `href` and `src` attributes that are not safe are left out.
+fn start_tag(out: &mut String, name: &str, attrs: &[(&str, Option<&str>)]) {
+    out.push('<');
+    out.push_str(name);
+    for (attr, value) in attrs {
+        let value = value.filter(|value| !matches!(*attr, "href" | "src") || is_safe_url(value));
+        if let Some(value) = value {
+            out.push_str(&format!(" {attr}=\"{}\"", escape(value)));
+        }
+    }
+    out.push('>');
+}
+
+/// Returns the comma-separated labels, or none if there are no labels.
+fn labels(labels: &LabelSet) -> Option<String> {
+    (!labels.is_empty()).then(|| labels.to_string())
+}
+
+fn end_tag(out: &mut String, name: &str) {
+    out.push_str(&format!("</{name}>"));
+}
+
+fn text_align(align: Option<&Alignment>) -> Option<&'static str> {
+    align.map(|align| match align {
+        Alignment::Left => "text-align:left",
+        Alignment::Center => "text-align:center",
+        Alignment::Right => "text-align:right",
+        Alignment::Justify => "text-align:justify",
+    })
+}
+
+pub fn write_document<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &Document,
+) {
+    start_tag(
+        out,
+        "article",
+        &[
+            ("data-docid", node.docid()),
+            ("data-type", node.doc_type.as_deref()),
+        ],
+    );
+    for section in &node.sections {
+        template.render_section(out, section);
+    }
+    end_tag(out, "article");
+}
+
+pub fn write_section<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Section) {
+    start_tag(out, "section", &[("id", Some(&section_id(&node.id)))]);
+    if let Some(title) = &node.content_title {
+        out.push_str(&format!("<header>{}</header>", escape(title)));
+    }
+    for content in &node.content {
+        template.render_section_content(out, content);
+    }
+    end_tag(out, "section");
+}
+
+pub fn write_section_content<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &SectionContent,
+) {
+    match node {
+        SectionContent::Fragment(frag) => template.render_fragment(out, frag),
+        SectionContent::PropertiesFragment(frag) => template.render_properties_fragment(out, frag),
+        SectionContent::XRefFragment(frag) => template.render_xref_fragment(out, frag),
+        SectionContent::Media(frag) => template.render_media_fragment(out, frag),
+        SectionContent::Title { text } => {
+            out.push_str(&format!("<header>{}</header>", escape(text)))
+        }
+    }
+}
+
+pub fn write_fragment<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &Fragment,
+) {
+    start_tag(
+        out,
+        "div",
+        &[
+            ("id", Some(&fragment_id(&node.id))),
+            ("class", Some("fragment")),
+            ("data-type", node.frag_type.as_deref()),
+            ("data-labels", labels(&node.labels).as_deref()),
+        ],
+    );
+    for content in &node.content {
+        template.render_fragment_content(out, content);
+    }
+    end_tag(out, "div");
+}
+
+pub fn write_properties_fragment<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &PropertiesFragment,
+) {
+    start_tag(
+        out,
+        "dl",
+        &[
+            ("id", Some(&fragment_id(&node.id))),
+            ("class", Some("properties-fragment")),
+            ("data-type", node.frag_type.as_deref()),
+            ("data-labels", labels(&node.labels).as_deref()),
+        ],
+    );
+    for property in &node.properties {
+        template.render_property(out, property);
+    }
+    end_tag(out, "dl");
+}
+
+/// Writes a property as a term with a description for each value.
+pub fn write_property<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &Property,
+) {
+    start_tag(out, "dt", &[("data-name", Some(&node.name))]);
+    out.push_str(&escape(node.title.as_deref().unwrap_or(&node.name)));
+    end_tag(out, "dt");
+
+    if let Some(value) = &node.attr_value {
+        out.push_str(&format!("<dd>{}</dd>", escape(value)));
+    }
+    for value in &node.values {
+        out.push_str("<dd>");
+        match value {
+            PropertyValue::XRef(xref) => template.render_xref(out, xref),
+            PropertyValue::Link(href) => {
+                start_tag(out, "a", &[("href", Some(href))]);
+                out.push_str(&escape(href));
+                end_tag(out, "a");
+            }
+            PropertyValue::Markup(markup) => {
+                for node in &markup.content {
+                    template.render_character_style(out, node);
+                }
+            }
+            PropertyValue::Markdown(text) | PropertyValue::Value(text) => {
+                out.push_str(&escape(text))
+            }
+        }
+        out.push_str("</dd>");
+    }
+}
+
+pub fn write_xref_fragment<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &XRefFragment,
+) {
+    start_tag(
+        out,
+        "div",
+        &[
+            ("id", Some(&fragment_id(&node.id))),
+            ("class", Some("xref-fragment")),
+            ("data-type", node.frag_type.as_deref()),
+            ("data-labels", labels(&node.labels).as_deref()),
+        ],
+    );
+    for xref in &node.xrefs {
+        template.render_block_xref(out, xref);
+    }
+    end_tag(out, "div");
+}
+
+/// Writes a block xref as a paragraph containing a link.
+pub fn write_block_xref<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &BlockXRef,
+) {
+    let text = [
+        &node.title,
+        &node.urititle,
+        &node.href,
+        &node.docid,
+        &node.uriid,
+    ]
+    .into_iter()
+    .flatten()
+    .find(|text| !text.is_empty())
+    .map(String::as_str)
+    .unwrap_or("");
+    let href = template.resolver().resolve(&node.into());
+
+    out.push_str("<p class=\"blockxref\">");
+    start_tag(out, "a", &[("href", href.as_deref())]);
+    out.push_str(&escape(text));
+    end_tag(out, "a");
+    out.push_str("</p>");
+}
+
+/// Writes a media fragment with its content as preformatted text.
+pub fn write_media_fragment<T: HtmlTemplate + ?Sized>(
+    _template: &mut T,
+    out: &mut String,
+    node: &MediaFragment,
+) {
+    start_tag(
+        out,
+        "pre",
+        &[
+            ("id", Some(&fragment_id(&node.id))),
+            ("class", Some("media-fragment")),
+            ("data-mediatype", Some(&node.mediatype)),
+        ],
+    );
+    out.push_str(&escape(&node.content));
+    end_tag(out, "pre");
+}
+
+pub fn write_fragment_content<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &FragmentContent,
+) {
+    match node {
+        FragmentContent::Text(text) => template.render_text(out, text),
+        FragmentContent::Heading(heading) => template.render_heading(out, heading),
+        FragmentContent::Block { label, child } => {
+            template.render_block(out, label.as_deref(), child)
+        }
+        FragmentContent::BlockXRef(xref) => template.render_block_xref(out, xref),
+        FragmentContent::Para(para) => template.render_para(out, para),
+        FragmentContent::Preformat { child } => template.render_preformat(out, child),
+        FragmentContent::Image(image) => template.render_image(out, image),
+        FragmentContent::Table(table) => template.render_table(out, table),
+        FragmentContent::List(list) => template.render_list(out, list),
+        FragmentContent::NumberedList(list) => template.render_numbered_list(out, list),
+    }
+}
+
+/// Writes a block as a `div` with the label as its class.
+pub fn write_block<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    label: Option<&str>,
+    child: &[FragmentContent],
+) {
+    let class = match label {
+        Some(label) => format!("block {label}"),
+        None => "block".to_string(),
+    };
+    start_tag(out, "div", &[("class", Some(&class))]);
+    for content in child {
+        template.render_fragment_content(out, content);
+    }
+    out.push_str("</div>");
+}
+
+pub fn write_preformat<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    child: &[CharacterStyle],
+) {
+    out.push_str("<pre>");
+    for content in child {
+        template.render_character_style(out, content);
+    }
+    out.push_str("</pre>");
+}
+
+pub fn write_heading<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Heading) {
+    let tag = format!("h{}", node.level.clamp(1, 6));
+    out.push_str(&format!("<{tag}>"));
+    for content in &node.content {
+        template.render_character_style(out, content);
+    }
+    end_tag(out, &tag);
+}
+
+/// Writes a paragraph, with the indent level and numbering as attributes
+/// and the prefix in a leading `span`.
+pub fn write_para<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Para) {
+    let indent = node.indent.map(|indent| indent.to_string());
+    start_tag(
+        out,
+        "p",
+        &[
+            ("class", (node.numbered == Some(true)).then_some("numbered")),
+            ("data-indent", indent.as_deref()),
+        ],
+    );
+    if let Some(prefix) = &node.prefix {
+        out.push_str(&format!(
+            "<span class=\"prefix\">{}</span> ",
+            escape(prefix)
+        ));
+    }
+    for content in &node.content {
+        template.render_para_content(out, content);
+    }
+    out.push_str("</p>");
+}
+
+/// Writes a table, grouping the rows into a head, body and foot by their part.
+/// Column widths and alignment are written as styles on `col` elements.
+pub fn write_table<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Table) {
+    start_tag(
+        out,
+        "table",
+        &[
+            ("class", node.role.as_deref()),
+            ("summary", node.summary.as_deref()),
+        ],
+    );
+    if let Some(caption) = &node.caption {
+        if !caption.caption.is_empty() {
+            out.push_str(&format!("<caption>{}</caption>", escape(&caption.caption)));
+        }
+    }
+
+    if node
+        .cols
+        .iter()
+        .any(|col| col.width.is_some() || col.align.is_some() || col.role.is_some())
+    {
+        out.push_str("<colgroup>");
+        for col in &node.cols {
+            let style = [
+                col.width.as_ref().map(|width| format!("width:{width}")),
+                text_align(col.align.as_ref()).map(str::to_string),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join(";");
+            start_tag(
+                out,
+                "col",
+                &[
+                    ("class", col.role.as_deref()),
+                    (
+                        "style",
+                        Some(style.as_str()).filter(|style| !style.is_empty()),
+                    ),
+                ],
+            );
+        }
+        out.push_str("</colgroup>");
+    }
+
+    for (tag, part) in [
+        ("thead", TablePart::Header),
+        ("tbody", TablePart::Body),
+        ("tfoot", TablePart::Footer),
+    ] {
+        let rows: Vec<&TableRow> = node
+            .rows
+            .iter()
+            .filter(|row| row.part.as_ref().unwrap_or(&TablePart::Body) == &part)
+            .collect();
+        if rows.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("<{tag}>"));
+        for row in rows {
+            template.render_table_row(out, row);
+        }
+        end_tag(out, tag);
+    }
+    end_tag(out, "table");
+}
+
+pub fn write_table_row<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &TableRow,
+) {
+    start_tag(
+        out,
+        "tr",
+        &[
+            ("class", node.role.as_deref()),
+            ("style", text_align(node.align.as_ref())),
+        ],
+    );
+    for cell in &node.cells {
+        template.render_table_cell(out, cell);
+    }
+    end_tag(out, "tr");
+}
+
+/// Writes a cell as `td`, or a header cell as `th`.
+pub fn write_table_cell<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &TableRowContent,
+) {
+    let tag = match node {
+        TableRowContent::Cell(_) => "td",
+        TableRowContent::HeaderCell(_) => "th",
+    };
+    let cell = node.cell();
+    let colspan = cell.colspan.map(|span| span.to_string());
+    let rowspan = cell.rowspan.map(|span| span.to_string());
+    start_tag(
+        out,
+        tag,
+        &[
+            ("class", cell.role.as_deref()),
+            ("colspan", colspan.as_deref()),
+            ("rowspan", rowspan.as_deref()),
+            ("style", text_align(cell.align.as_ref())),
+        ],
+    );
+    for content in &cell.content {
+        template.render_mixed_content(out, content);
+    }
+    end_tag(out, tag);
+}
+
+pub fn write_list<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &List) {
+    let style = node.list_type.as_ref().map(|kind| match kind {
+        ListKind::None => "list-style-type:none",
+        ListKind::Disc => "list-style-type:disc",
+        ListKind::Circle => "list-style-type:circle",
+        ListKind::Square => "list-style-type:square",
+    });
+    start_tag(
+        out,
+        "ul",
+        &[("class", node.role.as_deref()), ("style", style)],
+    );
+    for item in &node.items {
+        template.render_list_item(out, item);
+    }
+    end_tag(out, "ul");
+}
+
+pub fn write_numbered_list<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &NumberedList,
+) {
+    let start = node.start.map(|start| start.to_string());
+    let list_type = node.list_type.as_ref().map(|kind| match kind {
+        NumberedListKind::Arabic => "1",
+        NumberedListKind::UpperAlpha => "A",
+        NumberedListKind::LowerAlpha => "a",
+        NumberedListKind::UpperRoman => "I",
+        NumberedListKind::LowerRoman => "i",
+    });
+    start_tag(
+        out,
+        "ol",
+        &[
+            ("class", node.role.as_deref()),
+            ("start", start.as_deref()),
+            ("type", list_type),
+        ],
+    );
+    for item in &node.items {
+        template.render_list_item(out, item);
+    }
+    end_tag(out, "ol");
+}
+
+pub fn write_list_item<T: HtmlTemplate + ?Sized>(
+    template: &mut T,
+    out: &mut String,
+    node: &ListItem,
+) {
+    out.push_str("<li>");
+    for content in &node.content {
+        template.render_mixed_content(out, content);
+    }
+    out.push_str("</li>");
+}
+
+/// Generates a function dispatching each variant of a content enum to the template.
+macro_rules! write_content {
+    ($name:ident, $ty:ident { $($variant:pat => $render:ident($($arg:expr),*)),* $(,)? }) => {
+        pub fn $name<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &$ty) {
+            match node {
+                $ty::Text(text) => template.render_text(out, text),
+                $ty::Bold(bold) => template.render_bold(out, bold),
+                $ty::Italic(italic) => template.render_italic(out, italic),
+                $ty::Underline(underline) => template.render_underline(out, underline),
+                $ty::Subscript(subscript) => template.render_subscript(out, subscript),
+                $ty::Superscript(superscript) => template.render_superscript(out, superscript),
+                $ty::Monospace(monospace) => template.render_monospace(out, monospace),
+                $ty::XRef(xref) => template.render_xref(out, xref),
+                $ty::Link(link) => template.render_link(out, link),
+                $ty::Inline(inline) => template.render_inline(out, inline),
+                $ty::Anchor(anchor) => template.render_anchor(out, anchor),
+                $ty::Placeholder(placeholder) => template.render_placeholder(out, placeholder),
+                $ty::Br => template.render_br(out),
+                $($variant => template.$render(out, $($arg),*),)*
+            }
+        }
+    };
+}
+
+write_content!(write_character_style, CharacterStyle {});
+
+write_content!(write_para_content, ParaContent {
+    ParaContent::Image(image) => render_image(image),
+});
+
+write_content!(write_mixed_content, MixedContent {
+    MixedContent::Image(image) => render_image(image),
+    MixedContent::Para(para) => render_para(para),
+    MixedContent::Block { label, child } => render_block(label.as_deref(), child),
+    MixedContent::Preformat { child } => render_preformat(child),
+    MixedContent::List(list) => render_list(list),
+    MixedContent::NumberedList(list) => render_numbered_list(list),
+});
+
+/// Generates a function writing a character style as an HTML element.
+macro_rules! write_char_style {
+    ($name:ident, $ty:ty, $tag:literal) => {
+        pub fn $name<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &$ty) {
+            out.push_str(concat!("<", $tag, ">"));
+            for content in &node.content {
+                template.render_character_style(out, content);
+            }
+            out.push_str(concat!("</", $tag, ">"));
+        }
+    };
+}
+
+write_char_style!(write_bold, Bold, "b");
+write_char_style!(write_italic, Italic, "i");
+write_char_style!(write_underline, Underline, "u");
+write_char_style!(write_subscript, Subscript, "sub");
+write_char_style!(write_superscript, Superscript, "sup");
+write_char_style!(write_monospace, Monospace, "code");
+
+pub fn write_link<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Link) {
+    start_tag(out, "a", &[("href", node.href.as_deref())]);
+    for content in &node.content {
+        template.render_character_style(out, content);
+    }
+    end_tag(out, "a");
+}
+
+/// Writes an inline label as a `span` with the label as its class.
+pub fn write_inline<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Inline) {
+    start_tag(out, "span", &[("class", Some(&node.label))]);
+    for content in &node.content {
+        template.render_character_style(out, content);
+    }
+    end_tag(out, "span");
+}
+
+/// Writes an xref as an anchor linking to the resolved URL.
+/// Xrefs with no content use their title or target as the link text.
+pub fn write_xref<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &XRef) {
+    let text = [Some(&node.content), node.title.as_ref(), node.href.as_ref()]
+        .into_iter()
+        .flatten()
+        .chain(node.docid.as_ref())
+        .chain(node.uriid.as_ref())
+        .find(|text| !text.is_empty())
+        .map(String::as_str)
+        .unwrap_or("");
+    let href = template.resolver().resolve(&node.into());
+
+    start_tag(
+        out,
+        "a",
+        &[
+            ("class", Some("xref")),
+            ("href", href.as_deref()),
+            ("title", node.title.as_deref()),
+        ],
+    );
+    out.push_str(&escape(text));
+    end_tag(out, "a");
+}
+
+pub fn write_anchor<T: HtmlTemplate + ?Sized>(_template: &mut T, out: &mut String, node: &Anchor) {
+    start_tag(out, "a", &[("id", Some(&node.name))]);
+    end_tag(out, "a");
+}
+
+pub fn write_placeholder<T: HtmlTemplate + ?Sized>(
+    _template: &mut T,
+    out: &mut String,
+    node: &Placeholder,
+) {
+    start_tag(
+        out,
+        "span",
+        &[
+            ("class", Some("placeholder")),
+            ("data-name", Some(&node.name)),
+        ],
+    );
+    out.push_str(&escape(&node.content));
+    end_tag(out, "span");
+}
+
+pub fn write_image<T: HtmlTemplate + ?Sized>(template: &mut T, out: &mut String, node: &Image) {
+    let src = template.resolver().resolve(&node.target());
+    let width = node.width().map(|width| width.to_string());
+    let height = node.height().map(|height| height.to_string());
+    start_tag(
+        out,
+        "img",
+        &[
+            ("src", src.as_deref()),
+            ("alt", Some(node.alt().unwrap_or(""))),
+            ("width", width.as_deref()),
+            ("height", height.as_deref()),
+        ],
+    );
+}
@@ -0,0 +1,3 @@
+//! Rendering of PSML for display.
+
+pub mod html;
@@ -0,0 +1,487 @@
+//! Splitting documents into parts included by block xrefs, and composing them back.
+//!
+//! Splitting moves runs of fragments into part documents and leaves an xref fragment with an
+//! embed or transclude block xref in their place. Composing inlines the target of each embed or
+//! transclude block xref, so composing the parts of a split document gives the original back.
+//! References to moved fragments are retargeted either way.
+
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+    ops::Range,
+};
+
+use crate::{
+    diff::fragment_id,
+    error::{PsmlError, PsmlResult},
+    extract::PlainText,
+    model::{
+        BlockXRef, BlockXRefKind, Document, DocumentInfo, FragmentContent, Locator, SectionContent,
+        URIDescriptor, XRefFragment, XRefTarget,
+    },
+    xrefs::{self, is_internal, NewTarget},
+};
+
+/// Where to split a document into parts.
+/// Parts never cross sections, and any section title stays in the parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitBy {
+    /// The fragments of each section become a part.
+    Section,
+    /// A part starts at each fragment beginning with a heading of this level or above,
+    /// and ends before the next one or at the end of the section.
+    Heading(u8),
+    /// The fragments of each section are split into parts of at most this many fragments.
+    FragmentCount(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Options for splitting a document.
+pub struct SplitOptions {
+    pub by: SplitBy,
+    /// Type of the block xrefs to the parts, `Embed` or `Transclude`.
+    pub kind: BlockXRefKind,
+    /// Prefix of the href of each part, which is followed by the part number and `.psml`.
+    pub prefix: String,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        SplitOptions {
+            by: SplitBy::Section,
+            kind: BlockXRefKind::Embed,
+            prefix: "part".to_string(),
+        }
+    }
+}
+
+/// A document split off from its parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// Href the parent uses to include the part.
+    pub href: String,
+    pub document: Document,
+}
+
+/// The result of splitting a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Split {
+    /// The document with each part replaced by an xref fragment including it.
+    pub parent: Document,
+    /// The parts in document order.
+    pub parts: Vec<Part>,
+}
+
+impl Document {
+    /// Splits this document into parts.
+    ///
+    /// Each part keeps the attributes of its section and the IDs of its fragments, and gets the
+    /// docid of this document with the part number appended. The xref fragment left in the
+    /// parent has the ID of the first fragment in the part. References within this document are
+    /// retargeted to the document now containing their fragment. If this document has a table of
+    /// contents, the parent and each part get one generated from their own content.
+    pub fn split(&self, options: &SplitOptions) -> Split {
+        let mut parent = self.clone();
+        let mut parts = vec![];
+        // Index of the part each moved fragment is in, by fragment ID.
+        let mut owners = HashMap::new();
+        for section in &mut parent.sections {
+            let mut content = std::mem::take(&mut section.content);
+            let template = section.clone();
+            let mut removed = 0;
+            for range in part_ranges(&content, options.by) {
+                let range = range.start - removed..range.end - removed;
+                let moved: Vec<SectionContent> = content.drain(range.clone()).collect();
+                removed += moved.len() - 1;
+
+                let number = parts.len() + 1;
+                let href = format!("{}-{number}.psml", options.prefix);
+                let docid = self.docid().map(|docid| format!("{docid}-{number}"));
+                let title = starting_heading(&moved[0])
+                    .map(|heading| heading.plain_text())
+                    .or_else(|| template.content_title.clone())
+                    .or_else(|| template.title.clone());
+                let id = fragment_id(&moved[0]).unwrap_or_default().to_string();
+                for id in moved.iter().filter_map(fragment_id) {
+                    owners.insert(id.to_string(), parts.len());
+                }
+
+                let xref = BlockXRef {
+                    href: Some(href.clone()),
+                    docid: docid.clone(),
+                    frag: "default".to_string(),
+                    title: title.clone(),
+                    xref_type: Some(options.kind.clone()),
+                    ..Default::default()
+                };
+                content.insert(
+                    range.start,
+                    SectionContent::XRefFragment(XRefFragment::new(id).with_xrefs(vec![xref])),
+                );
+
+                let mut section = template.clone();
+                section.content = moved;
+                let document = Document {
+                    doc_info: Some(DocumentInfo {
+                        uri: Some(URIDescriptor {
+                            docid,
+                            doc_type: self.doc_type.clone(),
+                            title,
+                            ..Default::default()
+                        }),
+                        publication: None,
+                    }),
+                    frag_info: vec![],
+                    sections: vec![section],
+                    toc: None,
+                    doc_type: self.doc_type.clone(),
+                    edit: self.edit,
+                    level: self.level.clone(),
+                    lockstructure: self.lockstructure,
+                };
+                parts.push(Part { href, document });
+            }
+            section.content = content;
+        }
+
+        for locator in std::mem::take(&mut parent.frag_info) {
+            match locator.fragment_id.as_ref().and_then(|id| owners.get(id)) {
+                Some(&part) => parts[part].document.frag_info.push(locator),
+                None => parent.frag_info.push(locator),
+            }
+        }
+
+        let (uriid, docid) = (self.uriid(), self.docid());
+        let parent_target = (uriid.is_some() || docid.is_some()).then(|| NewTarget {
+            uriid: uriid.map(str::to_string),
+            docid: docid.map(str::to_string),
+            ..Default::default()
+        });
+        let part_targets: Vec<NewTarget> = parts
+            .iter()
+            .map(|part| NewTarget {
+                href: Some(part.href.clone()),
+                docid: part.document.docid().map(str::to_string),
+                ..Default::default()
+            })
+            .collect();
+        let documents = iter::once((None, &mut parent)).chain(
+            parts
+                .iter_mut()
+                .enumerate()
+                .map(|(index, part)| (Some(index), &mut part.document)),
+        );
+        for (this, document) in documents {
+            xrefs::rewrite(iter::once(document), |target| {
+                if !is_internal(target, uriid, docid) {
+                    return None;
+                }
+                let owner = owners.get(target.frag).copied();
+                let new = match owner {
+                    _ if owner == this => this.map(|_| NewTarget::default()),
+                    Some(part) => Some(part_targets[part].clone()),
+                    None => parent_target.clone(),
+                }?;
+                Some(NewTarget {
+                    frag: target.frag.to_string(),
+                    ..new
+                })
+            });
+        }
+
+        if self.toc.is_some() {
+            parent.toc = Some(parent.toc());
+            for part in &mut parts {
+                part.document.toc = Some(part.document.toc());
+            }
+        }
+        Split { parent, parts }
+    }
+
+    /// Returns this document with the target of each embed or transclude block xref inlined,
+    /// recursively. A block xref to the whole document inlines the fragments of all its sections,
+    /// and one to a fragment inlines only that fragment.
+    ///
+    /// Other block xrefs in the same xref fragment are kept in xref fragments around the inlined
+    /// fragments. References to inlined fragments are retargeted to this document.
+    /// Returns an error if a target cannot be resolved, includes itself or does not contain the
+    /// fragment, or if an inlined fragment has the same ID as another fragment.
+    /// If this document has a table of contents, it is generated again from the composed content.
+    pub fn compose(
+        &self,
+        resolve: impl FnMut(&XRefTarget) -> Option<Document>,
+    ) -> PsmlResult<Document> {
+        Composer {
+            resolve,
+            stack: vec![],
+        }
+        .compose(self.clone())
+        .map(|document| match document.toc {
+            Some(_) => document.with_toc(),
+            None => document,
+        })
+    }
+}
+
+/// Returns the ranges of the content of a section to move into parts.
+fn part_ranges(content: &[SectionContent], by: SplitBy) -> Vec<Range<usize>> {
+    let fragments: Vec<usize> = content
+        .iter()
+        .enumerate()
+        .filter(|(_, content)| fragment_id(content).is_some())
+        .map(|(index, _)| index)
+        .collect();
+    match by {
+        SplitBy::Section => fragments
+            .first()
+            .map(|&first| first..content.len())
+            .into_iter()
+            .collect(),
+        SplitBy::Heading(level) => {
+            let starts: Vec<usize> = fragments
+                .into_iter()
+                .filter(|&index| {
+                    starting_heading(&content[index]).is_some_and(|heading| heading.level <= level)
+                })
+                .collect();
+            starts
+                .iter()
+                .zip(starts.iter().skip(1).chain([&content.len()]))
+                .map(|(&start, &end)| start..end)
+                .collect()
+        }
+        SplitBy::FragmentCount(count) => fragments
+            .chunks(count.max(1))
+            .map(|chunk| chunk[0]..chunk[chunk.len() - 1] + 1)
+            .collect(),
+    }
+}
+
+/// Returns the heading a fragment starts with.
+fn starting_heading(content: &SectionContent) -> Option<&crate::text::Heading> {
+    match content {
+        SectionContent::Fragment(fragment) => match fragment.content.first() {
+            Some(FragmentContent::Heading(heading)) => Some(heading),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns true if the block xref includes its target.
+fn is_inclusion(xref: &BlockXRef) -> bool {
+    matches!(
+        xref.xref_type,
+        Some(BlockXRefKind::Embed | BlockXRefKind::Transclude)
+    )
+}
+
+/// Returns a description of a target for errors.
+fn describe(target: &XRefTarget) -> String {
+    match (target.href, target.docid, target.uriid) {
+        (Some(href), _, _) => format!("href {href:?}"),
+        (None, Some(docid), _) => format!("docid {docid:?}"),
+        (None, None, Some(uriid)) => format!("uriid {uriid:?}"),
+        (None, None, None) => "no target".to_string(),
+    }
+}
+
+fn error(msg: String) -> PsmlError {
+    PsmlError::ComposeError { msg }
+}
+
+/// A document inlined into another, and the IDs of its fragments that were inlined.
+struct Inclusion {
+    target: NewTarget,
+    fragments: HashSet<String>,
+}
+
+impl Inclusion {
+    /// Returns true if the target is the included document.
+    fn includes(&self, target: &XRefTarget) -> bool {
+        let same = |a: &Option<String>, b: Option<&str>| a.is_some() && a.as_deref() == b;
+        same(&self.target.uriid, target.uriid)
+            || same(&self.target.docid, target.docid)
+            || same(&self.target.href, target.href)
+    }
+}
+
+/// Inlines included documents, tracking the targets being composed to detect cycles.
+struct Composer<F> {
+    resolve: F,
+    stack: Vec<NewTarget>,
+}
+
+impl<F: FnMut(&XRefTarget) -> Option<Document>> Composer<F> {
+    fn compose(&mut self, mut document: Document) -> PsmlResult<Document> {
+        let mut inclusions = vec![];
+        for index in 0..document.sections.len() {
+            let content = std::mem::take(&mut document.sections[index].content);
+            let mut composed = vec![];
+            for item in content {
+                match item {
+                    SectionContent::XRefFragment(fragment)
+                        if fragment.xrefs.iter().any(is_inclusion) =>
+                    {
+                        self.inline(
+                            fragment,
+                            &mut composed,
+                            &mut document.frag_info,
+                            &mut inclusions,
+                        )?;
+                    }
+                    item => composed.push(item),
+                }
+            }
+            document.sections[index].content = composed;
+        }
+
+        let mut ids = HashSet::new();
+        if let Some(id) = document
+            .fragment_ids()
+            .into_iter()
+            .find(|id| !ids.insert(*id))
+        {
+            return Err(PsmlError::FragmentError {
+                id: id.to_string(),
+                msg: "duplicate fragment id".to_string(),
+            });
+        }
+
+        xrefs::rewrite(iter::once(&mut document), |target| {
+            inclusions
+                .iter()
+                .any(|inclusion| {
+                    inclusion.includes(target) && inclusion.fragments.contains(target.frag)
+                })
+                .then(|| NewTarget {
+                    frag: target.frag.to_string(),
+                    ..Default::default()
+                })
+        });
+        Ok(document)
+    }
+
+    /// Replaces an xref fragment with the fragments its inclusions target,
+    /// keeping its other block xrefs in xref fragments between them.
+    fn inline(
+        &mut self,
+        fragment: XRefFragment,
+        composed: &mut Vec<SectionContent>,
+        frag_info: &mut Vec<Locator>,
+        inclusions: &mut Vec<Inclusion>,
+    ) -> PsmlResult<()> {
+        let template = XRefFragment {
+            xrefs: vec![],
+            ..fragment.clone()
+        };
+        let mut kept = vec![];
+        let mut runs = 0;
+        let mut flush = |kept: &mut Vec<BlockXRef>, composed: &mut Vec<SectionContent>| {
+            if !kept.is_empty() {
+                let id = match runs {
+                    0 => template.id.clone(),
+                    n => format!("{}-{n}", template.id),
+                };
+                runs += 1;
+                composed.push(SectionContent::XRefFragment(XRefFragment {
+                    id,
+                    xrefs: std::mem::take(kept),
+                    ..template.clone()
+                }));
+            }
+        };
+
+        for xref in fragment.xrefs {
+            if !is_inclusion(&xref) {
+                kept.push(xref);
+                continue;
+            }
+            flush(&mut kept, composed);
+            let (content, locators, inclusion) = self.include(&xref)?;
+            composed.extend(content);
+            frag_info.extend(locators);
+            inclusions.push(inclusion);
+        }
+        flush(&mut kept, composed);
+        Ok(())
+    }
+
+    /// Returns the fragments and locators a block xref includes, with references retargeted.
+    fn include(
+        &mut self,
+        xref: &BlockXRef,
+    ) -> PsmlResult<(Vec<SectionContent>, Vec<Locator>, Inclusion)> {
+        let target = XRefTarget::from(xref);
+        let key = NewTarget {
+            frag: String::new(),
+            ..NewTarget::from(&target)
+        };
+        if self.stack.contains(&key) {
+            return Err(error(format!("{} includes itself", describe(&target))));
+        }
+        let document = (self.resolve)(&target)
+            .ok_or_else(|| error(format!("cannot resolve {}", describe(&target))))?;
+        self.stack.push(key.clone());
+        let mut document = self.compose(document)?;
+        self.stack.pop();
+
+        let fragments: HashSet<String> = match target.frag {
+            "" | "default" => document
+                .fragment_ids()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            frag if document.find_fragment(frag).is_some() => HashSet::from([frag.to_string()]),
+            frag => {
+                return Err(error(format!(
+                    "no fragment {frag:?} in {}",
+                    describe(&target)
+                )))
+            }
+        };
+
+        // References within the included document now point to this one if their fragment is
+        // inlined, or to the included document otherwise.
+        let uriid = document.uriid().map(str::to_string);
+        let docid = document.docid().map(str::to_string);
+        xrefs::rewrite(iter::once(&mut document), |reference| {
+            if !is_internal(reference, uriid.as_deref(), docid.as_deref()) {
+                return None;
+            }
+            let new = match fragments.contains(reference.frag) {
+                true => NewTarget::default(),
+                false => key.clone(),
+            };
+            Some(NewTarget {
+                frag: reference.frag.to_string(),
+                ..new
+            })
+        });
+
+        let content = document
+            .sections
+            .into_iter()
+            .flat_map(|section| section.content)
+            .filter(|content| fragment_id(content).is_some_and(|id| fragments.contains(id)))
+            .collect();
+        let locators = document
+            .frag_info
+            .into_iter()
+            .filter(|locator| {
+                locator
+                    .fragment_id
+                    .as_ref()
+                    .is_some_and(|id| fragments.contains(id))
+            })
+            .collect();
+        Ok((
+            content,
+            locators,
+            Inclusion {
+                target: key,
+                fragments,
+            },
+        ))
+    }
+}
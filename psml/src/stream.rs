@@ -0,0 +1,418 @@
+//! Incremental reading and writing of PSML documents.
+//!
+//! [`PsmlReader`] yields the document header, each section header and each fragment in turn,
+//! so only one fragment has to be held in memory at a time.
+//! [`PsmlWriter`] writes the same events back out as they are produced.
+
+use std::io::{BufRead, Read, Write};
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader, Writer,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::{PsmlError, PsmlResult},
+    model::{Document, DocumentInfo, Locator, Section, SectionContent},
+    toc::Toc,
+    xml::{deserialize, serialise, XML_DECLARATION},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsmlEvent {
+    /// Start of the document.
+    /// The document has no sections, and its metadata and table of contents are read as separate events.
+    DocumentStart(Document),
+    /// Metadata about the document.
+    DocumentInfo(DocumentInfo),
+    /// Metadata about fragments in the document.
+    FragmentInfo(Locator),
+    /// The table of contents of a processed document.
+    Toc(Toc),
+    /// Start of a section.
+    /// The section has no content, which is read as separate events.
+    SectionStart(Section),
+    /// A fragment or title in the current section.
+    Content(SectionContent),
+    SectionEnd,
+    DocumentEnd,
+}
+
+fn stream_error(err: impl ToString) -> PsmlError {
+    PsmlError::StreamError {
+        msg: err.to_string(),
+    }
+}
+
+/// Buffered reader that tracks the line and column of the input consumed so far.
+struct PositionReader<R> {
+    inner: R,
+    line: usize,
+    column: usize,
+}
+
+impl<R: BufRead> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for PositionReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.inner.fill_buf() {
+            for byte in &buf[..amt.min(buf.len())] {
+                if *byte == b'\n' {
+                    self.line += 1;
+                    self.column = 1;
+                } else if byte & 0xC0 != 0x80 {
+                    // Only count the first byte of each UTF-8 char.
+                    self.column += 1;
+                }
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamState {
+    BeforeDocument,
+    InDocument,
+    InSection,
+    Done,
+}
+
+/// Pull-based reader yielding the parts of a PSML document in order.
+pub struct PsmlReader<R: BufRead> {
+    reader: Reader<PositionReader<R>>,
+    buf: Vec<u8>,
+    state: StreamState,
+    /// Elements currently open, for locating errors.
+    path: Vec<String>,
+}
+
+impl<R: BufRead> PsmlReader<R> {
+    pub fn new(reader: R) -> Self {
+        let mut reader = Reader::from_reader(PositionReader {
+            inner: reader,
+            line: 1,
+            column: 1,
+        });
+        reader.expand_empty_elements(true);
+
+        PsmlReader {
+            reader,
+            buf: Vec::new(),
+            state: StreamState::BeforeDocument,
+            path: Vec::new(),
+        }
+    }
+
+    /// Returns the line and column of the next unread input.
+    fn location(&self) -> (usize, usize) {
+        let inner = self.reader.get_ref();
+        (inner.line, inner.column)
+    }
+
+    fn path_string(&self) -> String {
+        format!("/{}", self.path.join("/"))
+    }
+
+    /// Creates an error for malformed XML at the current position.
+    fn xml_error(&self, err: quick_xml::Error) -> PsmlError {
+        let (line, column) = self.location();
+        PsmlError::ParseError {
+            line,
+            column,
+            path: self.path_string(),
+            source: err.into(),
+        }
+    }
+
+    fn read_event(&mut self) -> PsmlResult<Event<'static>> {
+        self.buf.clear();
+        match self.reader.read_event_into(&mut self.buf) {
+            Ok(event) => Ok(event.into_owned()),
+            Err(err) => Err(self.xml_error(err)),
+        }
+    }
+
+    /// Reads the rest of the element started by `start` and returns its XML.
+    fn read_subtree(&mut self, start: BytesStart<'static>) -> PsmlResult<String> {
+        let mut writer = Writer::new(Vec::new());
+        writer
+            .write_event(Event::Start(start))
+            .map_err(stream_error)?;
+
+        let mut depth = 1;
+        while depth > 0 {
+            let event = self.read_event()?;
+            match &event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                Event::Eof => {
+                    return Err(
+                        self.xml_error(quick_xml::Error::UnexpectedEof("element".to_string()))
+                    )
+                }
+                _ => {}
+            }
+            writer.write_event(event).map_err(stream_error)?;
+        }
+
+        String::from_utf8(writer.into_inner()).map_err(stream_error)
+    }
+
+    /// Deserializes an element that started at the given position.
+    fn parse_element<T: DeserializeOwned>(
+        &self,
+        xml: &str,
+        (line, column): (usize, usize),
+    ) -> PsmlResult<T> {
+        deserialize(xml).map_err(|err| match err {
+            PsmlError::ParseError {
+                line: rel_line,
+                column: rel_column,
+                path,
+                source,
+            } => PsmlError::ParseError {
+                line: line + rel_line - 1,
+                column: if rel_line == 1 {
+                    column + rel_column - 1
+                } else {
+                    rel_column
+                },
+                path: match self.path.is_empty() {
+                    true => path,
+                    false => format!("{}{path}", self.path_string()),
+                },
+                source,
+            },
+            other => other,
+        })
+    }
+
+    /// Deserializes the start tag of an element on its own.
+    fn parse_header<T: DeserializeOwned>(
+        &self,
+        start: &BytesStart,
+        position: (usize, usize),
+    ) -> PsmlResult<T> {
+        let mut writer = Writer::new(Vec::new());
+        writer
+            .write_event(Event::Empty(start.borrow()))
+            .map_err(stream_error)?;
+        let xml = String::from_utf8(writer.into_inner()).map_err(stream_error)?;
+        self.parse_element(&xml, position)
+    }
+
+    fn next_event(&mut self) -> PsmlResult<Option<PsmlEvent>> {
+        loop {
+            if self.state == StreamState::Done {
+                return Ok(None);
+            }
+
+            let position = self.location();
+            let event = self.read_event()?;
+
+            match (self.state, event) {
+                (StreamState::BeforeDocument, Event::Eof) => {
+                    return Err(stream_error("input has no document element"))
+                }
+                (_, Event::Eof) => {
+                    return Err(
+                        self.xml_error(quick_xml::Error::UnexpectedEof("document".to_string()))
+                    )
+                }
+                (StreamState::BeforeDocument, Event::Start(start)) => {
+                    if start.name().as_ref() != b"document" {
+                        return Err(stream_error(format!(
+                            "expected document element, found {}",
+                            String::from_utf8_lossy(start.name().as_ref())
+                        )));
+                    }
+
+                    let document = self.parse_header(&start, position)?;
+                    self.path.push("document".to_string());
+                    self.state = StreamState::InDocument;
+                    return Ok(Some(PsmlEvent::DocumentStart(document)));
+                }
+                (StreamState::InDocument, Event::Start(start)) => match start.name().as_ref() {
+                    b"section" => {
+                        let section: Section = self.parse_header(&start, position)?;
+                        self.path.push(format!("section[@id='{}']", section.id));
+                        self.state = StreamState::InSection;
+                        return Ok(Some(PsmlEvent::SectionStart(section)));
+                    }
+                    b"documentinfo" => {
+                        let xml = self.read_subtree(start)?;
+                        let info = self.parse_element(&xml, position)?;
+                        return Ok(Some(PsmlEvent::DocumentInfo(info)));
+                    }
+                    b"fragmentinfo" => {
+                        let xml = self.read_subtree(start)?;
+                        let info = self.parse_element(&xml, position)?;
+                        return Ok(Some(PsmlEvent::FragmentInfo(info)));
+                    }
+                    b"toc" => {
+                        let xml = self.read_subtree(start)?;
+                        let toc = self.parse_element(&xml, position)?;
+                        return Ok(Some(PsmlEvent::Toc(toc)));
+                    }
+                    _ => {
+                        // Other metadata is not part of the model.
+                        let end = start.to_end().into_owned();
+                        self.buf.clear();
+                        if let Err(err) = self.reader.read_to_end_into(end.name(), &mut self.buf) {
+                            return Err(self.xml_error(err));
+                        }
+                    }
+                },
+                (StreamState::InDocument, Event::End(_)) => {
+                    self.path.pop();
+                    self.state = StreamState::Done;
+                    return Ok(Some(PsmlEvent::DocumentEnd));
+                }
+                (StreamState::InSection, Event::Start(start)) => {
+                    let xml = self.read_subtree(start)?;
+                    let content = self.parse_element(&xml, position)?;
+                    return Ok(Some(PsmlEvent::Content(content)));
+                }
+                (StreamState::InSection, Event::End(_)) => {
+                    self.path.pop();
+                    self.state = StreamState::InDocument;
+                    return Ok(Some(PsmlEvent::SectionEnd));
+                }
+                // Whitespace, comments, declarations etc.
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PsmlReader<R> {
+    type Item = PsmlResult<PsmlEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_event() {
+            Ok(event) => event.map(Ok),
+            Err(err) => {
+                self.state = StreamState::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Writer for producing a PSML document from events, one at a time.
+pub struct PsmlWriter<W: Write> {
+    writer: W,
+    state: StreamState,
+}
+
+impl<W: Write> PsmlWriter<W> {
+    /// Creates a writer and writes the XML declaration.
+    pub fn new(mut writer: W) -> PsmlResult<Self> {
+        writer.write_all(XML_DECLARATION.as_bytes())?;
+        Ok(PsmlWriter {
+            writer,
+            state: StreamState::BeforeDocument,
+        })
+    }
+
+    /// Writes a complete element.
+    /// Enums are written with the name of their variant instead of `root`.
+    fn write_element<T: Serialize>(&mut self, value: &T, root: &str) -> PsmlResult<()> {
+        let xml = serialise(value, Some(root), None)?;
+        self.writer.write_all(xml.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes an element without its end tag.
+    fn write_start<T: Serialize>(&mut self, value: &T, root: &str) -> PsmlResult<()> {
+        let xml = serialise(value, Some(root), None)?;
+
+        match xml.strip_suffix("/>") {
+            Some(start) if !start.contains('>') => {
+                self.writer.write_all(start.as_bytes())?;
+                self.writer.write_all(b">")?;
+            }
+            _ => {
+                let end = format!("</{root}>");
+                let start = xml
+                    .strip_suffix(&end)
+                    .ok_or_else(|| stream_error(format!("serialised {root} has no end tag")))?;
+                self.writer.write_all(start.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the next event of the document.
+    /// Content in document and section start events is written as well.
+    pub fn write_event(&mut self, event: &PsmlEvent) -> PsmlResult<()> {
+        match (self.state, event) {
+            (StreamState::BeforeDocument, PsmlEvent::DocumentStart(document)) => {
+                self.write_start(document, "document")?;
+                self.state = StreamState::InDocument;
+            }
+            (StreamState::InDocument, PsmlEvent::DocumentInfo(info)) => {
+                self.write_element(info, "documentinfo")?;
+            }
+            (StreamState::InDocument, PsmlEvent::FragmentInfo(info)) => {
+                self.write_element(info, "fragmentinfo")?;
+            }
+            (StreamState::InDocument, PsmlEvent::Toc(toc)) => {
+                self.write_element(toc, "toc")?;
+            }
+            (StreamState::InDocument, PsmlEvent::SectionStart(section)) => {
+                self.write_start(section, "section")?;
+                self.state = StreamState::InSection;
+            }
+            (StreamState::InSection, PsmlEvent::Content(content)) => {
+                self.write_element(content, "content")?;
+            }
+            (StreamState::InSection, PsmlEvent::SectionEnd) => {
+                self.writer.write_all(b"</section>")?;
+                self.state = StreamState::InDocument;
+            }
+            (StreamState::InDocument, PsmlEvent::DocumentEnd) => {
+                self.writer.write_all(b"</document>")?;
+                self.state = StreamState::Done;
+            }
+            (_, event) => {
+                let name = match event {
+                    PsmlEvent::DocumentStart(_) => "document start",
+                    PsmlEvent::DocumentInfo(_) => "document info",
+                    PsmlEvent::FragmentInfo(_) => "fragment info",
+                    PsmlEvent::Toc(_) => "table of contents",
+                    PsmlEvent::SectionStart(_) => "section start",
+                    PsmlEvent::Content(_) => "section content",
+                    PsmlEvent::SectionEnd => "section end",
+                    PsmlEvent::DocumentEnd => "document end",
+                };
+                return Err(stream_error(format!("unexpected {name} event")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes any open section and the document, and returns the inner writer.
+    pub fn finish(mut self) -> PsmlResult<W> {
+        if self.state == StreamState::InSection {
+            self.write_event(&PsmlEvent::SectionEnd)?;
+        }
+        if self.state == StreamState::InDocument {
+            self.write_event(&PsmlEvent::DocumentEnd)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
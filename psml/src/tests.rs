@@ -1,6 +1,35 @@
 use std::fs;
 
-use super::model::Document;
+use chrono::{DateTime, NaiveDate};
+use psml_derive::PsmlProperties;
+
+use super::{
+    builder::DocumentBuilder,
+    check::{self, BrokenLinkKind},
+    convert::{html as html_import, markdown},
+    diff::{self, Change, Edit, Position},
+    extract::{PlainText, TextOptions},
+    fragments::FragmentPosition,
+    lossless,
+    merge::{self, Conflict, MergeOptions},
+    model::{
+        href_resolver, BlockXRef, BlockXRefKind, Document, Fragment, FragmentContent, Fragments,
+        LabelSet, MediaEncoding, MediaFragment, MixedContent, PropertiesFragment, Property,
+        PropertyDatatype, Section, SectionContent, Table, TableCell, TableRowContent, XRef,
+        XRefResolver, XRefTarget,
+    },
+    properties::PsmlProperties as _,
+    render::html,
+    split::{SplitBy, SplitOptions},
+    stream::{PsmlEvent, PsmlReader, PsmlWriter},
+    text::{Bold, CharacterStyle, Heading, Image, Italic, Link, Para, ParaContent},
+    toc::TocPartKind,
+    validate::ViolationKind,
+    visit::{self, Visitor},
+    visit_mut::VisitorMut,
+    xrefs::{self, NewTarget, ReferenceKind, TargetKey, XRefIndex},
+    PsmlError, WriteOptions,
+};
 
 #[test]
 fn test_fragment() {
@@ -39,6 +68,211 @@ fn test_full_doc() {
     .unwrap();
 }
 
+#[test]
+fn test_typed_properties() {
+    let doc = crate::from_str(&fs::read_to_string("test/typed_properties.psml").unwrap()).unwrap();
+    let properties = match &doc.sections[0].content[0] {
+        SectionContent::PropertiesFragment(fragment) => &fragment.properties,
+        other => panic!("expected properties fragment, got {other:?}"),
+    };
+    let [due, snapshot, updated, summary, colour, tags] = &properties[..] else {
+        panic!("expected 6 properties, got {properties:?}");
+    };
+
+    let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+    assert_eq!(due.date_values().unwrap(), vec![date]);
+    assert_eq!(
+        snapshot.datetime_values().unwrap()[0].to_rfc3339(),
+        "2023-07-26T02:01:08+00:00"
+    );
+    assert_eq!(
+        updated
+            .datetime_values()
+            .unwrap()
+            .iter()
+            .map(|datetime| datetime.to_rfc3339())
+            .collect::<Vec<String>>(),
+        vec!["2024-03-01T09:30:00+10:00", "2024-03-02T10:00:00+00:00"]
+    );
+    assert!(matches!(
+        colour.date_values(),
+        Err(PsmlError::PropertyError { name, .. }) if name == "colour"
+    ));
+
+    assert_eq!(
+        summary.markup_values().unwrap(),
+        vec![vec![
+            CharacterStyle::Bold(crate::text::Bold::text("Important".to_string())),
+            CharacterStyle::Italic(crate::text::Italic::text("note".to_string())),
+        ]]
+    );
+    let parsed = Property::string(
+        "note".to_string(),
+        "Note".to_string(),
+        "<italic>Parsed</italic>".to_string(),
+    );
+    assert_eq!(
+        parsed.markup_values().unwrap(),
+        vec![vec![CharacterStyle::Italic(crate::text::Italic::text(
+            "Parsed".to_string()
+        ))]]
+    );
+    let escaped: Property = crate::xml::deserialize(
+        r#"<property name="co" value="AT&amp;T &lt;bold&gt;Inc&lt;/bold&gt;"/>"#,
+    )
+    .unwrap();
+    assert_eq!(
+        escaped.markup_values().unwrap(),
+        vec![vec![
+            CharacterStyle::Text("AT&T ".to_string()),
+            CharacterStyle::Bold(crate::text::Bold::text("Inc".to_string())),
+        ]]
+    );
+
+    assert_eq!(
+        colour.datatype,
+        Some(PropertyDatatype::Custom("colour".to_string()))
+    );
+    assert!(updated.check_multiple().is_ok());
+    assert!(tags.check_multiple().is_err());
+
+    let constructed = PropertiesFragment::new("2".to_string()).with_properties(vec![
+        Property::date("due".to_string(), "Due".to_string(), date),
+        Property::datetime(
+            "updated".to_string(),
+            "Updated".to_string(),
+            DateTime::parse_from_rfc3339("2024-03-01T09:30:00+10:00").unwrap(),
+        ),
+        Property::custom(
+            "colour".to_string(),
+            "Colour".to_string(),
+            "colour".to_string(),
+            "red".to_string(),
+        ),
+        Property::markup(
+            "summary".to_string(),
+            "Summary".to_string(),
+            vec![CharacterStyle::Bold(crate::text::Bold::text(
+                "Important".to_string(),
+            ))],
+        ),
+    ]);
+    let xml = quick_xml::se::to_string_with_root("properties-fragment", &constructed).unwrap();
+    assert_eq!(
+        xml,
+        "<properties-fragment id=\"2\">\
+         <property name=\"due\" title=\"Due\" datatype=\"date\" value=\"2024-03-01\"/>\
+         <property name=\"updated\" title=\"Updated\" datatype=\"datetime\" value=\"2024-03-01T09:30:00+10:00\"/>\
+         <property name=\"colour\" title=\"Colour\" datatype=\"colour\" value=\"red\"/>\
+         <property name=\"summary\" title=\"Summary\" datatype=\"markup\"><markup><bold>Important</bold></markup></property>\
+         </properties-fragment>"
+    );
+}
+
+#[test]
+fn test_xref_uriid() {
+    let xref = XRef::uriid("41265".to_string());
+    assert_eq!(xref.uriid.as_deref(), Some("41265"));
+    assert_eq!(xref.href, None);
+
+    let xml = quick_xml::se::to_string_with_root("xref", &xref).unwrap();
+    assert!(xml.starts_with(r#"<xref uriid="41265""#), "{xml}");
+    assert!(xml.ends_with("/>"), "{xml}");
+    assert_eq!(crate::xml::deserialize::<XRef>(&xml).unwrap(), xref);
+
+    let parsed: XRef = crate::xml::deserialize(
+        r#"<xref uriid="41265" display="document" frag="default" reverselink="true"/>"#,
+    )
+    .unwrap();
+    assert_eq!(parsed.uriid.as_deref(), Some("41265"));
+    assert_eq!(parsed.content, "");
+}
+
+#[test]
+fn test_derive_properties() {
+    #[derive(Debug, PartialEq, PsmlProperties)]
+    #[psml(crate = "crate")]
+    struct Node {
+        #[psml(title = "Name")]
+        name: String,
+        #[psml(name = "ipv4", title = "IP address", xref = "docid")]
+        ip: Option<String>,
+        #[psml(title = "Website", xref = "uriid")]
+        website: String,
+        #[psml(title = "Tag")]
+        tags: Vec<String>,
+        #[psml(title = "Ports", multiple)]
+        ports: Vec<u16>,
+        #[psml(title = "Snapshot")]
+        snapshot: NaiveDate,
+        #[psml(title = "Colour", datatype = "colour")]
+        colour: String,
+        #[psml(skip)]
+        cached: bool,
+    }
+
+    let node = Node {
+        name: "foobar".to_string(),
+        ip: Some("_nd_ipv4_192_168_13_12".to_string()),
+        website: "41265".to_string(),
+        tags: vec!["web".to_string(), "test".to_string()],
+        ports: vec![80, 443],
+        snapshot: NaiveDate::from_ymd_opt(2023, 8, 3).unwrap(),
+        colour: "red".to_string(),
+        cached: true,
+    };
+    let fragment = node.to_properties_fragment("node".to_string());
+    assert_eq!(
+        quick_xml::se::to_string_with_root("properties-fragment", &fragment).unwrap(),
+        "<properties-fragment id=\"node\">\
+         <property name=\"name\" title=\"Name\" value=\"foobar\"/>\
+         <property name=\"ipv4\" title=\"IP address\" datatype=\"xref\">\
+         <xref docid=\"_nd_ipv4_192_168_13_12\" display=\"document\" frag=\"default\" reverselink=\"true\"/>\
+         </property>\
+         <property name=\"website\" title=\"Website\" datatype=\"xref\">\
+         <xref uriid=\"41265\" display=\"document\" frag=\"default\" reverselink=\"true\"/>\
+         </property>\
+         <property name=\"tags\" title=\"Tag\" value=\"web\"/>\
+         <property name=\"tags\" title=\"Tag\" value=\"test\"/>\
+         <property name=\"ports\" title=\"Ports\" multiple=\"true\"><value>80</value><value>443</value></property>\
+         <property name=\"snapshot\" title=\"Snapshot\" datatype=\"date\" value=\"2023-08-03\"/>\
+         <property name=\"colour\" title=\"Colour\" datatype=\"colour\" value=\"red\"/>\
+         </properties-fragment>"
+    );
+
+    let xml = quick_xml::se::to_string_with_root("properties-fragment", &fragment).unwrap();
+    let parsed: PropertiesFragment = crate::xml::deserialize(&xml).unwrap();
+    assert_eq!(parsed, fragment);
+
+    let read = Node::from_properties_fragment(&parsed).unwrap();
+    assert_eq!(
+        read,
+        Node {
+            cached: false,
+            ..node
+        }
+    );
+
+    let missing = PropertiesFragment::new("empty".to_string());
+    assert!(matches!(
+        Node::from_properties_fragment(&missing),
+        Err(PsmlError::PropertyError { name, .. }) if name == "name"
+    ));
+
+    #[derive(Debug, PartialEq, PsmlProperties)]
+    #[psml(crate = "crate")]
+    struct Raw {
+        r#type: String,
+    }
+
+    let raw = Raw {
+        r#type: "server".to_string(),
+    };
+    let fragment = raw.to_properties_fragment("raw".to_string());
+    assert_eq!(fragment.properties[0].name, "type");
+    assert_eq!(Raw::from_properties_fragment(&fragment).unwrap(), raw);
+}
+
 #[test]
 fn test_table() {
     let doc: Document =
@@ -52,3 +286,1848 @@ fn test_table() {
 
     println!("{doc:#?}");
 }
+
+#[test]
+fn test_list() {
+    let doc: Document =
+        quick_xml::de::from_str(&fs::read_to_string("test/list.psml").unwrap()).unwrap();
+
+    let out = quick_xml::se::to_string(&doc).unwrap();
+    fs::write("test/out/list.psml", &out).unwrap();
+
+    let reparsed: Document = quick_xml::de::from_str(&out).unwrap();
+    assert_eq!(doc, reparsed);
+}
+
+#[test]
+fn test_inline() {
+    let xml = fs::read_to_string("test/inline.psml").unwrap();
+    let doc = crate::from_str(&xml).unwrap();
+
+    let out = quick_xml::se::to_string(&doc).unwrap();
+    fs::write("test/out/inline.psml", &out).unwrap();
+
+    // The fixture only has whitespace at the start of lines between block elements.
+    let expected: String = xml.lines().skip(1).map(str::trim).collect();
+    assert_eq!(out, expected);
+    assert_eq!(crate::from_str(&out).unwrap(), doc);
+}
+
+#[test]
+fn test_media_fragment() {
+    let xml = fs::read_to_string("test/media.psml").unwrap();
+    let doc = crate::from_str(&xml).unwrap();
+
+    let out = crate::to_string(&doc).unwrap();
+    fs::write("test/out/media.psml", &out).unwrap();
+    for line in xml
+        .lines()
+        .filter(|line| line.contains("<media-fragment id=\""))
+    {
+        if !line.contains("binary") {
+            assert!(out.contains(line.trim()), "missing {line} in {out}");
+        }
+    }
+    assert_eq!(crate::from_str(&out).unwrap(), doc);
+
+    let media: Vec<&MediaFragment> = doc.sections[0]
+        .content
+        .iter()
+        .filter_map(|content| match content {
+            SectionContent::Media(media) => Some(media),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(media[0].content, r#"{"name": "a < b", "items": [1, 2]}"#);
+    assert_eq!(media[0].encoding, MediaEncoding::CData);
+    assert!(media[1].content.starts_with("<svg"));
+    assert_eq!(media[1].encoding, MediaEncoding::Text);
+    assert!(media[2].content.starts_with("<svg"));
+    assert_eq!(media[2].encoding, MediaEncoding::Markup);
+    assert_eq!(media[3].decode_base64().unwrap(), b"psml media");
+
+    // Serde keeps the encoding without the XML layer.
+    let serde_out = quick_xml::se::to_string(&doc).unwrap();
+    assert_eq!(
+        quick_xml::de::from_str::<Document>(&serde_out).unwrap(),
+        doc
+    );
+}
+
+#[test]
+fn test_media_markup_errors() {
+    let markup = r#"<media-fragment id="m" mediatype="application/xml"><x>a</x></media-fragment>"#;
+    assert!(quick_xml::de::from_str::<MediaFragment>(markup).is_err());
+    assert_eq!(
+        crate::xml::deserialize::<MediaFragment>(markup)
+            .unwrap()
+            .encoding,
+        MediaEncoding::Markup
+    );
+
+    let mut doc = crate::from_str(&fs::read_to_string("test/media.psml").unwrap()).unwrap();
+    let SectionContent::Media(media) = &mut doc.sections[0].content[2] else {
+        panic!("expected a media fragment");
+    };
+    media.content = "<svg>".to_string();
+    assert!(matches!(
+        crate::to_string(&doc),
+        Err(PsmlError::FragmentError { id, .. }) if id == "inline-svg"
+    ));
+}
+
+#[test]
+fn test_media_encoding_in_content() {
+    let xml = "<document level=\"portable\"><section id=\"s\">\
+        <fragment id=\"f\"><para>\u{F8FF}&lt;script&gt;</para></fragment>\
+        <media-fragment id=\"m\" mediatype=\"text/plain\"><![CDATA[a]]]]><![CDATA[>b]]></media-fragment>\
+        </section></document>";
+    let doc = crate::from_str(xml).unwrap();
+
+    let out = crate::to_string(&doc).unwrap();
+    assert!(out.contains("<para>\u{F8FF}&lt;script&gt;</para>"), "{out}");
+    assert!(!out.contains("<script>"), "{out}");
+    assert!(out.contains("<![CDATA[a]]]]><![CDATA[>b]]>"), "{out}");
+    assert_eq!(crate::from_str(&out).unwrap(), doc);
+
+    let SectionContent::Media(media) = &doc.sections[0].content[1] else {
+        panic!("expected a media fragment");
+    };
+    assert_eq!(media.content, "a]]>b");
+    assert_eq!(media.encoding, MediaEncoding::CData);
+
+    // Content starting with a private-use character is still text.
+    let text = MediaFragment::new("t".to_string(), "text/plain".to_string())
+        .with_content("\u{F8FF}<b>".to_string());
+    assert_eq!(
+        quick_xml::se::to_string(&text).unwrap(),
+        "<media-fragment id=\"t\" mediatype=\"text/plain\">\u{F8FF}&lt;b&gt;</media-fragment>"
+    );
+}
+
+#[test]
+fn test_rich_table() {
+    let doc: Document =
+        quick_xml::de::from_str(&fs::read_to_string("test/rich_table.psml").unwrap()).unwrap();
+
+    let out = quick_xml::se::to_string(&doc).unwrap();
+    fs::write("test/out/rich_table.psml", &out).unwrap();
+
+    let reparsed: Document = quick_xml::de::from_str(&out).unwrap();
+    assert_eq!(doc, reparsed);
+
+    let table = Table::rich(
+        2,
+        vec![vec![
+            TableCell::text("Name".to_string()),
+            TableCell::new(vec![MixedContent::Para(Para::new(vec![
+                ParaContent::Text("Value".to_string()),
+            ]))]),
+        ]],
+        "Caption".to_string(),
+    );
+    assert_eq!(
+        quick_xml::se::to_string(&table).unwrap(),
+        "<Table><caption>Caption</caption><col/><col/><row><cell>Name</cell><cell><para>Value</para></cell></row></Table>"
+    );
+
+    // Tables without a caption are written without an empty caption element.
+    let uncaptioned = Table {
+        caption: None,
+        ..table
+    };
+    let xml = quick_xml::se::to_string_with_root("table", &uncaptioned).unwrap();
+    assert!(xml.starts_with("<table><col/>"), "{xml}");
+    assert_eq!(quick_xml::de::from_str::<Table>(&xml).unwrap(), uncaptioned);
+}
+
+#[test]
+fn test_toc() {
+    let doc = crate::from_str(&fs::read_to_string("test/toc.psml").unwrap())
+        .unwrap()
+        .with_toc();
+    let toc = doc.toc.as_ref().unwrap();
+    assert_eq!(toc.tree.title.as_deref(), Some("Handbook"));
+
+    let parts: Vec<(u8, &str, &str)> = toc
+        .flatten()
+        .into_iter()
+        .map(|part| (part.level, part.fragment.as_str(), part.title.as_str()))
+        .collect();
+    assert_eq!(
+        parts,
+        vec![
+            (1, "1", "Handbook"),
+            (2, "2", "Getting started"),
+            (3, "2", "Install"),
+            (3, "2", "Configure"),
+            (2, "3", "Appendix"),
+            (1, "4", "Reference"),
+        ]
+    );
+
+    let handbook = &toc.tree.parts[0];
+    assert_eq!(toc.tree.parts.len(), 2);
+    assert_eq!(handbook.parts.len(), 2);
+    assert_eq!(handbook.parts[0].parts[1].prefix.as_deref(), Some("1.2"));
+    assert_eq!(handbook.parts[1].kind, TocPartKind::XRef);
+    assert_eq!(handbook.parts[1].docid.as_deref(), Some("appendix"));
+
+    let out = crate::to_string(&doc).unwrap();
+    fs::write("test/out/toc.psml", &out).unwrap();
+    assert!(out.contains(
+        "<toc><toc-tree title=\"Handbook\"><toc-part level=\"1\" idx=\"1\" type=\"heading\" fragment=\"1\" title=\"Handbook\">"
+    ));
+    assert_eq!(crate::from_str(&out).unwrap().toc.as_ref(), Some(toc));
+
+    let events = PsmlReader::new(out.as_bytes())
+        .collect::<Result<Vec<PsmlEvent>, PsmlError>>()
+        .unwrap();
+    assert!(events.contains(&PsmlEvent::Toc(toc.clone())));
+    let mut writer = PsmlWriter::new(Vec::new()).unwrap();
+    for event in &events {
+        writer.write_event(event).unwrap();
+    }
+    let streamed = String::from_utf8(writer.finish().unwrap()).unwrap();
+    assert_eq!(crate::from_str(&streamed).unwrap(), doc);
+
+    let mut moved = doc.clone();
+    let count = xrefs::rewrite([&mut moved], |target| {
+        (target.docid == Some("appendix")).then(|| NewTarget {
+            docid: Some("annex".to_string()),
+            ..target.into()
+        })
+    });
+    assert_eq!(count, 1);
+    let moved_toc = moved.toc.as_ref().unwrap();
+    assert_eq!(
+        moved_toc.tree.parts[0].parts[1].docid.as_deref(),
+        Some("annex")
+    );
+}
+
+#[test]
+fn test_diff() {
+    let old = crate::from_str(&fs::read_to_string("test/toc.psml").unwrap()).unwrap();
+    let mut new = old.clone();
+    assert_eq!(diff::diff(&old, &new).unwrap(), vec![]);
+
+    let content = &mut new.get_mut_section("content").unwrap().content;
+    let moved = content.remove(2);
+    content.remove(1);
+    match &mut content[0] {
+        SectionContent::Fragment(fragment) => {
+            fragment.content[1] = FragmentContent::Para(Para::new(vec![
+                ParaContent::Text("Now ".to_string()),
+                ParaContent::Bold(crate::text::Bold::text("part".to_string())),
+                ParaContent::Text(" of the contents.".to_string()),
+            ]))
+        }
+        other => panic!("expected fragment, got {other:?}"),
+    }
+    content.push(SectionContent::Fragment(Fragment::new("5".to_string())));
+    new.sections.insert(0, Section::new("intro".to_string()));
+    new.sections.swap(1, 2);
+    new.sections[2].content.push(moved);
+
+    let changes = diff::diff(&old, &new).unwrap();
+    let position = |section: &str, index| Position {
+        section: section.to_string(),
+        index,
+    };
+    assert_eq!(
+        changes
+            .iter()
+            .filter(|change| !matches!(change, Change::FragmentModified { .. }))
+            .cloned()
+            .collect::<Vec<Change>>(),
+        vec![
+            Change::SectionAdded {
+                section: "intro".to_string(),
+                index: 0
+            },
+            Change::FragmentAdded {
+                fragment: "5".to_string(),
+                to: position("content", 1)
+            },
+            Change::SectionMoved {
+                section: "title".to_string(),
+                from: 0,
+                to: 2
+            },
+            Change::FragmentMoved {
+                fragment: "4".to_string(),
+                from: position("content", 2),
+                to: position("title", 1)
+            },
+            Change::FragmentRemoved {
+                fragment: "3".to_string(),
+                from: position("content", 1)
+            },
+        ]
+    );
+
+    match &changes[1] {
+        Change::FragmentModified {
+            fragment, edits, ..
+        } => {
+            assert_eq!(fragment, "2");
+            let changed: Vec<&Edit> = edits
+                .iter()
+                .filter(|edit| !matches!(edit, Edit::Equal(_)))
+                .collect();
+            assert_eq!(
+                changed,
+                vec![
+                    &Edit::Delete("Not".to_string()),
+                    &Edit::Insert("Now".to_string()),
+                    &Edit::Insert("<bold>".to_string()),
+                    &Edit::Insert("</bold>".to_string()),
+                ]
+            );
+        }
+        other => panic!("expected modified fragment, got {other:?}"),
+    }
+
+    let updates = diff::fragment_updates(&changes, &new).unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].fragment, "2");
+    assert!(updates[0].psml.starts_with("<fragment id=\"2\">"));
+}
+
+#[test]
+fn test_inline_diff_long() {
+    // Every third word differs, so little of the input is a common prefix or suffix.
+    let words = |changed: &str| -> String {
+        (0..1200)
+            .map(|i| match i % 3 {
+                0 => format!("{changed}{i}"),
+                _ => format!("word{i}"),
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+    let (old, new) = (words("old"), words("new"));
+    let edits = diff::inline_diff(&old, &new);
+
+    let side = |keep: fn(&Edit) -> Option<&String>| -> String {
+        edits.iter().filter_map(keep).map(String::as_str).collect()
+    };
+    let equal = side(|edit| match edit {
+        Edit::Equal(text) => Some(text),
+        _ => None,
+    });
+    assert_eq!(
+        side(|edit| match edit {
+            Edit::Equal(text) | Edit::Delete(text) => Some(text),
+            Edit::Insert(_) => None,
+        }),
+        old
+    );
+    assert_eq!(
+        side(|edit| match edit {
+            Edit::Equal(text) | Edit::Insert(text) => Some(text),
+            Edit::Delete(_) => None,
+        }),
+        new
+    );
+    let changed: usize = (0..1200).step_by(3).map(|i| format!("old{i}").len()).sum();
+    assert_eq!(equal.len(), old.len() - changed);
+}
+
+#[test]
+fn test_merge() {
+    fn fragment(doc: &mut Document, index: usize) -> &mut SectionContent {
+        &mut doc.get_mut_section("content").unwrap().content[index]
+    }
+    fn set_para(content: &mut SectionContent, text: &str) {
+        match content {
+            SectionContent::Fragment(fragment) => {
+                fragment.content = vec![FragmentContent::Para(Para::new(vec![ParaContent::Text(
+                    text.to_string(),
+                )]))]
+            }
+            other => panic!("expected fragment, got {other:?}"),
+        }
+    }
+    fn set_property(doc: &mut Document, name: &str, value: &str) {
+        match &mut doc.get_mut_section("details").unwrap().content[0] {
+            SectionContent::PropertiesFragment(fragment) => {
+                let property = fragment.properties.iter_mut().find(|p| p.name == name);
+                property.unwrap().attr_value = Some(value.to_string());
+            }
+            other => panic!("expected properties fragment, got {other:?}"),
+        }
+    }
+
+    let base = crate::from_str(&fs::read_to_string("test/merge.psml").unwrap()).unwrap();
+    let mut ours = base.clone();
+    let mut theirs = base.clone();
+
+    set_property(&mut ours, "owner", "Bob");
+    set_property(&mut ours, "version", "2");
+    set_property(&mut theirs, "status", "final");
+    set_property(&mut theirs, "version", "3");
+    set_para(fragment(&mut ours, 1), "Edited by us.");
+    set_para(fragment(&mut theirs, 1), "Edited by them.");
+    set_para(fragment(&mut theirs, 0), "Shared introduction, improved.");
+    ours.get_mut_section("content").unwrap().content.remove(2);
+    theirs
+        .get_mut_section("content")
+        .unwrap()
+        .add_fragment(Fragments::Fragment(Fragment::new("5".to_string())));
+
+    let options = MergeOptions { compare: true };
+    let merge = merge::merge(&base, &ours, &theirs, &options).unwrap();
+
+    let mut expected = ours.clone();
+    set_property(&mut expected, "status", "final");
+    set_para(fragment(&mut expected, 0), "Shared introduction, improved.");
+    expected
+        .get_mut_section("content")
+        .unwrap()
+        .add_fragment(Fragments::Fragment(Fragment::new("5".to_string())));
+    assert_eq!(merge.document, expected);
+
+    assert_eq!(merge.conflicts.len(), 2);
+    match &merge.conflicts[0] {
+        Conflict::Property {
+            fragment,
+            name,
+            ours,
+            theirs,
+            compare,
+            ..
+        } => {
+            assert_eq!((fragment.as_str(), name.as_str()), ("1", "version"));
+            assert_eq!(ours.as_ref().unwrap().attr_value.as_deref(), Some("2"));
+            assert_eq!(theirs.as_ref().unwrap().attr_value.as_deref(), Some("3"));
+            assert!(compare
+                .as_ref()
+                .unwrap()
+                .contains(" value=\"2\" dfx:delete=\"true\"/>"));
+        }
+        other => panic!("expected property conflict, got {other:?}"),
+    }
+    match &merge.conflicts[1] {
+        Conflict::Fragment {
+            fragment, compare, ..
+        } => {
+            assert_eq!(fragment, "3");
+            assert_eq!(
+                compare.as_deref(),
+                Some(
+                    "<fragment xmlns:dfx=\"http://www.topologi.com/2005/Diff-X\" id=\"3\">\
+                     <para>Edited by <dfx:del>us.</dfx:del><dfx:ins>them.</dfx:ins></para></fragment>"
+                )
+            );
+        }
+        other => panic!("expected fragment conflict, got {other:?}"),
+    }
+
+    let clean = merge::merge(&base, &ours, &base, &Default::default()).unwrap();
+    assert_eq!(clean.document, ours);
+    assert!(clean.conflicts.is_empty());
+}
+
+#[test]
+fn test_compare_markup_changed_elements() {
+    let well_formed = |xml: &str| {
+        let mut reader = quick_xml::Reader::from_str(xml);
+        loop {
+            match reader.read_event() {
+                Ok(quick_xml::events::Event::Eof) => break,
+                Ok(_) => {}
+                Err(err) => panic!("{err} in {xml}"),
+            }
+        }
+    };
+
+    let renamed = merge::compare_markup("<para>a</para>", "<heading level=\"1\">a</heading>");
+    well_formed(&renamed);
+    assert_eq!(
+        renamed,
+        "<para xmlns:dfx=\"http://www.topologi.com/2005/Diff-X\" dfx:delete=\"true\">\
+         <dfx:del>a</dfx:del></para>\
+         <heading xmlns:dfx=\"http://www.topologi.com/2005/Diff-X\" level=\"1\" dfx:insert=\"true\">\
+         <dfx:ins>a</dfx:ins></heading>"
+    );
+
+    let restyled = merge::compare_markup(
+        "<para>x <bold>y</bold> z</para>",
+        "<para>x <italic>y</italic> z</para>",
+    );
+    well_formed(&restyled);
+    assert_eq!(
+        restyled,
+        "<para xmlns:dfx=\"http://www.topologi.com/2005/Diff-X\">x \
+         <bold dfx:delete=\"true\"><dfx:del>y</dfx:del></bold>\
+         <italic dfx:insert=\"true\"><dfx:ins>y</dfx:ins></italic> z</para>"
+    );
+}
+
+#[test]
+fn test_merge_identical() {
+    // test/document.psml repeats property names in its properties fragments.
+    let doc = crate::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+    let merge = merge::merge(&doc, &doc, &doc, &MergeOptions::default()).unwrap();
+    assert_eq!(merge.document, doc);
+    assert!(merge.conflicts.is_empty());
+}
+
+#[test]
+fn test_merge_moved() {
+    let doc = |sections: [&str; 3]| {
+        let sections: String = sections
+            .iter()
+            .enumerate()
+            .map(|(i, content)| format!("<section id=\"s{}\">{content}</section>", i + 1))
+            .collect();
+        crate::from_str(&format!(
+            "<document level=\"portable\">{sections}</document>"
+        ))
+        .unwrap()
+    };
+    let (f1, f2) = (r#"<fragment id="f1"/>"#, r#"<fragment id="f2"/>"#);
+    let base = doc([f1, f2, ""]);
+
+    // Moved by them only.
+    let theirs = doc(["", &format!("{f2}{f1}"), ""]);
+    let merge = merge::merge(&base, &base, &theirs, &Default::default()).unwrap();
+    assert_eq!(merge.document, theirs);
+    assert!(merge.conflicts.is_empty());
+
+    // Moved to different sections by each side.
+    let ours = doc(["", &format!("{f2}{f1}"), ""]);
+    let theirs = doc(["", f2, f1]);
+    let merge = merge::merge(&base, &ours, &theirs, &Default::default()).unwrap();
+    assert_eq!(merge.document, ours);
+    assert_eq!(
+        merge.conflicts,
+        vec![Conflict::Move {
+            fragment: "f1".to_string(),
+            ours: "s2".to_string(),
+            theirs: "s3".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_lossless() {
+    let xml = fs::read_to_string("test/unknown.psml").unwrap();
+    assert!(quick_xml::de::from_str::<Document>(&xml).is_err());
+
+    let doc = lossless::from_str(&xml).unwrap();
+    let out = lossless::to_string(&doc).unwrap();
+    fs::write("test/out/unknown.psml", &out).unwrap();
+
+    assert_eq!(
+        out,
+        concat!(
+            r#"<document level="portable" schemaversion="1.4" status="Initiated"><metadata>"#,
+            "\n\t\t<properties>",
+            "\n\t\t\t",
+            r#"<property name="template_version" title="Template version" value="1.2"/>"#,
+            "\n\t\t</properties>\n\t</metadata>",
+            r#"<section id="content"><fragment id="intro" custom="yes">"#,
+            r#"<para>Before <math xmlns="http://www.w3.org/1998/Math/MathML"><mi>x</mi></math> after</para>"#,
+            r#"<toc/><para>Second <bold>para</bold></para>"#,
+            r#"<diagram kind="flow"><!-- generated --><![CDATA[a -> b]]></diagram><note>one</note></fragment>"#,
+            r#"<media-fragment id="svg" mediatype="image/svg+xml">"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><rect width="10" height="10"/></svg>"#,
+            "</media-fragment></section></document>"
+        )
+    );
+
+    let mixed = r#"<document level="portable"><section id="s"><fragment id="1"><para><x/> <bold>a</bold> b <y/><z/> c <w/></para></fragment></section></document>"#;
+    let doc_mixed = lossless::from_str(mixed).unwrap();
+    assert_eq!(lossless::to_string(&doc_mixed).unwrap(), mixed);
+
+    let dropped = concat!(
+        r#"<document level="portable"><section id="s"><!-- first -->"#,
+        r#"<fragment id="1"><!--a--><para>a <!-- b --> c<bold>d</bold><!--e--></para>"#,
+        r#"<table><row>loose <!--f--><x/><cell>g</cell></row></table></fragment>"#,
+        r#"<fragment id="2">text<!--h--><para>i</para></fragment></section>"#,
+        r#"<section id="t">loose<properties-fragment id="3"><property name="p">j<value>k</value></property>"#,
+        r#"</properties-fragment></section></document>"#
+    );
+    let doc_dropped = lossless::from_str(dropped).unwrap();
+    assert_eq!(lossless::to_string(&doc_dropped).unwrap(), dropped);
+
+    assert_eq!(lossless::from_str(&out).unwrap(), doc);
+
+    let full = lossless::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+    let out = lossless::to_string(&full).unwrap();
+    assert!(out.contains(r#"<reversexref uriid="17257""#));
+    assert!(out.contains(r#"<locfoobarr id="119333" fragment="domains"/>"#));
+}
+
+#[test]
+fn test_read_write() {
+    let doc = crate::from_reader(fs::File::open("test/fragment.psml").unwrap()).unwrap();
+    let out = crate::to_string(&doc).unwrap();
+    assert!(out.starts_with(r#"<?xml version="1.0" encoding="utf-8"?><document"#));
+    assert_eq!(crate::from_str(&out).unwrap(), doc);
+
+    let indented = crate::to_string_with_options(
+        &doc,
+        &WriteOptions {
+            declaration: false,
+            indent: Some((' ', 2)),
+        },
+    )
+    .unwrap();
+    assert!(indented.starts_with("<document"));
+    assert!(indented.contains("\n  <section"));
+
+    let mut written = Vec::new();
+    crate::to_writer(&mut written, &doc).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(), out);
+}
+
+#[test]
+fn test_parse_error() {
+    let xml = r#"<document level="portable">
+    <section id="body">
+        <fragment id="intro">
+            <heading level="one">Title</heading>
+        </fragment>
+    </section>
+</document>"#;
+
+    match crate::from_str(xml) {
+        Err(PsmlError::ParseError {
+            line, column, path, ..
+        }) => {
+            assert_eq!(line, 4);
+            assert!(column > 1);
+            assert_eq!(
+                path,
+                "/document/section[@id='body']/fragment[@id='intro']/heading"
+            );
+        }
+        other => panic!("expected parse error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_parse_whitespace() {
+    let xml = r#"<document level="portable"><section id="body"><fragment id="1">
+    <para>Hello <bold>big</bold> world</para>
+    <para><bold>a</bold> <italic>b</italic></para>
+    <table><row><cell><bold>a</bold> <italic>b</italic></cell></row></table>
+    <list><item><bold>a</bold> <italic>b</italic></item></list>
+</fragment></section></document>"#;
+    let doc = crate::from_str(xml).unwrap();
+    let SectionContent::Fragment(fragment) = &doc.sections[0].content[0] else {
+        panic!("expected fragment");
+    };
+    assert_eq!(fragment.content.len(), 4);
+    let FragmentContent::Table(table) = &fragment.content[2] else {
+        panic!("expected table");
+    };
+    let TableRowContent::Cell(cell) = &table.rows[0].cells[0] else {
+        panic!("expected cell");
+    };
+    assert_eq!(cell.content[1], MixedContent::Text(" ".to_string()));
+    let FragmentContent::List(list) = &fragment.content[3] else {
+        panic!("expected list");
+    };
+    assert_eq!(
+        list.items[0].content[1],
+        MixedContent::Text(" ".to_string())
+    );
+    let FragmentContent::Para(para) = &fragment.content[0] else {
+        panic!("expected para");
+    };
+    assert_eq!(para.content[0], ParaContent::Text("Hello ".to_string()));
+    assert_eq!(para.content[2], ParaContent::Text(" world".to_string()));
+
+    assert_eq!(fragment.plain_text(), "Hello big world\na b\na b\na b");
+    assert_eq!(
+        markdown::from_content(&fragment.content, &Default::default()),
+        "Hello **big** world\n\n**a** *b*\n\n|  |\n| --- |\n| **a** *b* |\n\n- **a** *b*\n"
+    );
+    assert_eq!(
+        crate::from_str(&crate::to_string(&doc).unwrap()).unwrap(),
+        doc
+    );
+}
+
+#[test]
+fn test_stream() {
+    let xml = fs::read_to_string("test/document.psml").unwrap();
+    let expected = crate::from_str(&xml).unwrap();
+
+    let events = PsmlReader::new(xml.as_bytes())
+        .collect::<Result<Vec<PsmlEvent>, PsmlError>>()
+        .unwrap();
+    assert_eq!(
+        events
+            .iter()
+            .filter(|event| matches!(event, PsmlEvent::SectionStart(_)))
+            .count(),
+        expected.sections.len()
+    );
+
+    let mut writer = PsmlWriter::new(Vec::new()).unwrap();
+    for event in &events {
+        writer.write_event(event).unwrap();
+    }
+    let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+    assert_eq!(crate::from_str(&out).unwrap(), expected);
+
+    let invalid = "<document level=\"portable\">\n<section id=\"body\">\n<fragment id=\"a\">\n<heading level=\"one\">Title</heading>\n</fragment>\n</section>\n</document>";
+    let result = PsmlReader::new(invalid.as_bytes()).collect::<Result<Vec<PsmlEvent>, PsmlError>>();
+    match result {
+        Err(PsmlError::ParseError { line, path, .. }) => {
+            assert_eq!(line, 4);
+            assert_eq!(
+                path,
+                "/document/section[@id='body']/fragment[@id='a']/heading"
+            );
+        }
+        other => panic!("expected parse error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_visitor() {
+    struct XRefCollector<'a> {
+        xrefs: Vec<&'a XRef>,
+        paras: usize,
+    }
+
+    impl<'a> Visitor<'a> for XRefCollector<'a> {
+        fn visit_xref(&mut self, node: &'a XRef) {
+            self.xrefs.push(node);
+        }
+
+        fn visit_para(&mut self, node: &'a Para) {
+            self.paras += 1;
+            visit::walk_para(self, node);
+        }
+    }
+
+    struct HrefRewriter;
+
+    impl VisitorMut for HrefRewriter {
+        fn visit_xref(&mut self, node: &mut XRef) {
+            node.href = node
+                .href
+                .as_ref()
+                .map(|href| href.replace("/ps/netdox/", "/ps/moved/"));
+        }
+    }
+
+    let mut doc = crate::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+
+    let mut collector = XRefCollector {
+        xrefs: vec![],
+        paras: 0,
+    };
+    collector.visit_document(&doc);
+    assert_eq!(collector.xrefs.len(), 6);
+    assert_eq!(collector.paras, 2);
+
+    HrefRewriter.visit_document(&mut doc);
+    let mut collector = XRefCollector {
+        xrefs: vec![],
+        paras: 0,
+    };
+    collector.visit_document(&doc);
+    assert!(collector.xrefs.iter().all(|xref| xref
+        .href
+        .as_ref()
+        .unwrap()
+        .starts_with("/ps/moved/")));
+
+    struct BlockLabels<'a>(Vec<Option<&'a str>>);
+
+    impl<'a> Visitor<'a> for BlockLabels<'a> {
+        fn visit_block(&mut self, label: Option<&'a str>, child: &'a [FragmentContent]) {
+            self.0.push(label);
+            visit::walk_block(self, label, child);
+        }
+    }
+
+    struct BlockRelabeller;
+
+    impl VisitorMut for BlockRelabeller {
+        fn visit_block(&mut self, label: &mut Option<String>, _child: &mut Vec<FragmentContent>) {
+            *label = Some("note".to_string());
+        }
+    }
+
+    let mut table = crate::from_str(&fs::read_to_string("test/rich_table.psml").unwrap()).unwrap();
+    let mut labels = BlockLabels(vec![]);
+    labels.visit_document(&table);
+    assert_eq!(labels.0, vec![Some("warning")]);
+
+    BlockRelabeller.visit_document(&mut table);
+    let mut labels = BlockLabels(vec![]);
+    labels.visit_document(&table);
+    assert_eq!(labels.0, vec![Some("note")]);
+}
+
+#[test]
+fn test_plain_text() {
+    let doc = crate::from_str(&fs::read_to_string("test/rich_table.psml").unwrap()).unwrap();
+    let text = doc.plain_text();
+    let rows: Vec<&str> = text.lines().collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0], "Parameter\tNotes");
+    assert!(rows[1].starts_with("timeout\tSeconds to wait. Defaults to"));
+    assert_eq!(rows[2], "\tDo not change. timeout = 30");
+
+    let doc = crate::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+    assert_eq!(doc.plain_text(), "Node\nfoobar – testing\n—");
+
+    let options = TextOptions {
+        properties: true,
+        sections: Some(vec!["body".to_string()]),
+    };
+    let text = doc.plain_text_with(&options);
+    assert!(text.starts_with("Description: FOOBAR backup server\nUUID: "));
+    assert!(text.contains("Host Node: Cisco 2\n"));
+    assert!(!text.contains("Node\nfoobar"));
+}
+
+#[test]
+fn test_markdown() {
+    let options = markdown::MarkdownOptions {
+        split: markdown::FragmentSplit::Heading(2),
+        section_level: Some(1),
+    };
+    let doc = markdown::to_document(&fs::read_to_string("test/convert.md").unwrap(), &options);
+
+    let out = crate::to_string(&doc).unwrap();
+    fs::write("test/out/convert.psml", &out).unwrap();
+
+    let sections: Vec<(&str, Option<&str>, usize)> = doc
+        .sections
+        .iter()
+        .map(|s| (s.id.as_str(), s.title.as_deref(), s.content.len()))
+        .collect();
+    assert_eq!(
+        sections,
+        vec![
+            ("section-1", None, 1),
+            ("section-2", Some("Installation"), 2),
+            ("section-3", Some("Usage"), 1),
+        ]
+    );
+
+    let content = markdown::to_content("1. one\n2. two\n\n---\n\n3) three");
+    assert_eq!(content.len(), 2);
+    match &content[1] {
+        FragmentContent::NumberedList(nlist) => assert_eq!(nlist.start, Some(3)),
+        other => panic!("expected nlist, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_html_import() {
+    let import = html_import::to_content(&fs::read_to_string("test/legacy.html").unwrap());
+
+    let fragment = Fragment::new("1".to_string()).with_content(import.content.clone());
+    fs::write(
+        "test/out/legacy.psml",
+        quick_xml::se::to_string_with_root("fragment", &fragment).unwrap(),
+    )
+    .unwrap();
+
+    let losses: Vec<(&str, &html_import::LossKind)> = import
+        .losses
+        .iter()
+        .map(|loss| (loss.path.as_str(), &loss.kind))
+        .collect();
+    assert_eq!(
+        losses,
+        vec![
+            (
+                "/p",
+                &html_import::LossKind::Attribute {
+                    name: "style".to_string()
+                }
+            ),
+            (
+                "/p/font",
+                &html_import::LossKind::Labelled {
+                    label: "font".to_string()
+                }
+            ),
+            (
+                "/p/font",
+                &html_import::LossKind::Attribute {
+                    name: "color".to_string()
+                }
+            ),
+            ("/p/a", &html_import::LossKind::Flattened),
+            (
+                "/div",
+                &html_import::LossKind::Labelled {
+                    label: "note".to_string()
+                }
+            ),
+            ("/script", &html_import::LossKind::Dropped),
+        ]
+    );
+
+    let attrs = html_import::to_content(
+        "<h2 id=\"a\" class=\"title\" style=\"color: red\">Title</h2>\
+         <p><b style=\"color: red\">bold</b> <code class=\"rust\">code</code><br clear=\"all\"></p>",
+    );
+    let losses: Vec<String> = attrs
+        .losses
+        .iter()
+        .map(|loss| match &loss.kind {
+            html_import::LossKind::Attribute { name } => format!("{}@{name}", loss.path),
+            kind => panic!("expected attribute loss, got {kind:?}"),
+        })
+        .collect();
+    assert_eq!(
+        losses,
+        vec![
+            "/h2@class",
+            "/h2@style",
+            "/p/b@style",
+            "/p/code@class",
+            "/p/br@clear"
+        ]
+    );
+
+    let anchors = html_import::to_content(
+        "<p><a href=\"https://example.com\" title=\"Example\">a</a> \
+         <a href=\"other.html\" title=\"Other\">b</a> <a name=\"n\" id=\"i\">c</a> <a id=\"j\">d</a></p>",
+    );
+    assert_eq!(
+        anchors.losses,
+        vec![
+            html_import::Loss {
+                path: "/p/a".to_string(),
+                kind: html_import::LossKind::Attribute {
+                    name: "title".to_string()
+                }
+            },
+            html_import::Loss {
+                path: "/p/a".to_string(),
+                kind: html_import::LossKind::Attribute {
+                    name: "id".to_string()
+                }
+            },
+        ]
+    );
+
+    let classes = html_import::to_content(
+        "<aside class=\"md:flex\">a</aside><p><span class=\"w-1/2 half\">b</span></p>",
+    );
+    assert_eq!(
+        classes.losses,
+        vec![
+            html_import::Loss {
+                path: "/aside".to_string(),
+                kind: html_import::LossKind::Labelled {
+                    label: "aside".to_string()
+                }
+            },
+            html_import::Loss {
+                path: "/aside".to_string(),
+                kind: html_import::LossKind::Attribute {
+                    name: "class".to_string()
+                }
+            },
+            html_import::Loss {
+                path: "/p/span".to_string(),
+                kind: html_import::LossKind::Labelled {
+                    label: "span".to_string()
+                }
+            },
+            html_import::Loss {
+                path: "/p/span".to_string(),
+                kind: html_import::LossKind::Attribute {
+                    name: "class".to_string()
+                }
+            },
+        ]
+    );
+    assert!(matches!(
+        &classes.content[0],
+        FragmentContent::Block { label: Some(label), .. } if label == "aside"
+    ));
+
+    let nested = html_import::to_content(
+        "<table><tr><td><h3>a</h3>b</td></tr></table><ul><li><table></table></li></ul>",
+    );
+    let losses: Vec<(&str, &html_import::LossKind)> = nested
+        .losses
+        .iter()
+        .map(|loss| (loss.path.as_str(), &loss.kind))
+        .collect();
+    assert_eq!(
+        losses,
+        vec![
+            ("/table/tbody/tr/td", &html_import::LossKind::Flattened),
+            ("/ul/li", &html_import::LossKind::Flattened),
+        ]
+    );
+
+    let spaced = html_import::to_content(
+        "<ul><li><b>a</b> <i>b</i></li></ul>\
+         <table><tr><td><b>a</b> <i>b</i></td><td> <p>c</p> <p>d</p> </td></tr></table>",
+    );
+    assert!(spaced.losses.is_empty());
+    let expected = vec![
+        MixedContent::Bold(Bold::new(vec![CharacterStyle::Text("a".to_string())])),
+        MixedContent::Text(" ".to_string()),
+        MixedContent::Italic(Italic::new(vec![CharacterStyle::Text("b".to_string())])),
+    ];
+    let FragmentContent::List(list) = &spaced.content[0] else {
+        panic!("expected list, got {:?}", spaced.content[0]);
+    };
+    assert_eq!(list.items[0].content, expected);
+    let FragmentContent::Table(table) = &spaced.content[1] else {
+        panic!("expected table, got {:?}", spaced.content[1]);
+    };
+    assert_eq!(table.rows[0].cells[0].cell().content, expected);
+    assert!(table.rows[0].cells[1]
+        .cell()
+        .content
+        .iter()
+        .all(|node| matches!(node, MixedContent::Para(_))));
+
+    let markdown = markdown::from_content(&import.content, &Default::default());
+    assert_eq!(
+        markdown.lines().take(3).collect::<Vec<&str>>(),
+        vec![
+            "# <a id=\"intro\"></a>Legacy *page*",
+            "",
+            "Some **bold** and coloured text with a [relative link](other.html#part-2) and an [external one](https://example.com).",
+        ]
+    );
+
+    match &import.content[0] {
+        FragmentContent::Heading(heading) => assert!(matches!(
+            &heading.content[0],
+            CharacterStyle::Anchor(anchor) if anchor.name == "intro"
+        )),
+        other => panic!("expected heading, got {other:?}"),
+    }
+    match &import.content[3] {
+        FragmentContent::NumberedList(nlist) => {
+            assert_eq!(nlist.start, Some(3));
+            assert_eq!(nlist.items.len(), 2);
+        }
+        other => panic!("expected nlist, got {other:?}"),
+    }
+    match &import.content[4] {
+        FragmentContent::Table(table) => {
+            assert_eq!(table.cols.len(), 2);
+            assert_eq!(table.rows.len(), 3);
+            assert_eq!(table.rows[2].cells[0].cell().colspan, Some(2));
+        }
+        other => panic!("expected table, got {other:?}"),
+    }
+    assert_eq!(
+        import.content[5],
+        FragmentContent::Preformat {
+            child: vec![CharacterStyle::Text("fn main() {}\n".to_string())]
+        }
+    );
+}
+
+#[test]
+fn test_render_markdown() {
+    let doc = markdown::to_document(
+        &fs::read_to_string("test/convert.md").unwrap(),
+        &markdown::MarkdownOptions::default(),
+    );
+    let options = markdown::RenderOptions::default();
+    let out = markdown::from_document(&doc, &options);
+    fs::write("test/out/convert.md", &out).unwrap();
+    assert_eq!(markdown::to_document(&out, &Default::default()), doc);
+
+    let doc = crate::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+    let out = markdown::from_document(&doc, &options);
+    assert!(out.starts_with("---\nname: \"foobar – testing\"\n"));
+    assert!(out.contains("\ntag:\n  - \"other-app\"\n  - \"test\"\n"));
+    assert!(out.contains("\n---\n\n## Node\n\n# foobar – testing\n"));
+
+    let options = markdown::RenderOptions {
+        properties: markdown::PropertiesStyle::Table,
+        ..Default::default()
+    };
+    let out = markdown::from_document(&doc, &options);
+    assert!(
+        out.contains("| Property | Value |\n| --- | --- |\n| Domain | foobar-bkp.domain.com.au |")
+    );
+
+    let resolver = |target: &XRefTarget| target.docid.map(|docid| format!("/docs/{docid}.md"));
+    let options = markdown::RenderOptions {
+        resolver: &resolver,
+        ..Default::default()
+    };
+    let content = vec![
+        FragmentContent::Para(Para::new(vec![
+            ParaContent::Text("See ".to_string()),
+            ParaContent::XRef(
+                XRef::docid("guide".to_string()).with_content("the guide".to_string()),
+            ),
+        ])),
+        FragmentContent::Para(Para {
+            numbered: Some(true),
+            indent: Some(1),
+            ..Para::new(vec![ParaContent::Text("Step".to_string())])
+        }),
+        FragmentContent::Para(Para {
+            prefix: Some("1.2".to_string()),
+            ..Para::new(vec![ParaContent::Text("Scope".to_string())])
+        }),
+    ];
+    assert_eq!(
+        markdown::from_content(&content, &options),
+        "See [the guide](/docs/guide.md)\n\n   1. Step\n\n1.2 Scope\n"
+    );
+}
+
+#[test]
+fn test_html() {
+    let doc = crate::from_str(&fs::read_to_string("test/rich_table.psml").unwrap()).unwrap();
+    let out = html::to_html(&doc);
+    fs::write("test/out/rich_table.html", &out).unwrap();
+    assert!(out.starts_with(
+        "<article><section id=\"section-content\"><div id=\"fragment-spec\" class=\"fragment\"><table>"
+    ));
+    assert!(out.contains(
+        "<thead><tr><th>Parameter</th><th style=\"text-align:center\">Notes</th></tr></thead>"
+    ));
+    assert!(out.contains("<td><img src=\"diagram.png\" alt=\"Diagram\"></td>"));
+
+    fn preview(target: &XRefTarget) -> Option<String> {
+        target.docid.map(|docid| format!("/preview/{docid}.html"))
+    }
+
+    struct Template;
+    impl html::HtmlTemplate for Template {
+        fn resolver(&self) -> &dyn XRefResolver {
+            &preview
+        }
+
+        fn render_heading(&mut self, out: &mut String, node: &Heading) {
+            out.push_str("<header>");
+            html::write_heading(self, out, node);
+            out.push_str("</header>");
+        }
+    }
+
+    let mut cell = TableCell::text("A & B".to_string());
+    cell.colspan = Some(2);
+    let doc =
+        Document {
+            sections: vec![Section::new("body".to_string()).with_fragments(vec![
+                Fragments::Fragment(Fragment::new("1".to_string()).with_content(vec![
+                    FragmentContent::Heading(Heading {
+                        level: 2,
+                        content: vec![CharacterStyle::XRef(Box::new(
+                            XRef::docid("guide".to_string()).with_content("Guide".to_string()),
+                        ))],
+                    }),
+                    FragmentContent::Table(Table::rich(2, vec![vec![cell]], String::new())),
+                ])),
+            ])],
+            ..Default::default()
+        };
+    assert_eq!(
+        html::to_html_with(&doc, &mut Template),
+        "<article><section id=\"section-body\"><div id=\"fragment-1\" class=\"fragment\">\
+         <header><h2><a class=\"xref\" href=\"/preview/guide.html\">Guide</a></h2></header>\
+         <table><tbody><tr><td colspan=\"2\">A &amp; B</td></tr></tbody></table>\
+         </div></section></article>"
+    );
+
+    // Sections and fragments in test/document.psml share IDs, such as "title".
+    let doc = crate::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+    let out = html::to_html(&doc);
+    let ids: Vec<&str> = out
+        .split(" id=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .collect();
+    assert!(ids.contains(&"section-title") && ids.contains(&"fragment-title"));
+    assert_eq!(
+        ids.iter().collect::<std::collections::HashSet<_>>().len(),
+        ids.len()
+    );
+
+    let local = XRef {
+        frag_id: "title".to_string(),
+        ..XRef::href(String::new())
+    };
+    let mut out = String::new();
+    html::write_xref(&mut html::DefaultTemplate::default(), &mut out, &local);
+    assert_eq!(out, "<a class=\"xref\" href=\"#fragment-title\"></a>");
+
+    // Xrefs with only a fragment link within the document by default,
+    // and templates take the same resolvers as the Markdown renderer.
+    let internal = XRef {
+        frag_id: "title".to_string(),
+        docid: None,
+        ..XRef::docid(String::new())
+    };
+    let mut out = String::new();
+    html::write_xref(&mut html::DefaultTemplate::default(), &mut out, &internal);
+    assert_eq!(out, "<a class=\"xref\" href=\"#fragment-title\"></a>");
+    let mut out = String::new();
+    let mut template = html::DefaultTemplate {
+        resolver: &href_resolver,
+    };
+    html::write_xref(&mut template, &mut out, &internal);
+    assert_eq!(out, "<a class=\"xref\"></a>");
+
+    let mut out = String::new();
+    let link = Link {
+        href: Some(" Java\tScript:alert(1)".to_string()),
+        content: vec![CharacterStyle::Text("click".to_string())],
+    };
+    html::write_link(&mut html::DefaultTemplate::default(), &mut out, &link);
+    assert_eq!(out, "<a>click</a>");
+    assert!(html::is_safe_url("https://example.com/a:b"));
+    assert!(html::is_safe_url("docs/page.html?at=1:2"));
+    assert!(!html::is_safe_url("vbscript:msgbox"));
+}
+
+#[test]
+fn test_validate() {
+    let doc = crate::from_str(&fs::read_to_string("test/invalid.psml").unwrap()).unwrap();
+    let violations = doc.validate();
+    let paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "/document/section[@id='body']/fragment[@id='1']/heading[1]",
+            "/document/section[@id='body']/fragment[@id='1']/para[2]/image[1]",
+            "/document/section[@id='body']/fragment[@id='2']",
+            "/document/section[@id='body']",
+            "/document/section[@id='body']/properties-fragment[@id='1']",
+            "/document/section[@id='body']/properties-fragment[@id='1']/property[@name='bad name!']",
+            "/document/section[@id='body']/properties-fragment[@id='1']/property[@name='tags']",
+        ]
+    );
+    assert_eq!(violations[0].kind, ViolationKind::HeadingLevel { level: 7 });
+    assert_eq!(violations[1].kind, ViolationKind::ImageTarget);
+    assert_eq!(
+        violations[2].kind,
+        ViolationKind::FragmentType {
+            fragment_type: "warning".to_string(),
+            allowed: "default,note".to_string(),
+        }
+    );
+    assert!(matches!(
+        &violations[3].kind,
+        ViolationKind::DuplicateSectionId { id, .. } if id == "body"
+    ));
+    assert_eq!(
+        violations[4].kind,
+        ViolationKind::DuplicateFragmentId {
+            id: "1".to_string(),
+            first: "/document/section[@id='body']/fragment[@id='1']".to_string(),
+        }
+    );
+    assert!(matches!(
+        &violations[5].kind,
+        ViolationKind::PropertyName { name } if name == "bad name!"
+    ));
+    assert!(matches!(
+        violations[6].kind,
+        ViolationKind::PropertyValues { .. }
+    ));
+    assert_eq!(
+        violations[0].to_string(),
+        "/document/section[@id='body']/fragment[@id='1']/heading[1]: heading level 7 is not between 1 and 6"
+    );
+
+    let valid = crate::from_str(&fs::read_to_string("test/toc.psml").unwrap()).unwrap();
+    assert_eq!(valid.validate(), vec![]);
+}
+
+#[test]
+fn test_builder() {
+    let built = DocumentBuilder::new()
+        .docid("guide")
+        .title("Guide")
+        .section("title", |section| {
+            section.fragment(|fragment| {
+                fragment
+                    .heading(1, |heading| heading.text("Guide"))
+                    .para(|para| {
+                        para.text("Hello")
+                            .bold(|bold| bold.italic(|italic| italic.text("world")))
+                    })
+            })
+        })
+        .section("body", |section| {
+            section
+                .fragment(|fragment| {
+                    fragment
+                        .id("intro")
+                        .list(|list| {
+                            list.item(|item| item.text("one"))
+                                .item(|item| item.text("two"))
+                        })
+                        .table(|table| {
+                            table
+                                .caption("Prices")
+                                .header(|row| row.cell(|cell| cell.text("Item")))
+                                .row(|row| row.cell(|cell| cell.text("Tea")))
+                        })
+                })
+                .properties(|properties| properties.string("author", "Author", "Jane"))
+                .xrefs(|xrefs| xrefs.xref(BlockXRef::docid("other".to_string())))
+        })
+        .build()
+        .unwrap();
+
+    let from_macro = crate::psml! {
+        docid("guide")
+        title("Guide")
+        section("title") {
+            fragment {
+                heading(1) { "Guide" }
+                para { "Hello" bold { italic { "world" } } }
+            }
+        }
+        section("body") {
+            fragment {
+                id("intro")
+                list { item { "one" } item { "two" } }
+                table {
+                    caption("Prices")
+                    header { cell { "Item" } }
+                    row { cell { "Tea" } }
+                }
+            }
+            properties { string("author", "Author", "Jane") }
+            xrefs { xref(BlockXRef::docid("other".to_string())) }
+        }
+    }
+    .unwrap();
+    assert_eq!(built, from_macro);
+
+    let ids: Vec<&str> = built
+        .sections
+        .iter()
+        .flat_map(|section| section.content.iter().filter_map(diff::fragment_id))
+        .collect();
+    assert_eq!(ids, vec!["1", "intro", "3", "4"]);
+    assert_eq!(built.docid(), Some("guide"));
+    match &built.sections[1].content[0] {
+        SectionContent::Fragment(fragment) => match &fragment.content[1] {
+            FragmentContent::Table(table) => assert_eq!(table.cols.len(), 1),
+            other => panic!("expected table, got {other:?}"),
+        },
+        other => panic!("expected fragment, got {other:?}"),
+    }
+    match &built.sections[1].content[2] {
+        SectionContent::XRefFragment(fragment) => assert_eq!(fragment.xrefs[0].frag, "default"),
+        other => panic!("expected xref fragment, got {other:?}"),
+    }
+
+    let xml = crate::to_string(&built).unwrap();
+    assert_eq!(crate::from_str(&xml).unwrap(), built);
+
+    let invalid = crate::psml! {
+        section("body") {
+            fragment { id("1") heading(9) { "Too deep" } }
+            fragment { id("1") }
+        }
+    };
+    match invalid {
+        Err(PsmlError::InvalidDocument { violations }) => assert_eq!(violations.len(), 2),
+        other => panic!("expected invalid document, got {other:?}"),
+    }
+
+    let explicit = crate::psml! {
+        section("a") {
+            fragment { id("2") }
+            fragment {}
+        }
+        section("b") {
+            properties { id("4") }
+            fragment {}
+        }
+    }
+    .unwrap();
+    assert_eq!(explicit.fragment_ids(), vec!["2", "3", "4", "5"]);
+
+    let later = crate::psml! {
+        section("a") {
+            fragment {}
+            fragment {}
+        }
+        section("b") {
+            fragment { id("1") }
+            xrefs { id("3") }
+            fragment {}
+        }
+    }
+    .unwrap();
+    assert_eq!(later.fragment_ids(), vec!["2", "4", "1", "3", "7"]);
+
+    // More items in one group than the default recursion limit.
+    let long = crate::psml! {
+        section("body") {
+            fragment {
+                para {
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a" "a"
+                    br
+                }
+            }
+        }
+    }
+    .unwrap();
+    match &long.sections[0].content[0] {
+        SectionContent::Fragment(fragment) => match &fragment.content[0] {
+            FragmentContent::Para(para) => assert_eq!(para.content.len(), 151),
+            other => panic!("expected para, got {other:?}"),
+        },
+        other => panic!("expected fragment, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_labels() {
+    let doc = crate::from_str(&fs::read_to_string("test/document.psml").unwrap()).unwrap();
+    let uri = doc.doc_info.as_ref().unwrap().uri.as_ref().unwrap();
+    let labels = uri.labels.as_ref().unwrap();
+    assert_eq!(
+        labels.iter().collect::<Vec<_>>(),
+        vec!["show-reversexrefs", "netdox-default", "AT"]
+    );
+
+    let mut labels: LabelSet = "a, b,,a".parse().unwrap();
+    assert_eq!(labels.to_string(), "a,b");
+    assert!(labels.add("c").unwrap());
+    assert!(!labels.add("a").unwrap());
+    assert!(labels.add("not valid").is_err());
+    assert!(labels.remove("b"));
+    assert!(!labels.contains("b"));
+    let union = labels.union(&"b,c".parse().unwrap());
+    assert_eq!(union.to_string(), "a,c,b");
+    assert!(matches!(
+        LabelSet::parse("ok,-bad"),
+        Err(PsmlError::LabelError { label }) if label == "-bad"
+    ));
+
+    let mut image = Image::from_src("a.png".to_string()).with_labels("a".parse().unwrap());
+    assert!(image.labels_mut().add("b").unwrap());
+    assert_eq!(image.labels().to_string(), "a,b");
+
+    let xml = r#"<document level="portable"><section id="body"><fragment id="1" labels="draft, needs review"><para>Text</para></fragment></section></document>"#;
+    let doc = crate::from_str(xml).unwrap();
+    let violations = doc.validate();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(
+        violations[0].path,
+        "/document/section[@id='body']/fragment[@id='1']"
+    );
+    assert_eq!(
+        violations[0].kind,
+        ViolationKind::Label {
+            label: "needs review".to_string()
+        }
+    );
+    assert!(crate::to_string(&doc)
+        .unwrap()
+        .contains(r#"labels="draft,needs review""#));
+}
+
+#[test]
+fn test_xref_index() {
+    let guide = crate::from_str(&fs::read_to_string("test/xrefs.psml").unwrap()).unwrap();
+    let reference = crate::psml! {
+        docid("reference")
+        section("body") {
+            fragment {
+                para { xref(XRef::docid("guide".to_string())) }
+            }
+        }
+    }
+    .unwrap();
+    let mut documents = vec![guide, reference];
+
+    let index = XRefIndex::new(&documents);
+    assert_eq!(index.references().len(), 5);
+    assert_eq!(index.outgoing(0).count(), 4);
+    let kinds: Vec<_> = index
+        .to(TargetKey::Docid("reference"))
+        .iter()
+        .map(|reference| (reference.fragment, reference.kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            ("1", ReferenceKind::XRef),
+            ("3", ReferenceKind::Property { name: "related" })
+        ]
+    );
+    assert_eq!(
+        index.to_fragment(TargetKey::Docid("reference"), "2").len(),
+        1
+    );
+    assert_eq!(
+        index.to(TargetKey::URIID("1234"))[0].kind,
+        ReferenceKind::BlockXRef
+    );
+    assert_eq!(index.to(TargetKey::Href("appendix.psml")).len(), 1);
+    let reverse = index.reverse(0);
+    assert_eq!(reverse.len(), 1);
+    assert_eq!(reverse[0].document, 1);
+    assert_eq!(index.reverse(1), index.to(TargetKey::Docid("reference")));
+
+    let count = xrefs::rewrite(&mut documents, |target| {
+        (target.docid == Some("reference")).then(|| NewTarget {
+            docid: Some("reference-v2".to_string()),
+            ..target.into()
+        })
+    });
+    assert_eq!(count, 2);
+    let index = XRefIndex::new(&documents);
+    assert!(index.to(TargetKey::Docid("reference")).is_empty());
+    let moved = index.to(TargetKey::Docid("reference-v2"));
+    assert_eq!(moved.len(), 2);
+    assert_eq!(moved[0].target.frag, "2");
+}
+
+#[test]
+fn test_check_links() {
+    let report = check::check_dir("test/links").unwrap();
+    assert_eq!(report.checked, 12);
+    let broken: Vec<(String, &str, &BrokenLinkKind)> = report
+        .broken
+        .iter()
+        .map(|link| {
+            (
+                link.file.display().to_string(),
+                link.fragment.as_str(),
+                &link.kind,
+            )
+        })
+        .collect();
+    assert_eq!(
+        broken,
+        vec![
+            (
+                "guides/install.psml".to_string(),
+                "2",
+                &BrokenLinkKind::MissingDocument {
+                    attr: "href",
+                    value: "../missing.psml".to_string()
+                }
+            ),
+            (
+                "guides/install.psml".to_string(),
+                "2",
+                &BrokenLinkKind::MissingDocument {
+                    attr: "href",
+                    value: "diagram.png".to_string()
+                }
+            ),
+            (
+                "index.psml".to_string(),
+                "1",
+                &BrokenLinkKind::MissingFragment {
+                    frag: "9".to_string()
+                }
+            ),
+            (
+                "index.psml".to_string(),
+                "1",
+                &BrokenLinkKind::MissingFragment {
+                    frag: "3".to_string()
+                }
+            ),
+            (
+                "index.psml".to_string(),
+                "2",
+                &BrokenLinkKind::MissingDocument {
+                    attr: "uriid",
+                    value: "404".to_string()
+                }
+            ),
+            ("index.psml".to_string(), "2", &BrokenLinkKind::Unresolved),
+        ]
+    );
+    assert_eq!(
+        report.broken[2].to_string(),
+        "index.psml (section body, fragment 1): no fragment \"9\" in the target document"
+    );
+
+    match check::check_dir("test/missing-dir") {
+        Err(PsmlError::IoError(_)) => {}
+        other => panic!("expected io error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_fragment_ids() {
+    let xref = |docid: &str, frag: &str| XRef {
+        frag_id: frag.to_string(),
+        ..XRef::docid(docid.to_string())
+    };
+    let mut doc = crate::psml! {
+        docid("guide")
+        section("a") {
+            fragment { id("intro") para { "Introduction" } }
+            fragment { id("5") para { xref(xref("guide", "intro")) } }
+        }
+        section("b") {
+            fragment { id("x") para { xref(xref("other", "intro")) } }
+        }
+    }
+    .unwrap();
+    doc.sections[0].content.insert(
+        0,
+        SectionContent::Title {
+            text: "Guide".to_string(),
+        },
+    );
+
+    assert_eq!(doc.fragment_section("5").unwrap().id, "a");
+    assert!(matches!(
+        doc.find_fragment("x"),
+        Some(SectionContent::Fragment(Fragment { id, .. })) if id == "x"
+    ));
+    assert!(doc.find_fragment("missing").is_none());
+    assert_eq!(doc.next_fragment_id(), "6");
+
+    let new = Fragments::Fragment(Fragment::new(doc.next_fragment_id()));
+    doc.insert_fragment(new, FragmentPosition::First("a"))
+        .unwrap();
+    assert_eq!(doc.fragment_ids(), vec!["6", "intro", "5", "x"]);
+    assert!(matches!(
+        doc.sections[0].content[0],
+        SectionContent::Title { .. }
+    ));
+    assert!(matches!(
+        doc.insert_fragment(
+            Fragments::Fragment(Fragment::new("x".to_string())),
+            FragmentPosition::Last("b")
+        ),
+        Err(PsmlError::FragmentError { .. })
+    ));
+
+    doc.move_fragment("intro", FragmentPosition::After("x"))
+        .unwrap();
+    assert_eq!(doc.fragment_ids(), vec!["6", "5", "x", "intro"]);
+    assert!(doc
+        .move_fragment("intro", FragmentPosition::Before("intro"))
+        .is_err());
+    match doc.move_fragment("intro", FragmentPosition::Last("c")) {
+        Err(err) => assert_eq!(err.to_string(), "no section with id \"c\""),
+        Ok(()) => panic!("expected missing section"),
+    }
+    assert_eq!(doc.fragment_ids(), vec!["6", "5", "x", "intro"]);
+
+    let ids = doc.renumber_fragments(1);
+    assert_eq!(ids["intro"], "4");
+    assert_eq!(doc.fragment_ids(), vec!["1", "2", "3", "4"]);
+    let targets: Vec<String> = XRefIndex::new([&doc])
+        .references()
+        .iter()
+        .map(|reference| {
+            format!(
+                "{}#{}",
+                reference.target.docid.unwrap(),
+                reference.target.frag
+            )
+        })
+        .collect();
+    assert_eq!(targets, vec!["guide#4", "other#intro"]);
+
+    assert!(matches!(
+        doc.remove_fragment("3"),
+        Some(Fragments::Fragment(Fragment { id, .. })) if id == "3"
+    ));
+    assert!(doc.remove_fragment("3").is_none());
+    assert_eq!(doc.fragment_ids(), vec!["1", "2", "4"]);
+
+    let mut handbook = crate::from_str(&fs::read_to_string("test/toc.psml").unwrap())
+        .unwrap()
+        .with_toc();
+    handbook.renumber_fragments(100);
+    let toc = handbook.toc.as_ref().unwrap();
+    assert_eq!(toc.flatten()[0].fragment, "100");
+    assert_eq!(handbook.toc, Some(handbook.toc()));
+
+    let mut large = crate::from_str(
+        "<document level=\"portable\"><section id=\"s\">\
+         <fragment id=\"18446744073709551615\"/><fragment id=\"0099\"/></section></document>",
+    )
+    .unwrap();
+    assert_eq!(large.next_fragment_id(), "18446744073709551616");
+    large.renumber_fragments(u64::MAX);
+    assert_eq!(
+        large.fragment_ids(),
+        vec!["18446744073709551615", "18446744073709551616"]
+    );
+}
+
+#[test]
+fn test_split_compose() {
+    let local = |frag: &str| XRef {
+        href: None,
+        frag_id: frag.to_string(),
+        ..XRef::href(String::new())
+    };
+    let doc = crate::psml! {
+        section("a") {
+            content_title("Guide")
+            fragment { para { "Preface" } }
+            fragment {
+                heading(1) { "Intro" }
+                para { xref(local("5")) }
+            }
+            fragment { para { "Details" } }
+            fragment { heading(2) { "Sub" } }
+        }
+        section("b") {
+            fragment {
+                heading(1) { "Usage" }
+                para { xref(local("2")) }
+            }
+        }
+    }
+    .unwrap();
+
+    let split = doc.split(&SplitOptions {
+        by: SplitBy::Heading(1),
+        ..Default::default()
+    });
+    assert_eq!(split.parent.fragment_ids(), vec!["1", "2", "5"]);
+    let parts: Vec<(&str, Option<&str>, Vec<&str>)> = split
+        .parts
+        .iter()
+        .map(|part| {
+            let title = part.document.doc_info.as_ref().unwrap().uri.as_ref();
+            (
+                part.href.as_str(),
+                title.unwrap().title.as_deref(),
+                part.document.fragment_ids(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        parts,
+        vec![
+            ("part-1.psml", Some("Intro"), vec!["2", "3", "4"]),
+            ("part-2.psml", Some("Usage"), vec!["5"]),
+        ]
+    );
+    match split.parent.find_fragment("5") {
+        Some(SectionContent::XRefFragment(fragment)) => {
+            assert_eq!(fragment.xrefs[0].href.as_deref(), Some("part-2.psml"));
+            assert_eq!(fragment.xrefs[0].xref_type, Some(BlockXRefKind::Embed));
+        }
+        other => panic!("expected xref fragment, got {other:?}"),
+    }
+    let targets: Vec<String> = XRefIndex::new(split.parts.iter().map(|part| &part.document))
+        .references()
+        .iter()
+        .map(|reference| format!("{:?}#{}", reference.target.href, reference.target.frag))
+        .collect();
+    assert_eq!(
+        targets,
+        vec!["Some(\"part-2.psml\")#5", "Some(\"part-1.psml\")#2"]
+    );
+
+    for by in [
+        SplitBy::Section,
+        SplitBy::Heading(2),
+        SplitBy::FragmentCount(2),
+    ] {
+        let split = doc.split(&SplitOptions {
+            by,
+            kind: BlockXRefKind::Transclude,
+            ..Default::default()
+        });
+        let composed = split
+            .parent
+            .compose(|target| {
+                split
+                    .parts
+                    .iter()
+                    .find(|part| target.href == Some(part.href.as_str()))
+                    .map(|part| part.document.clone())
+            })
+            .unwrap();
+        assert_eq!(composed, doc, "split by {by:?}");
+    }
+
+    // The tables of contents are generated again for the parent, the parts and the composition.
+    let with_toc = doc.clone().with_toc();
+    let split_toc = with_toc.split(&SplitOptions {
+        by: SplitBy::Heading(1),
+        ..Default::default()
+    });
+    assert_eq!(split_toc.parent.toc, Some(split_toc.parent.toc()));
+    assert!(split_toc.parent.toc.as_ref().unwrap().flatten().is_empty());
+    let titles: Vec<Vec<&str>> = split_toc
+        .parts
+        .iter()
+        .map(|part| {
+            let toc = part.document.toc.as_ref().unwrap();
+            toc.flatten()
+                .iter()
+                .map(|part| part.title.as_str())
+                .collect()
+        })
+        .collect();
+    assert_eq!(titles, vec![vec!["Intro", "Sub"], vec!["Usage"]]);
+    let composed = split_toc
+        .parent
+        .compose(|target| {
+            split_toc
+                .parts
+                .iter()
+                .find(|part| target.href == Some(part.href.as_str()))
+                .map(|part| part.document.clone())
+        })
+        .unwrap();
+    assert_eq!(composed, with_toc);
+
+    let mut fragment = split.parent.find_fragment("2").cloned().unwrap();
+    if let SectionContent::XRefFragment(fragment) = &mut fragment {
+        fragment.xrefs[0].frag = "3".to_string();
+    }
+    let mut single = Document::default();
+    single.sections.push(Section {
+        content: vec![fragment],
+        ..Section::new("a".to_string())
+    });
+    let composed = single
+        .compose(|_| Some(split.parts[0].document.clone()))
+        .unwrap();
+    assert_eq!(composed.fragment_ids(), vec!["3"]);
+
+    match split.parent.compose(|_| None) {
+        Err(err) => assert_eq!(
+            err.to_string(),
+            "failed to compose document: cannot resolve href \"part-1.psml\""
+        ),
+        Ok(_) => panic!("expected unresolved block xref"),
+    }
+    assert!(matches!(
+        split.parent.compose(|_| Some(split.parent.clone())),
+        Err(PsmlError::ComposeError { .. })
+    ));
+}
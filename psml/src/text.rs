@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use super::model::XRef;
+use super::model::{LabelSet, XRef, XRefTarget};
 
 macro_rules! impl_char_style {
     ($name:ty) => {
@@ -68,12 +68,85 @@ pub struct Monospace {
 impl_char_style!(Monospace);
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+/// A link to an external resource.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-link.html
 pub struct Link {
+    #[serde(rename = "@href")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// URL of the linked resource.
+    pub href: Option<String>,
     #[serde(rename = "$value", default)]
     pub content: Vec<CharacterStyle>,
 }
 
-impl_char_style!(Link);
+impl Link {
+    pub fn new(content: Vec<CharacterStyle>) -> Self {
+        Self {
+            href: None,
+            content,
+        }
+    }
+
+    pub fn text(text: String) -> Self {
+        Self::new(vec![CharacterStyle::Text(text)])
+    }
+
+    /// Sets the URL of the link and returns it.
+    pub fn with_href(self, href: String) -> Self {
+        Self {
+            href: Some(href),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename = "inline")]
+/// A labelled inline element.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-inline.html
+pub struct Inline {
+    #[serde(rename = "@label")]
+    /// Label for this inline.
+    pub label: String,
+    #[serde(rename = "$value", default)]
+    pub content: Vec<CharacterStyle>,
+}
+
+impl Inline {
+    pub fn new(label: String, content: Vec<CharacterStyle>) -> Self {
+        Self { label, content }
+    }
+
+    pub fn text(label: String, text: String) -> Self {
+        Self {
+            label,
+            content: vec![CharacterStyle::Text(text)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename = "anchor")]
+/// A named anchor that can be linked to.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-anchor.html
+pub struct Anchor {
+    #[serde(rename = "@name")]
+    /// Name of the anchor.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename = "placeholder")]
+/// A placeholder for a value substituted at publication time.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-placeholder.html
+pub struct Placeholder {
+    #[serde(rename = "@name")]
+    /// Name of the placeholder.
+    pub name: String,
+    #[serde(rename = "$text", default)]
+    /// Text to display when the placeholder is not resolved.
+    pub content: String,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -87,13 +160,16 @@ pub enum CharacterStyle {
     Superscript(Superscript),
     Monospace(Monospace),
     XRef(Box<XRef>),
-    Link(Link), // TODO inline, anchor, placeholder, br
+    Link(Link),
+    Inline(Inline),
+    Anchor(Anchor),
+    Placeholder(Placeholder),
+    Br,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename = "image")]
 pub struct Image {
-    // TODO enforce at least one of src, docid, uriid
     #[serde(rename = "@src")]
     #[serde(skip_serializing_if = "Option::is_none")]
     src: Option<String>,
@@ -103,9 +179,9 @@ pub struct Image {
     #[serde(rename = "@uriid")]
     #[serde(skip_serializing_if = "Option::is_none")]
     uriid: Option<String>,
-    #[serde(rename = "@labels")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    labels: Option<String>,
+    #[serde(rename = "@labels", default)]
+    #[serde(skip_serializing_if = "LabelSet::is_empty")]
+    labels: LabelSet,
     #[serde(rename = "@height")]
     #[serde(skip_serializing_if = "Option::is_none")]
     height: Option<u64>,
@@ -117,6 +193,105 @@ pub struct Image {
     alt: Option<String>,
 }
 
+impl Image {
+    fn empty() -> Image {
+        Image {
+            src: None,
+            docid: None,
+            uriid: None,
+            labels: LabelSet::new(),
+            height: None,
+            width: None,
+            alt: None,
+        }
+    }
+
+    /// Creates an image with the given source path or URL.
+    pub fn from_src(src: String) -> Image {
+        Image {
+            src: Some(src),
+            ..Image::empty()
+        }
+    }
+
+    /// Creates an image referencing the document with the given docid.
+    pub fn from_docid(docid: String) -> Image {
+        Image {
+            docid: Some(docid),
+            ..Image::empty()
+        }
+    }
+
+    /// Creates an image referencing the URI with the given id.
+    pub fn from_uriid(uriid: String) -> Image {
+        Image {
+            uriid: Some(uriid),
+            ..Image::empty()
+        }
+    }
+
+    /// Sets the alternate text and returns the image.
+    pub fn with_alt(self, alt: Option<String>) -> Image {
+        Image { alt, ..self }
+    }
+
+    /// Sets the labels and returns the image.
+    pub fn with_labels(self, labels: LabelSet) -> Image {
+        Image { labels, ..self }
+    }
+
+    /// Sets the dimensions in pixels and returns the image.
+    pub fn with_size(self, width: Option<u64>, height: Option<u64>) -> Image {
+        Image {
+            width,
+            height,
+            ..self
+        }
+    }
+
+    pub fn src(&self) -> Option<&str> {
+        self.src.as_deref()
+    }
+
+    pub fn docid(&self) -> Option<&str> {
+        self.docid.as_deref()
+    }
+
+    pub fn uriid(&self) -> Option<&str> {
+        self.uriid.as_deref()
+    }
+
+    pub fn labels(&self) -> &LabelSet {
+        &self.labels
+    }
+
+    pub fn labels_mut(&mut self) -> &mut LabelSet {
+        &mut self.labels
+    }
+
+    pub fn alt(&self) -> Option<&str> {
+        self.alt.as_deref()
+    }
+
+    pub fn width(&self) -> Option<u64> {
+        self.width
+    }
+
+    pub fn height(&self) -> Option<u64> {
+        self.height
+    }
+
+    /// Returns the image source as a target to resolve.
+    pub fn target(&self) -> XRefTarget<'_> {
+        XRefTarget {
+            href: self.src(),
+            docid: self.docid(),
+            uriid: self.uriid(),
+            frag: "default",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ParaContent {
@@ -130,6 +305,10 @@ pub enum ParaContent {
     Monospace(Monospace),
     XRef(XRef),
     Link(Link),
+    Inline(Inline),
+    Anchor(Anchor),
+    Placeholder(Placeholder),
+    Br,
     Image(Image),
 }
 
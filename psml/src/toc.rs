@@ -0,0 +1,218 @@
+//! Table of contents for PSML documents.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    extract::PlainText,
+    model::{BlockXRef, Document, Fragment, XRefFragment},
+    text::{Heading, Para},
+    visit::{self, Visitor},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename = "toc")]
+/// The table of contents of a processed document.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-toc.html
+pub struct Toc {
+    #[serde(rename = "toc-tree")]
+    /// The tree of parts in the document.
+    pub tree: TocTree,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename = "toc-tree")]
+/// The root of a table of contents.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-toc-tree.html
+pub struct TocTree {
+    #[serde(rename = "@title", skip_serializing_if = "Option::is_none")]
+    /// Title of the document.
+    pub title: Option<String>,
+    #[serde(rename = "toc-part", default)]
+    /// Top level parts of the document.
+    pub parts: Vec<TocPart>,
+}
+
+/// The kind of content a part of the table of contents was generated from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TocPartKind {
+    Heading,
+    Para,
+    XRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename = "toc-part")]
+/// A part of the table of contents and the parts nested below it.
+/// For PSML definition see: https://dev.pageseeder.com/psml/element_reference/element-toc-part.html
+pub struct TocPart {
+    #[serde(rename = "@level")]
+    /// Level of the part, starting at 1.
+    pub level: u8,
+    #[serde(rename = "@idx")]
+    /// Position of the part in the document, starting at 1.
+    pub idx: usize,
+    #[serde(rename = "@type")]
+    /// Kind of content the part was generated from.
+    pub kind: TocPartKind,
+    #[serde(rename = "@fragment")]
+    /// ID of the fragment containing the part.
+    pub fragment: String,
+    #[serde(rename = "@title")]
+    /// Text of the heading or paragraph, or title of the xref.
+    pub title: String,
+    #[serde(rename = "@prefix", skip_serializing_if = "Option::is_none")]
+    /// Prefix of the paragraph.
+    pub prefix: Option<String>,
+    #[serde(rename = "@docid", skip_serializing_if = "Option::is_none")]
+    /// Docid of the document included by the xref.
+    pub docid: Option<String>,
+    #[serde(rename = "@uriid", skip_serializing_if = "Option::is_none")]
+    /// URI ID of the document included by the xref.
+    pub uriid: Option<String>,
+    #[serde(rename = "@href", skip_serializing_if = "Option::is_none")]
+    /// Href of the document included by the xref.
+    pub href: Option<String>,
+    #[serde(rename = "toc-part", default)]
+    /// Parts nested below this one.
+    pub parts: Vec<TocPart>,
+}
+
+impl Toc {
+    /// Returns the parts in document order, ignoring nesting.
+    pub fn flatten(&self) -> Vec<&TocPart> {
+        fn push<'a>(parts: &'a [TocPart], out: &mut Vec<&'a TocPart>) {
+            for part in parts {
+                out.push(part);
+                push(&part.parts, out);
+            }
+        }
+
+        let mut out = vec![];
+        push(&self.tree.parts, &mut out);
+        out
+    }
+}
+
+impl Document {
+    /// Generates the table of contents of this document.
+    ///
+    /// Parts are created for each heading, numbered paragraph and block xref with a level,
+    /// and nested below the closest preceding part with a lower level.
+    /// Numbered paragraphs have the level of their indent.
+    pub fn toc(&self) -> Toc {
+        let mut builder = TocBuilder::default();
+        builder.visit_document(self);
+
+        let mut root: Vec<TocPart> = vec![];
+        // Parts that may still have parts nested below them.
+        let mut open: Vec<TocPart> = vec![];
+        for part in builder.parts {
+            close(&mut open, &mut root, part.level);
+            open.push(part);
+        }
+        close(&mut open, &mut root, 0);
+
+        Toc {
+            tree: TocTree {
+                title: self
+                    .doc_info
+                    .as_ref()
+                    .and_then(|info| info.uri.as_ref())
+                    .and_then(|uri| uri.title.clone()),
+                parts: root,
+            },
+        }
+    }
+
+    /// Returns this document with its table of contents generated.
+    pub fn with_toc(mut self) -> Self {
+        self.toc = Some(self.toc());
+        self
+    }
+}
+
+/// Closes the open parts with a level of at least `level`,
+/// nesting each in the part before it or adding it to the root.
+fn close(open: &mut Vec<TocPart>, root: &mut Vec<TocPart>, level: u8) {
+    while open.last().is_some_and(|part| part.level >= level) {
+        let part = open.pop().unwrap();
+        match open.last_mut() {
+            Some(parent) => parent.parts.push(part),
+            None => root.push(part),
+        }
+    }
+}
+
+/// Visitor that collects the parts of the table of contents in document order.
+#[derive(Default)]
+struct TocBuilder {
+    fragment: String,
+    parts: Vec<TocPart>,
+}
+
+impl TocBuilder {
+    fn push(&mut self, level: u8, kind: TocPartKind, title: String) -> &mut TocPart {
+        self.parts.push(TocPart {
+            level: level.max(1),
+            idx: self.parts.len() + 1,
+            kind,
+            fragment: self.fragment.clone(),
+            title: title.split_whitespace().collect::<Vec<&str>>().join(" "),
+            prefix: None,
+            docid: None,
+            uriid: None,
+            href: None,
+            parts: vec![],
+        });
+        self.parts.last_mut().unwrap()
+    }
+}
+
+impl<'a> Visitor<'a> for TocBuilder {
+    fn visit_fragment(&mut self, node: &'a Fragment) {
+        self.fragment.clone_from(&node.id);
+        visit::walk_fragment(self, node);
+    }
+
+    fn visit_xref_fragment(&mut self, node: &'a XRefFragment) {
+        self.fragment.clone_from(&node.id);
+        visit::walk_xref_fragment(self, node);
+    }
+
+    fn visit_block_xref(&mut self, node: &'a BlockXRef) {
+        let Some(level) = node.level else {
+            return;
+        };
+        let title = node
+            .title
+            .as_ref()
+            .or(node.urititle.as_ref())
+            .or(node.href.as_ref())
+            .or(node.docid.as_ref())
+            .or(node.uriid.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        let part = self.push(level, TocPartKind::XRef, title);
+        part.docid.clone_from(&node.docid);
+        part.uriid.clone_from(&node.uriid);
+        part.href.clone_from(&node.href);
+    }
+
+    fn visit_heading(&mut self, node: &'a Heading) {
+        self.push(node.level, TocPartKind::Heading, node.plain_text());
+    }
+
+    fn visit_para(&mut self, node: &'a Para) {
+        if node.numbered != Some(true) {
+            return;
+        }
+        let title = Para {
+            prefix: None,
+            ..node.clone()
+        }
+        .plain_text();
+        let part = self.push(node.indent.unwrap_or(1), TocPartKind::Para, title);
+        part.prefix.clone_from(&node.prefix);
+    }
+}
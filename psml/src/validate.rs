@@ -0,0 +1,351 @@
+//! Validation of PSML documents against rules PageSeeder enforces on upload.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{
+    error::PsmlError,
+    model::{
+        BlockXRef, Document, DocumentInfo, Fragment, FragmentContent, LabelSet, List, ListItem,
+        MediaFragment, NumberedList, PropertiesFragment, Property, Section, Table, TableRow,
+        TableRowContent, XRef, XRefFragment,
+    },
+    text::{
+        Bold, CharacterStyle, Heading, Image, Inline, Italic, Link, Monospace, Para, Subscript,
+        Superscript, Underline,
+    },
+    visit::{self, Visitor},
+};
+
+/// A rule broken by a node in a document.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ViolationKind {
+    #[error("section id {id:?} is already used at {first}")]
+    DuplicateSectionId { id: String, first: String },
+    #[error("fragment id {id:?} is already used at {first}")]
+    DuplicateFragmentId { id: String, first: String },
+    #[error("heading level {level} is not between 1 and 6")]
+    HeadingLevel { level: u8 },
+    #[error(
+        "fragment type {fragment_type:?} is not one of the section's fragment types {allowed:?}"
+    )]
+    FragmentType {
+        fragment_type: String,
+        allowed: String,
+    },
+    #[error("image has no src, docid or uriid")]
+    ImageTarget,
+    #[error("property name {name:?} contains illegal characters")]
+    PropertyName { name: String },
+    #[error("{msg}")]
+    PropertyValues { msg: String },
+    #[error("label {label:?} contains illegal characters")]
+    Label { label: String },
+}
+
+/// A rule broken by a node in a document.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{path}: {kind}")]
+pub struct Violation {
+    /// Path to the node, e.g. `/document/section[@id='body']/fragment[@id='1']/para[2]/image[1]`.
+    /// Elements without an ID or name have their position among siblings with the same name.
+    pub path: String,
+    pub kind: ViolationKind,
+}
+
+impl Document {
+    /// Checks this document against the rules PageSeeder enforces on upload,
+    /// returning every violation in document order.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut validator = Validator::default();
+        validator.visit_document(self);
+        validator.violations
+    }
+}
+
+/// Visitor that tracks the path to the current node and collects violations.
+#[derive(Default)]
+struct Validator {
+    path: Vec<String>,
+    /// Number of child elements visited so far by name, for each element in the path.
+    counts: Vec<HashMap<&'static str, usize>>,
+    section_ids: HashMap<String, String>,
+    fragment_ids: HashMap<String, String>,
+    /// Fragment types allowed in the current section, if it restricts them.
+    fragment_types: Option<String>,
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    fn path(&self) -> String {
+        format!("/{}", self.path.join("/"))
+    }
+
+    fn violation(&mut self, kind: ViolationKind) {
+        self.violations.push(Violation {
+            path: self.path(),
+            kind,
+        });
+    }
+
+    /// Visits a node inside an element with the given path segment.
+    fn within(&mut self, segment: String, visit: impl FnOnce(&mut Self)) {
+        self.path.push(segment);
+        self.counts.push(HashMap::new());
+        visit(self);
+        self.counts.pop();
+        self.path.pop();
+    }
+
+    /// Visits a node inside the next element with the name,
+    /// with its 1-based position among siblings with the same name as the path segment.
+    fn within_nth(&mut self, element: &'static str, visit: impl FnOnce(&mut Self)) {
+        let position = match self.counts.last_mut() {
+            Some(counts) => {
+                let count = counts.entry(element).or_default();
+                *count += 1;
+                *count
+            }
+            None => 1,
+        };
+        self.within(format!("{element}[{position}]"), visit);
+    }
+
+    fn labels(&mut self, labels: &LabelSet) {
+        for label in labels.invalid() {
+            self.violation(ViolationKind::Label {
+                label: label.to_string(),
+            });
+        }
+    }
+
+    /// Checks the id, type and labels of a fragment, then visits it.
+    fn fragment(
+        &mut self,
+        element: &str,
+        id: &str,
+        fragment_type: Option<&str>,
+        labels: &LabelSet,
+        visit: impl FnOnce(&mut Self),
+    ) {
+        self.within(format!("{element}[@id='{id}']"), |this| {
+            let path = this.path();
+            if let Some(first) = this.fragment_ids.get(id) {
+                let first = first.clone();
+                this.violation(ViolationKind::DuplicateFragmentId {
+                    id: id.to_string(),
+                    first,
+                });
+            } else {
+                this.fragment_ids.insert(id.to_string(), path);
+            }
+
+            if let Some(allowed) = &this.fragment_types {
+                let fragment_type = fragment_type.unwrap_or("default");
+                if !allowed
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .any(|allowed| allowed == fragment_type)
+                {
+                    let allowed = allowed.clone();
+                    this.violation(ViolationKind::FragmentType {
+                        fragment_type: fragment_type.to_string(),
+                        allowed,
+                    });
+                }
+            }
+
+            this.labels(labels);
+            visit(this);
+        });
+    }
+}
+
+impl<'a> Visitor<'a> for Validator {
+    fn visit_document(&mut self, node: &'a Document) {
+        self.within("document".to_string(), |this| {
+            visit::walk_document(this, node)
+        });
+    }
+
+    fn visit_document_info(&mut self, node: &'a DocumentInfo) {
+        if let Some(labels) = node.uri.as_ref().and_then(|uri| uri.labels.as_ref()) {
+            self.within("documentinfo/uri".to_string(), |this| this.labels(labels));
+        }
+        visit::walk_document_info(self, node);
+    }
+
+    fn visit_section(&mut self, node: &'a Section) {
+        self.within(format!("section[@id='{}']", node.id), |this| {
+            let path = this.path();
+            if let Some(first) = this.section_ids.get(&node.id) {
+                let first = first.clone();
+                this.violation(ViolationKind::DuplicateSectionId {
+                    id: node.id.clone(),
+                    first,
+                });
+            } else {
+                this.section_ids.insert(node.id.clone(), path);
+            }
+
+            this.fragment_types = node.fragment_types.clone();
+            visit::walk_section(this, node);
+            this.fragment_types = None;
+        });
+    }
+
+    fn visit_fragment(&mut self, node: &'a Fragment) {
+        self.fragment(
+            "fragment",
+            &node.id,
+            node.frag_type.as_deref(),
+            &node.labels,
+            |this| visit::walk_fragment(this, node),
+        );
+    }
+
+    fn visit_properties_fragment(&mut self, node: &'a PropertiesFragment) {
+        self.fragment(
+            "properties-fragment",
+            &node.id,
+            node.frag_type.as_deref(),
+            &node.labels,
+            |this| visit::walk_properties_fragment(this, node),
+        );
+    }
+
+    fn visit_xref_fragment(&mut self, node: &'a XRefFragment) {
+        self.fragment(
+            "xref-fragment",
+            &node.id,
+            node.frag_type.as_deref(),
+            &node.labels,
+            |this| visit::walk_xref_fragment(this, node),
+        );
+    }
+
+    fn visit_media_fragment(&mut self, node: &'a MediaFragment) {
+        self.fragment(
+            "media-fragment",
+            &node.id,
+            node.frag_type.as_deref(),
+            &node.labels,
+            |_| {},
+        );
+    }
+
+    fn visit_property(&mut self, node: &'a Property) {
+        self.within(format!("property[@name='{}']", node.name), |this| {
+            if node.name.is_empty() || Property::sanitize_name(&node.name, "") != node.name {
+                this.violation(ViolationKind::PropertyName {
+                    name: node.name.clone(),
+                });
+            }
+            if let Err(PsmlError::PropertyError { msg, .. }) = node.check_multiple() {
+                this.violation(ViolationKind::PropertyValues { msg });
+            }
+            visit::walk_property(this, node);
+        });
+    }
+
+    fn visit_block_xref(&mut self, node: &'a BlockXRef) {
+        self.within_nth("blockxref", |this| {
+            this.labels(&node.labels);
+            this.labels(&node.urilabels);
+        });
+    }
+
+    fn visit_block(&mut self, label: Option<&'a str>, child: &'a [FragmentContent]) {
+        self.within_nth("block", |this| visit::walk_block(this, label, child));
+    }
+
+    fn visit_preformat(&mut self, child: &'a [CharacterStyle]) {
+        self.within_nth("preformat", |this| visit::walk_preformat(this, child));
+    }
+
+    fn visit_heading(&mut self, node: &'a Heading) {
+        self.within_nth("heading", |this| {
+            if !(1..=6).contains(&node.level) {
+                this.violation(ViolationKind::HeadingLevel { level: node.level });
+            }
+            visit::walk_heading(this, node);
+        });
+    }
+
+    fn visit_para(&mut self, node: &'a Para) {
+        self.within_nth("para", |this| visit::walk_para(this, node));
+    }
+
+    fn visit_table(&mut self, node: &'a Table) {
+        self.within_nth("table", |this| visit::walk_table(this, node));
+    }
+
+    fn visit_table_row(&mut self, node: &'a TableRow) {
+        self.within_nth("row", |this| visit::walk_table_row(this, node));
+    }
+
+    fn visit_table_row_content(&mut self, node: &'a TableRowContent) {
+        let element = match node {
+            TableRowContent::Cell(_) => "cell",
+            TableRowContent::HeaderCell(_) => "hcell",
+        };
+        self.within_nth(element, |this| visit::walk_table_row_content(this, node));
+    }
+
+    fn visit_list(&mut self, node: &'a List) {
+        self.within_nth("list", |this| visit::walk_list(this, node));
+    }
+
+    fn visit_numbered_list(&mut self, node: &'a NumberedList) {
+        self.within_nth("nlist", |this| visit::walk_numbered_list(this, node));
+    }
+
+    fn visit_list_item(&mut self, node: &'a ListItem) {
+        self.within_nth("item", |this| visit::walk_list_item(this, node));
+    }
+
+    fn visit_bold(&mut self, node: &'a Bold) {
+        self.within_nth("bold", |this| visit::walk_bold(this, node));
+    }
+
+    fn visit_italic(&mut self, node: &'a Italic) {
+        self.within_nth("italic", |this| visit::walk_italic(this, node));
+    }
+
+    fn visit_underline(&mut self, node: &'a Underline) {
+        self.within_nth("underline", |this| visit::walk_underline(this, node));
+    }
+
+    fn visit_subscript(&mut self, node: &'a Subscript) {
+        self.within_nth("subscript", |this| visit::walk_subscript(this, node));
+    }
+
+    fn visit_superscript(&mut self, node: &'a Superscript) {
+        self.within_nth("superscript", |this| visit::walk_superscript(this, node));
+    }
+
+    fn visit_monospace(&mut self, node: &'a Monospace) {
+        self.within_nth("monospace", |this| visit::walk_monospace(this, node));
+    }
+
+    fn visit_link(&mut self, node: &'a Link) {
+        self.within_nth("link", |this| visit::walk_link(this, node));
+    }
+
+    fn visit_inline(&mut self, node: &'a Inline) {
+        self.within_nth("inline", |this| visit::walk_inline(this, node));
+    }
+
+    fn visit_image(&mut self, node: &'a Image) {
+        self.within_nth("image", |this| {
+            if node.src().is_none() && node.docid().is_none() && node.uriid().is_none() {
+                this.violation(ViolationKind::ImageTarget);
+            }
+            this.labels(node.labels());
+        });
+    }
+
+    fn visit_xref(&mut self, node: &'a XRef) {
+        self.within_nth("xref", |this| this.labels(&node.labels));
+    }
+}
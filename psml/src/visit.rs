@@ -0,0 +1,453 @@
+//! Traversal of a PSML document by shared reference.
+//!
+//! Each method of [`Visitor`] defaults to calling the matching `walk_*` function, which visits
+//! the children of the node. Override a method to act on a node, and call the `walk_*` function
+//! from it to keep descending.
+//!
+//! For traversal by mutable reference see [`crate::visit_mut`].
+
+use crate::{
+    model::{
+        BlockXRef, Document, DocumentInfo, Fragment, FragmentContent, List, ListItem, Locator,
+        MediaFragment, MixedContent, NumberedList, PropertiesFragment, Property, PropertyValue,
+        Section, SectionContent, Table, TableCell, TableColumn, TableRow, TableRowContent,
+        URIDescriptor, XRef, XRefFragment,
+    },
+    text::{
+        Anchor, Bold, CharacterStyle, Heading, Image, Inline, Italic, Link, Monospace, Para,
+        ParaContent, Placeholder, Subscript, Superscript, Underline,
+    },
+    toc::{Toc, TocPart},
+};
+
+pub trait Visitor<'a> {
+    fn visit_document(&mut self, node: &'a Document) {
+        walk_document(self, node)
+    }
+
+    fn visit_document_info(&mut self, node: &'a DocumentInfo) {
+        walk_document_info(self, node)
+    }
+
+    fn visit_uri_descriptor(&mut self, _node: &'a URIDescriptor) {}
+
+    fn visit_locator(&mut self, _node: &'a Locator) {}
+
+    fn visit_toc(&mut self, node: &'a Toc) {
+        walk_toc(self, node)
+    }
+
+    fn visit_toc_part(&mut self, node: &'a TocPart) {
+        walk_toc_part(self, node)
+    }
+
+    fn visit_section(&mut self, node: &'a Section) {
+        walk_section(self, node)
+    }
+
+    fn visit_section_content(&mut self, node: &'a SectionContent) {
+        walk_section_content(self, node)
+    }
+
+    fn visit_section_title(&mut self, _title: &'a str) {}
+
+    fn visit_fragment(&mut self, node: &'a Fragment) {
+        walk_fragment(self, node)
+    }
+
+    fn visit_properties_fragment(&mut self, node: &'a PropertiesFragment) {
+        walk_properties_fragment(self, node)
+    }
+
+    fn visit_property(&mut self, node: &'a Property) {
+        walk_property(self, node)
+    }
+
+    fn visit_property_value(&mut self, node: &'a PropertyValue) {
+        walk_property_value(self, node)
+    }
+
+    fn visit_xref_fragment(&mut self, node: &'a XRefFragment) {
+        walk_xref_fragment(self, node)
+    }
+
+    fn visit_block_xref(&mut self, _node: &'a BlockXRef) {}
+
+    fn visit_media_fragment(&mut self, _node: &'a MediaFragment) {}
+
+    fn visit_fragment_content(&mut self, node: &'a FragmentContent) {
+        walk_fragment_content(self, node)
+    }
+
+    fn visit_block(&mut self, label: Option<&'a str>, child: &'a [FragmentContent]) {
+        walk_block(self, label, child)
+    }
+
+    fn visit_preformat(&mut self, child: &'a [CharacterStyle]) {
+        walk_preformat(self, child)
+    }
+
+    fn visit_heading(&mut self, node: &'a Heading) {
+        walk_heading(self, node)
+    }
+
+    fn visit_para(&mut self, node: &'a Para) {
+        walk_para(self, node)
+    }
+
+    fn visit_para_content(&mut self, node: &'a ParaContent) {
+        walk_para_content(self, node)
+    }
+
+    fn visit_table(&mut self, node: &'a Table) {
+        walk_table(self, node)
+    }
+
+    fn visit_table_column(&mut self, _node: &'a TableColumn) {}
+
+    fn visit_table_row(&mut self, node: &'a TableRow) {
+        walk_table_row(self, node)
+    }
+
+    fn visit_table_row_content(&mut self, node: &'a TableRowContent) {
+        walk_table_row_content(self, node)
+    }
+
+    fn visit_table_cell(&mut self, node: &'a TableCell) {
+        walk_table_cell(self, node)
+    }
+
+    fn visit_list(&mut self, node: &'a List) {
+        walk_list(self, node)
+    }
+
+    fn visit_numbered_list(&mut self, node: &'a NumberedList) {
+        walk_numbered_list(self, node)
+    }
+
+    fn visit_list_item(&mut self, node: &'a ListItem) {
+        walk_list_item(self, node)
+    }
+
+    fn visit_mixed_content(&mut self, node: &'a MixedContent) {
+        walk_mixed_content(self, node)
+    }
+
+    fn visit_character_style(&mut self, node: &'a CharacterStyle) {
+        walk_character_style(self, node)
+    }
+
+    fn visit_text(&mut self, _text: &'a str) {}
+
+    fn visit_bold(&mut self, node: &'a Bold) {
+        walk_bold(self, node)
+    }
+
+    fn visit_italic(&mut self, node: &'a Italic) {
+        walk_italic(self, node)
+    }
+
+    fn visit_underline(&mut self, node: &'a Underline) {
+        walk_underline(self, node)
+    }
+
+    fn visit_subscript(&mut self, node: &'a Subscript) {
+        walk_subscript(self, node)
+    }
+
+    fn visit_superscript(&mut self, node: &'a Superscript) {
+        walk_superscript(self, node)
+    }
+
+    fn visit_monospace(&mut self, node: &'a Monospace) {
+        walk_monospace(self, node)
+    }
+
+    fn visit_link(&mut self, node: &'a Link) {
+        walk_link(self, node)
+    }
+
+    fn visit_inline(&mut self, node: &'a Inline) {
+        walk_inline(self, node)
+    }
+
+    fn visit_xref(&mut self, _node: &'a XRef) {}
+
+    fn visit_anchor(&mut self, _node: &'a Anchor) {}
+
+    fn visit_placeholder(&mut self, _node: &'a Placeholder) {}
+
+    fn visit_br(&mut self) {}
+
+    fn visit_image(&mut self, _node: &'a Image) {}
+}
+
+pub fn walk_document<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Document) {
+    if let Some(info) = &node.doc_info {
+        visitor.visit_document_info(info);
+    }
+    for locator in &node.frag_info {
+        visitor.visit_locator(locator);
+    }
+    for section in &node.sections {
+        visitor.visit_section(section);
+    }
+    if let Some(toc) = &node.toc {
+        visitor.visit_toc(toc);
+    }
+}
+
+pub fn walk_toc<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Toc) {
+    for part in &node.tree.parts {
+        visitor.visit_toc_part(part);
+    }
+}
+
+pub fn walk_toc_part<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a TocPart) {
+    for part in &node.parts {
+        visitor.visit_toc_part(part);
+    }
+}
+
+pub fn walk_document_info<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a DocumentInfo) {
+    if let Some(uri) = &node.uri {
+        visitor.visit_uri_descriptor(uri);
+    }
+}
+
+pub fn walk_section<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Section) {
+    for content in &node.content {
+        visitor.visit_section_content(content);
+    }
+}
+
+pub fn walk_section_content<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    node: &'a SectionContent,
+) {
+    match node {
+        SectionContent::Fragment(frag) => visitor.visit_fragment(frag),
+        SectionContent::PropertiesFragment(frag) => visitor.visit_properties_fragment(frag),
+        SectionContent::XRefFragment(frag) => visitor.visit_xref_fragment(frag),
+        SectionContent::Media(frag) => visitor.visit_media_fragment(frag),
+        SectionContent::Title { text } => visitor.visit_section_title(text),
+    }
+}
+
+pub fn walk_fragment<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Fragment) {
+    for content in &node.content {
+        visitor.visit_fragment_content(content);
+    }
+}
+
+pub fn walk_properties_fragment<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    node: &'a PropertiesFragment,
+) {
+    for property in &node.properties {
+        visitor.visit_property(property);
+    }
+}
+
+pub fn walk_property<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Property) {
+    for value in &node.values {
+        visitor.visit_property_value(value);
+    }
+}
+
+pub fn walk_property_value<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a PropertyValue) {
+    match node {
+        PropertyValue::XRef(xref) => visitor.visit_xref(xref),
+        PropertyValue::Markup(markup) => {
+            for child in &markup.content {
+                visitor.visit_character_style(child);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn walk_xref_fragment<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a XRefFragment) {
+    for xref in &node.xrefs {
+        visitor.visit_block_xref(xref);
+    }
+}
+
+pub fn walk_fragment_content<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    node: &'a FragmentContent,
+) {
+    match node {
+        FragmentContent::Text(text) => visitor.visit_text(text),
+        FragmentContent::Heading(heading) => visitor.visit_heading(heading),
+        FragmentContent::Block { label, child } => visitor.visit_block(label.as_deref(), child),
+        FragmentContent::BlockXRef(xref) => visitor.visit_block_xref(xref),
+        FragmentContent::Para(para) => visitor.visit_para(para),
+        FragmentContent::Preformat { child } => visitor.visit_preformat(child),
+        FragmentContent::Image(image) => visitor.visit_image(image),
+        FragmentContent::Table(table) => visitor.visit_table(table),
+        FragmentContent::List(list) => visitor.visit_list(list),
+        FragmentContent::NumberedList(list) => visitor.visit_numbered_list(list),
+    }
+}
+
+pub fn walk_block<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    _label: Option<&'a str>,
+    child: &'a [FragmentContent],
+) {
+    for content in child {
+        visitor.visit_fragment_content(content);
+    }
+}
+
+pub fn walk_preformat<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, child: &'a [CharacterStyle]) {
+    for content in child {
+        visitor.visit_character_style(content);
+    }
+}
+
+pub fn walk_heading<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Heading) {
+    for content in &node.content {
+        visitor.visit_character_style(content);
+    }
+}
+
+pub fn walk_para<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Para) {
+    for content in &node.content {
+        visitor.visit_para_content(content);
+    }
+}
+
+pub fn walk_para_content<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a ParaContent) {
+    match node {
+        ParaContent::Text(text) => visitor.visit_text(text),
+        ParaContent::Bold(bold) => visitor.visit_bold(bold),
+        ParaContent::Italic(italic) => visitor.visit_italic(italic),
+        ParaContent::Underline(underline) => visitor.visit_underline(underline),
+        ParaContent::Subscript(subscript) => visitor.visit_subscript(subscript),
+        ParaContent::Superscript(superscript) => visitor.visit_superscript(superscript),
+        ParaContent::Monospace(monospace) => visitor.visit_monospace(monospace),
+        ParaContent::XRef(xref) => visitor.visit_xref(xref),
+        ParaContent::Link(link) => visitor.visit_link(link),
+        ParaContent::Inline(inline) => visitor.visit_inline(inline),
+        ParaContent::Anchor(anchor) => visitor.visit_anchor(anchor),
+        ParaContent::Placeholder(placeholder) => visitor.visit_placeholder(placeholder),
+        ParaContent::Br => visitor.visit_br(),
+        ParaContent::Image(image) => visitor.visit_image(image),
+    }
+}
+
+pub fn walk_table<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Table) {
+    for col in &node.cols {
+        visitor.visit_table_column(col);
+    }
+    for row in &node.rows {
+        visitor.visit_table_row(row);
+    }
+}
+
+pub fn walk_table_row<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a TableRow) {
+    for cell in &node.cells {
+        visitor.visit_table_row_content(cell);
+    }
+}
+
+pub fn walk_table_row_content<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    node: &'a TableRowContent,
+) {
+    match node {
+        TableRowContent::Cell(cell) | TableRowContent::HeaderCell(cell) => {
+            visitor.visit_table_cell(cell)
+        }
+    }
+}
+
+pub fn walk_table_cell<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a TableCell) {
+    for content in &node.content {
+        visitor.visit_mixed_content(content);
+    }
+}
+
+pub fn walk_list<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a List) {
+    for item in &node.items {
+        visitor.visit_list_item(item);
+    }
+}
+
+pub fn walk_numbered_list<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a NumberedList) {
+    for item in &node.items {
+        visitor.visit_list_item(item);
+    }
+}
+
+pub fn walk_list_item<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a ListItem) {
+    for content in &node.content {
+        visitor.visit_mixed_content(content);
+    }
+}
+
+pub fn walk_mixed_content<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a MixedContent) {
+    match node {
+        MixedContent::Text(text) => visitor.visit_text(text),
+        MixedContent::Bold(bold) => visitor.visit_bold(bold),
+        MixedContent::Italic(italic) => visitor.visit_italic(italic),
+        MixedContent::Underline(underline) => visitor.visit_underline(underline),
+        MixedContent::Subscript(subscript) => visitor.visit_subscript(subscript),
+        MixedContent::Superscript(superscript) => visitor.visit_superscript(superscript),
+        MixedContent::Monospace(monospace) => visitor.visit_monospace(monospace),
+        MixedContent::XRef(xref) => visitor.visit_xref(xref),
+        MixedContent::Link(link) => visitor.visit_link(link),
+        MixedContent::Inline(inline) => visitor.visit_inline(inline),
+        MixedContent::Anchor(anchor) => visitor.visit_anchor(anchor),
+        MixedContent::Placeholder(placeholder) => visitor.visit_placeholder(placeholder),
+        MixedContent::Br => visitor.visit_br(),
+        MixedContent::Image(image) => visitor.visit_image(image),
+        MixedContent::Para(para) => visitor.visit_para(para),
+        MixedContent::Block { label, child } => visitor.visit_block(label.as_deref(), child),
+        MixedContent::Preformat { child } => visitor.visit_preformat(child),
+        MixedContent::List(list) => visitor.visit_list(list),
+        MixedContent::NumberedList(list) => visitor.visit_numbered_list(list),
+    }
+}
+
+pub fn walk_character_style<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    node: &'a CharacterStyle,
+) {
+    match node {
+        CharacterStyle::Text(text) => visitor.visit_text(text),
+        CharacterStyle::Bold(bold) => visitor.visit_bold(bold),
+        CharacterStyle::Italic(italic) => visitor.visit_italic(italic),
+        CharacterStyle::Underline(underline) => visitor.visit_underline(underline),
+        CharacterStyle::Subscript(subscript) => visitor.visit_subscript(subscript),
+        CharacterStyle::Superscript(superscript) => visitor.visit_superscript(superscript),
+        CharacterStyle::Monospace(monospace) => visitor.visit_monospace(monospace),
+        CharacterStyle::XRef(xref) => visitor.visit_xref(xref),
+        CharacterStyle::Link(link) => visitor.visit_link(link),
+        CharacterStyle::Inline(inline) => visitor.visit_inline(inline),
+        CharacterStyle::Anchor(anchor) => visitor.visit_anchor(anchor),
+        CharacterStyle::Placeholder(placeholder) => visitor.visit_placeholder(placeholder),
+        CharacterStyle::Br => visitor.visit_br(),
+    }
+}
+
+macro_rules! walk_char_style {
+    ($name:ident, $ty:ty) => {
+        pub fn $name<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a $ty) {
+            for content in &node.content {
+                visitor.visit_character_style(content);
+            }
+        }
+    };
+}
+
+walk_char_style!(walk_bold, Bold);
+walk_char_style!(walk_italic, Italic);
+walk_char_style!(walk_underline, Underline);
+walk_char_style!(walk_subscript, Subscript);
+walk_char_style!(walk_superscript, Superscript);
+walk_char_style!(walk_monospace, Monospace);
+walk_char_style!(walk_link, Link);
+walk_char_style!(walk_inline, Inline);
@@ -0,0 +1,441 @@
+//! Traversal of a PSML document by mutable reference.
+//!
+//! Each method of [`VisitorMut`] defaults to calling the matching `walk_*` function, which visits
+//! the children of the node. Override a method to act on a node, and call the `walk_*` function
+//! from it to keep descending.
+//!
+//! For traversal by shared reference see [`crate::visit`].
+
+use crate::{
+    model::{
+        BlockXRef, Document, DocumentInfo, Fragment, FragmentContent, List, ListItem, Locator,
+        MediaFragment, MixedContent, NumberedList, PropertiesFragment, Property, PropertyValue,
+        Section, SectionContent, Table, TableCell, TableColumn, TableRow, TableRowContent,
+        URIDescriptor, XRef, XRefFragment,
+    },
+    text::{
+        Anchor, Bold, CharacterStyle, Heading, Image, Inline, Italic, Link, Monospace, Para,
+        ParaContent, Placeholder, Subscript, Superscript, Underline,
+    },
+    toc::{Toc, TocPart},
+};
+
+pub trait VisitorMut {
+    fn visit_document(&mut self, node: &mut Document) {
+        walk_document(self, node)
+    }
+
+    fn visit_document_info(&mut self, node: &mut DocumentInfo) {
+        walk_document_info(self, node)
+    }
+
+    fn visit_uri_descriptor(&mut self, _node: &mut URIDescriptor) {}
+
+    fn visit_locator(&mut self, _node: &mut Locator) {}
+
+    fn visit_toc(&mut self, node: &mut Toc) {
+        walk_toc(self, node)
+    }
+
+    fn visit_toc_part(&mut self, node: &mut TocPart) {
+        walk_toc_part(self, node)
+    }
+
+    fn visit_section(&mut self, node: &mut Section) {
+        walk_section(self, node)
+    }
+
+    fn visit_section_content(&mut self, node: &mut SectionContent) {
+        walk_section_content(self, node)
+    }
+
+    fn visit_section_title(&mut self, _title: &mut String) {}
+
+    fn visit_fragment(&mut self, node: &mut Fragment) {
+        walk_fragment(self, node)
+    }
+
+    fn visit_properties_fragment(&mut self, node: &mut PropertiesFragment) {
+        walk_properties_fragment(self, node)
+    }
+
+    fn visit_property(&mut self, node: &mut Property) {
+        walk_property(self, node)
+    }
+
+    fn visit_property_value(&mut self, node: &mut PropertyValue) {
+        walk_property_value(self, node)
+    }
+
+    fn visit_xref_fragment(&mut self, node: &mut XRefFragment) {
+        walk_xref_fragment(self, node)
+    }
+
+    fn visit_block_xref(&mut self, _node: &mut BlockXRef) {}
+
+    fn visit_media_fragment(&mut self, _node: &mut MediaFragment) {}
+
+    fn visit_fragment_content(&mut self, node: &mut FragmentContent) {
+        walk_fragment_content(self, node)
+    }
+
+    fn visit_block(&mut self, label: &mut Option<String>, child: &mut Vec<FragmentContent>) {
+        walk_block(self, label, child)
+    }
+
+    fn visit_preformat(&mut self, child: &mut Vec<CharacterStyle>) {
+        walk_preformat(self, child)
+    }
+
+    fn visit_heading(&mut self, node: &mut Heading) {
+        walk_heading(self, node)
+    }
+
+    fn visit_para(&mut self, node: &mut Para) {
+        walk_para(self, node)
+    }
+
+    fn visit_para_content(&mut self, node: &mut ParaContent) {
+        walk_para_content(self, node)
+    }
+
+    fn visit_table(&mut self, node: &mut Table) {
+        walk_table(self, node)
+    }
+
+    fn visit_table_column(&mut self, _node: &mut TableColumn) {}
+
+    fn visit_table_row(&mut self, node: &mut TableRow) {
+        walk_table_row(self, node)
+    }
+
+    fn visit_table_row_content(&mut self, node: &mut TableRowContent) {
+        walk_table_row_content(self, node)
+    }
+
+    fn visit_table_cell(&mut self, node: &mut TableCell) {
+        walk_table_cell(self, node)
+    }
+
+    fn visit_list(&mut self, node: &mut List) {
+        walk_list(self, node)
+    }
+
+    fn visit_numbered_list(&mut self, node: &mut NumberedList) {
+        walk_numbered_list(self, node)
+    }
+
+    fn visit_list_item(&mut self, node: &mut ListItem) {
+        walk_list_item(self, node)
+    }
+
+    fn visit_mixed_content(&mut self, node: &mut MixedContent) {
+        walk_mixed_content(self, node)
+    }
+
+    fn visit_character_style(&mut self, node: &mut CharacterStyle) {
+        walk_character_style(self, node)
+    }
+
+    fn visit_text(&mut self, _text: &mut String) {}
+
+    fn visit_bold(&mut self, node: &mut Bold) {
+        walk_bold(self, node)
+    }
+
+    fn visit_italic(&mut self, node: &mut Italic) {
+        walk_italic(self, node)
+    }
+
+    fn visit_underline(&mut self, node: &mut Underline) {
+        walk_underline(self, node)
+    }
+
+    fn visit_subscript(&mut self, node: &mut Subscript) {
+        walk_subscript(self, node)
+    }
+
+    fn visit_superscript(&mut self, node: &mut Superscript) {
+        walk_superscript(self, node)
+    }
+
+    fn visit_monospace(&mut self, node: &mut Monospace) {
+        walk_monospace(self, node)
+    }
+
+    fn visit_link(&mut self, node: &mut Link) {
+        walk_link(self, node)
+    }
+
+    fn visit_inline(&mut self, node: &mut Inline) {
+        walk_inline(self, node)
+    }
+
+    fn visit_xref(&mut self, _node: &mut XRef) {}
+
+    fn visit_anchor(&mut self, _node: &mut Anchor) {}
+
+    fn visit_placeholder(&mut self, _node: &mut Placeholder) {}
+
+    fn visit_br(&mut self) {}
+
+    fn visit_image(&mut self, _node: &mut Image) {}
+}
+
+pub fn walk_document<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Document) {
+    if let Some(info) = &mut node.doc_info {
+        visitor.visit_document_info(info);
+    }
+    for locator in &mut node.frag_info {
+        visitor.visit_locator(locator);
+    }
+    for section in &mut node.sections {
+        visitor.visit_section(section);
+    }
+    if let Some(toc) = &mut node.toc {
+        visitor.visit_toc(toc);
+    }
+}
+
+pub fn walk_toc<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Toc) {
+    for part in &mut node.tree.parts {
+        visitor.visit_toc_part(part);
+    }
+}
+
+pub fn walk_toc_part<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut TocPart) {
+    for part in &mut node.parts {
+        visitor.visit_toc_part(part);
+    }
+}
+
+pub fn walk_document_info<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut DocumentInfo) {
+    if let Some(uri) = &mut node.uri {
+        visitor.visit_uri_descriptor(uri);
+    }
+}
+
+pub fn walk_section<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Section) {
+    for content in &mut node.content {
+        visitor.visit_section_content(content);
+    }
+}
+
+pub fn walk_section_content<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut SectionContent) {
+    match node {
+        SectionContent::Fragment(frag) => visitor.visit_fragment(frag),
+        SectionContent::PropertiesFragment(frag) => visitor.visit_properties_fragment(frag),
+        SectionContent::XRefFragment(frag) => visitor.visit_xref_fragment(frag),
+        SectionContent::Media(frag) => visitor.visit_media_fragment(frag),
+        SectionContent::Title { text } => visitor.visit_section_title(text),
+    }
+}
+
+pub fn walk_fragment<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Fragment) {
+    for content in &mut node.content {
+        visitor.visit_fragment_content(content);
+    }
+}
+
+pub fn walk_properties_fragment<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    node: &mut PropertiesFragment,
+) {
+    for property in &mut node.properties {
+        visitor.visit_property(property);
+    }
+}
+
+pub fn walk_property<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Property) {
+    for value in &mut node.values {
+        visitor.visit_property_value(value);
+    }
+}
+
+pub fn walk_property_value<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut PropertyValue) {
+    match node {
+        PropertyValue::XRef(xref) => visitor.visit_xref(xref),
+        PropertyValue::Markup(markup) => {
+            for child in &mut markup.content {
+                visitor.visit_character_style(child);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn walk_xref_fragment<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut XRefFragment) {
+    for xref in &mut node.xrefs {
+        visitor.visit_block_xref(xref);
+    }
+}
+
+pub fn walk_fragment_content<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut FragmentContent) {
+    match node {
+        FragmentContent::Text(text) => visitor.visit_text(text),
+        FragmentContent::Heading(heading) => visitor.visit_heading(heading),
+        FragmentContent::Block { label, child } => visitor.visit_block(label, child),
+        FragmentContent::BlockXRef(xref) => visitor.visit_block_xref(xref),
+        FragmentContent::Para(para) => visitor.visit_para(para),
+        FragmentContent::Preformat { child } => visitor.visit_preformat(child),
+        FragmentContent::Image(image) => visitor.visit_image(image),
+        FragmentContent::Table(table) => visitor.visit_table(table),
+        FragmentContent::List(list) => visitor.visit_list(list),
+        FragmentContent::NumberedList(list) => visitor.visit_numbered_list(list),
+    }
+}
+
+pub fn walk_block<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    _label: &mut Option<String>,
+    child: &mut Vec<FragmentContent>,
+) {
+    for content in child {
+        visitor.visit_fragment_content(content);
+    }
+}
+
+pub fn walk_preformat<V: VisitorMut + ?Sized>(visitor: &mut V, child: &mut Vec<CharacterStyle>) {
+    for content in child {
+        visitor.visit_character_style(content);
+    }
+}
+
+pub fn walk_heading<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Heading) {
+    for content in &mut node.content {
+        visitor.visit_character_style(content);
+    }
+}
+
+pub fn walk_para<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Para) {
+    for content in &mut node.content {
+        visitor.visit_para_content(content);
+    }
+}
+
+pub fn walk_para_content<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut ParaContent) {
+    match node {
+        ParaContent::Text(text) => visitor.visit_text(text),
+        ParaContent::Bold(bold) => visitor.visit_bold(bold),
+        ParaContent::Italic(italic) => visitor.visit_italic(italic),
+        ParaContent::Underline(underline) => visitor.visit_underline(underline),
+        ParaContent::Subscript(subscript) => visitor.visit_subscript(subscript),
+        ParaContent::Superscript(superscript) => visitor.visit_superscript(superscript),
+        ParaContent::Monospace(monospace) => visitor.visit_monospace(monospace),
+        ParaContent::XRef(xref) => visitor.visit_xref(xref),
+        ParaContent::Link(link) => visitor.visit_link(link),
+        ParaContent::Inline(inline) => visitor.visit_inline(inline),
+        ParaContent::Anchor(anchor) => visitor.visit_anchor(anchor),
+        ParaContent::Placeholder(placeholder) => visitor.visit_placeholder(placeholder),
+        ParaContent::Br => visitor.visit_br(),
+        ParaContent::Image(image) => visitor.visit_image(image),
+    }
+}
+
+pub fn walk_table<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Table) {
+    for col in &mut node.cols {
+        visitor.visit_table_column(col);
+    }
+    for row in &mut node.rows {
+        visitor.visit_table_row(row);
+    }
+}
+
+pub fn walk_table_row<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut TableRow) {
+    for cell in &mut node.cells {
+        visitor.visit_table_row_content(cell);
+    }
+}
+
+pub fn walk_table_row_content<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut TableRowContent) {
+    match node {
+        TableRowContent::Cell(cell) | TableRowContent::HeaderCell(cell) => {
+            visitor.visit_table_cell(cell)
+        }
+    }
+}
+
+pub fn walk_table_cell<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut TableCell) {
+    for content in &mut node.content {
+        visitor.visit_mixed_content(content);
+    }
+}
+
+pub fn walk_list<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut List) {
+    for item in &mut node.items {
+        visitor.visit_list_item(item);
+    }
+}
+
+pub fn walk_numbered_list<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut NumberedList) {
+    for item in &mut node.items {
+        visitor.visit_list_item(item);
+    }
+}
+
+pub fn walk_list_item<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut ListItem) {
+    for content in &mut node.content {
+        visitor.visit_mixed_content(content);
+    }
+}
+
+pub fn walk_mixed_content<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut MixedContent) {
+    match node {
+        MixedContent::Text(text) => visitor.visit_text(text),
+        MixedContent::Bold(bold) => visitor.visit_bold(bold),
+        MixedContent::Italic(italic) => visitor.visit_italic(italic),
+        MixedContent::Underline(underline) => visitor.visit_underline(underline),
+        MixedContent::Subscript(subscript) => visitor.visit_subscript(subscript),
+        MixedContent::Superscript(superscript) => visitor.visit_superscript(superscript),
+        MixedContent::Monospace(monospace) => visitor.visit_monospace(monospace),
+        MixedContent::XRef(xref) => visitor.visit_xref(xref),
+        MixedContent::Link(link) => visitor.visit_link(link),
+        MixedContent::Inline(inline) => visitor.visit_inline(inline),
+        MixedContent::Anchor(anchor) => visitor.visit_anchor(anchor),
+        MixedContent::Placeholder(placeholder) => visitor.visit_placeholder(placeholder),
+        MixedContent::Br => visitor.visit_br(),
+        MixedContent::Image(image) => visitor.visit_image(image),
+        MixedContent::Para(para) => visitor.visit_para(para),
+        MixedContent::Block { label, child } => visitor.visit_block(label, child),
+        MixedContent::Preformat { child } => visitor.visit_preformat(child),
+        MixedContent::List(list) => visitor.visit_list(list),
+        MixedContent::NumberedList(list) => visitor.visit_numbered_list(list),
+    }
+}
+
+pub fn walk_character_style<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut CharacterStyle) {
+    match node {
+        CharacterStyle::Text(text) => visitor.visit_text(text),
+        CharacterStyle::Bold(bold) => visitor.visit_bold(bold),
+        CharacterStyle::Italic(italic) => visitor.visit_italic(italic),
+        CharacterStyle::Underline(underline) => visitor.visit_underline(underline),
+        CharacterStyle::Subscript(subscript) => visitor.visit_subscript(subscript),
+        CharacterStyle::Superscript(superscript) => visitor.visit_superscript(superscript),
+        CharacterStyle::Monospace(monospace) => visitor.visit_monospace(monospace),
+        CharacterStyle::XRef(xref) => visitor.visit_xref(xref),
+        CharacterStyle::Link(link) => visitor.visit_link(link),
+        CharacterStyle::Inline(inline) => visitor.visit_inline(inline),
+        CharacterStyle::Anchor(anchor) => visitor.visit_anchor(anchor),
+        CharacterStyle::Placeholder(placeholder) => visitor.visit_placeholder(placeholder),
+        CharacterStyle::Br => visitor.visit_br(),
+    }
+}
+
+macro_rules! walk_char_style {
+    ($name:ident, $ty:ty) => {
+        pub fn $name<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut $ty) {
+            for content in &mut node.content {
+                visitor.visit_character_style(content);
+            }
+        }
+    };
+}
+
+walk_char_style!(walk_bold, Bold);
+walk_char_style!(walk_italic, Italic);
+walk_char_style!(walk_underline, Underline);
+walk_char_style!(walk_subscript, Subscript);
+walk_char_style!(walk_superscript, Superscript);
+walk_char_style!(walk_monospace, Monospace);
+walk_char_style!(walk_link, Link);
+walk_char_style!(walk_inline, Inline);
@@ -0,0 +1,522 @@
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    io::{BufRead, Read, Write},
+    ops::Range,
+    rc::Rc,
+};
+
+use quick_xml::{
+    de::Deserializer,
+    escape::{escape, unescape},
+    events::Event,
+    se::Serializer,
+    Reader,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::{PsmlError, PsmlResult},
+    model::{Document, MediaEncoding},
+};
+
+pub(crate) const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="utf-8"?>"#;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Options for writing PSML.
+pub struct WriteOptions {
+    /// Whether to start the output with an XML declaration.
+    pub declaration: bool,
+    /// Character and number of them to indent nested elements with.
+    /// Indenting adds whitespace to mixed content, so should only be used for display.
+    pub indent: Option<(char, usize)>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            declaration: true,
+            indent: None,
+        }
+    }
+}
+
+/// Buffered reader over a byte slice that records how much input has been consumed.
+struct TrackingReader<'a> {
+    input: &'a [u8],
+    consumed: Rc<Cell<usize>>,
+}
+
+impl Read for TrackingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for TrackingReader<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(&self.input[self.consumed.get()..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed
+            .set((self.consumed.get() + amt).min(self.input.len()));
+    }
+}
+
+/// Elements whose content is text mixed with inline elements,
+/// so whitespace between two child elements is part of the text.
+const MIXED_CONTENT: &[&[u8]] = &[
+    b"para",
+    b"heading",
+    b"preformat",
+    b"bold",
+    b"italic",
+    b"underline",
+    b"subscript",
+    b"superscript",
+    b"monospace",
+    b"link",
+    b"inline",
+    b"xref",
+    b"cell",
+    b"hcell",
+    b"item",
+    b"placeholder",
+];
+
+/// Block elements in mixed content, so whitespace next to them is only layout.
+const BLOCK_CONTENT: &[&[u8]] = &[
+    b"para",
+    b"heading",
+    b"preformat",
+    b"block",
+    b"blockxref",
+    b"table",
+    b"list",
+    b"nlist",
+];
+
+/// Returns whether the content of elements with the name is mixed.
+pub(crate) fn is_mixed(name: &str) -> bool {
+    MIXED_CONTENT.contains(&name.as_bytes())
+}
+
+/// A token of the input, with whether elements are blocks and the byte range of text.
+enum Token {
+    Start { block: bool },
+    End { block: bool },
+    Empty { block: bool },
+    Text { start: usize, end: usize },
+    Other,
+}
+
+/// A media fragment being read, with the position of its content.
+struct MediaContent {
+    /// Number of elements open inside the media fragment.
+    depth: usize,
+    /// Offset of the `>` closing the start tag.
+    tag_end: usize,
+    start: usize,
+    has_elements: bool,
+    /// Whether the content has text outside CDATA sections.
+    has_text: bool,
+    /// Content of the CDATA sections.
+    cdata: Option<String>,
+}
+
+/// Input rewritten by [`prepare_input`].
+#[derive(Default)]
+struct PreparedInput {
+    xml: String,
+    /// End offsets in the rewritten XML and in the input of each rewritten range.
+    offsets: Vec<(usize, usize)>,
+}
+
+/// Name of the attribute serde reads and writes the encoding of media fragments in.
+const MEDIA_ENCODING_ATTR: &str = "psml-encoding";
+
+/// Returns the attribute serde writes for a media encoding, with a leading space.
+fn media_encoding_attr(encoding: MediaEncoding) -> String {
+    let value = match encoding {
+        MediaEncoding::Text => "text",
+        MediaEncoding::CData => "cdata",
+        MediaEncoding::Markup => "markup",
+    };
+    format!(" {MEDIA_ENCODING_ATTR}=\"{value}\"")
+}
+
+/// Escapes text for the deserializer, keeping trailing whitespace it would trim.
+fn escape_text(text: &str) -> String {
+    let trimmed = text.trim_end_matches(|c: char| c.is_ascii_whitespace());
+    let mut escaped = escape(trimmed).into_owned();
+    for c in text[trimmed.len()..].chars() {
+        escaped.push_str(&format!("&#{};", c as u32));
+    }
+    escaped
+}
+
+/// Rewrites the input so the deserializer keeps content it would otherwise lose.
+///
+/// The deserializer trims text at every element boundary, which drops the spaces around inline
+/// elements in mixed content, such as those in `Hello <bold>big</bold> world`. Whitespace at the
+/// edges of text next to a sibling inline element is escaped as character references.
+///
+/// The deserializer also reads CDATA as text and fails on markup in media fragments, so the
+/// content of media fragments written as either is escaped, and its encoding added as the
+/// attribute serde reads it from.
+///
+/// Returns none if the input is not well-formed, to leave reporting errors to the deserializer.
+fn prepare_input(xml: &str) -> Option<PreparedInput> {
+    let mut reader = Reader::from_str(xml);
+    let mut tokens = vec![];
+    // Whether each element has mixed content, the elements left open,
+    // and each text token with the element containing it.
+    let mut mixed = vec![];
+    let mut open: Vec<usize> = vec![];
+    let mut texts = vec![];
+    let mut media: Option<MediaContent> = None;
+    // Ranges of the input to replace, in order.
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    loop {
+        let start = reader.buffer_position();
+        let event = reader.read_event().ok()?;
+        if let Some(content) = &mut media {
+            match event {
+                Event::Start(_) => {
+                    content.depth += 1;
+                    content.has_elements = true;
+                }
+                Event::Empty(_) => content.has_elements = true,
+                Event::End(_) if content.depth > 0 => content.depth -= 1,
+                Event::End(_) => {
+                    let raw = &xml[content.start..start];
+                    let (encoding, replacement) = match (content.has_text, &content.cdata) {
+                        _ if content.has_elements => (MediaEncoding::Markup, escape_text(raw)),
+                        (false, Some(cdata)) => (MediaEncoding::CData, escape_text(cdata)),
+                        _ => (MediaEncoding::Text, String::new()),
+                    };
+                    if encoding != MediaEncoding::Text {
+                        let tag_end = content.tag_end;
+                        edits.push((tag_end..tag_end, media_encoding_attr(encoding)));
+                        edits.push((content.start..start, replacement));
+                    }
+                    media = None;
+                    open.pop();
+                    tokens.push(Token::End { block: false });
+                }
+                Event::CData(cdata) => {
+                    let cdata = std::str::from_utf8(&cdata).ok()?;
+                    content
+                        .cdata
+                        .get_or_insert_with(String::new)
+                        .push_str(cdata);
+                }
+                Event::Text(text) if !text.iter().all(u8::is_ascii_whitespace) => {
+                    content.has_text = true;
+                }
+                Event::Eof => return None,
+                _ => {}
+            }
+            continue;
+        }
+
+        let token = match event {
+            Event::Eof => break,
+            Event::Start(element) => {
+                if element.name().as_ref() == b"media-fragment" {
+                    media = Some(MediaContent {
+                        depth: 0,
+                        tag_end: reader.buffer_position() - 1,
+                        start: reader.buffer_position(),
+                        has_elements: false,
+                        has_text: false,
+                        cdata: None,
+                    });
+                }
+                open.push(mixed.len());
+                mixed.push(MIXED_CONTENT.contains(&element.name().as_ref()));
+                Token::Start {
+                    block: BLOCK_CONTENT.contains(&element.name().as_ref()),
+                }
+            }
+            Event::End(element) => {
+                open.pop();
+                Token::End {
+                    block: BLOCK_CONTENT.contains(&element.name().as_ref()),
+                }
+            }
+            Event::Empty(element) => Token::Empty {
+                block: BLOCK_CONTENT.contains(&element.name().as_ref()),
+            },
+            Event::Text(text) => {
+                let parent = open.last().copied();
+                if let Some(parent) = parent {
+                    if !text.iter().all(u8::is_ascii_whitespace) {
+                        mixed[parent] = true;
+                    }
+                }
+                texts.push((tokens.len(), parent));
+                Token::Text {
+                    start,
+                    end: reader.buffer_position(),
+                }
+            }
+            _ => Token::Other,
+        };
+        tokens.push(token);
+    }
+
+    for (index, parent) in texts {
+        let Token::Text { start, end } = tokens[index] else {
+            continue;
+        };
+        let sibling_before = index > 0
+            && matches!(
+                tokens[index - 1],
+                Token::End { block: false } | Token::Empty { block: false }
+            );
+        let sibling_after = matches!(
+            tokens.get(index + 1),
+            Some(Token::Start { block: false } | Token::Empty { block: false })
+        );
+        let text = &xml[start..end];
+        let trimmed = text.trim_matches(|c: char| c.is_ascii_whitespace());
+        let (lead, trail) = if trimmed.is_empty() {
+            match sibling_before && sibling_after && parent.is_some_and(|parent| mixed[parent]) {
+                true => (text.len(), 0),
+                false => continue,
+            }
+        } else {
+            let lead = text.len()
+                - text
+                    .trim_start_matches(|c: char| c.is_ascii_whitespace())
+                    .len();
+            let trail = text.len()
+                - text
+                    .trim_end_matches(|c: char| c.is_ascii_whitespace())
+                    .len();
+            (
+                if sibling_before { lead } else { 0 },
+                if sibling_after { trail } else { 0 },
+            )
+        };
+
+        for range in [start..start + lead, end - trail..end] {
+            if !range.is_empty() {
+                let escaped = xml[range.clone()]
+                    .chars()
+                    .map(|c| format!("&#{};", c as u32))
+                    .collect();
+                edits.push((range, escaped));
+            }
+        }
+    }
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut prepared = String::with_capacity(xml.len());
+    let mut offsets = vec![];
+    let mut copied = 0;
+    for (range, replacement) in edits {
+        prepared.push_str(&xml[copied..range.start]);
+        prepared.push_str(&replacement);
+        copied = range.end;
+        offsets.push((prepared.len(), range.end));
+    }
+    prepared.push_str(&xml[copied..]);
+    Some(PreparedInput {
+        xml: prepared,
+        offsets,
+    })
+}
+
+/// Returns the offset in the input of an offset in XML rewritten by [`prepare_input`].
+fn original_offset(offsets: &[(usize, usize)], offset: usize) -> usize {
+    match offsets
+        .iter()
+        .rev()
+        .find(|(prepared, _)| *prepared <= offset)
+    {
+        Some((prepared, original)) => original + (offset - prepared),
+        None => offset,
+    }
+}
+
+/// Returns whether the text is well-formed XML content, so can be written as markup.
+fn is_markup(text: &str) -> bool {
+    let mut reader = Reader::from_str(text);
+    let mut depth = 0usize;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(_)) => depth += 1,
+            Ok(Event::End(_)) => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            Ok(Event::Eof) => return depth == 0,
+            Ok(Event::Decl(_) | Event::DocType(_)) | Err(_) => return false,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Returns the text as CDATA sections, splitting it wherever it contains `]]>`.
+fn cdata_sections(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// Rewrites the content of serialised media fragments, which serde writes as escaped text,
+/// as CDATA sections or markup according to the encoding serde writes alongside it.
+/// Fails if the content of a fragment encoded as markup is not well-formed XML.
+fn restore_media(xml: String) -> PsmlResult<String> {
+    if !xml.contains(MEDIA_ENCODING_ATTR) {
+        return Ok(xml);
+    }
+
+    let mut reader = Reader::from_str(&xml);
+    let mut edits = vec![];
+    loop {
+        let tag_start = reader.buffer_position();
+        let event = match reader.read_event() {
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(event) => event,
+        };
+        let Event::Start(start) = event else {
+            continue;
+        };
+        if start.name().as_ref() != b"media-fragment" {
+            continue;
+        }
+
+        let tag = tag_start..reader.buffer_position();
+        let Some((encoding, attr_start)) = [MediaEncoding::CData, MediaEncoding::Markup]
+            .into_iter()
+            .find_map(|encoding| {
+                let attr_start = xml[tag.clone()].find(&media_encoding_attr(encoding))?;
+                Some((encoding, tag.start + attr_start))
+            })
+        else {
+            continue;
+        };
+        let attr_end = attr_start + media_encoding_attr(encoding).len();
+        edits.push((attr_start..attr_end, String::new()));
+
+        // Serialised media fragments only contain text.
+        let start_tag = start.into_owned();
+        let start = reader.buffer_position();
+        let mut end = start;
+        while let Ok(Event::Text(_)) = reader.read_event() {
+            end = reader.buffer_position();
+        }
+        let content = unescape(&xml[start..end]).unwrap_or(Cow::Borrowed(&xml[start..end]));
+        let replacement = match encoding {
+            MediaEncoding::Markup if !is_markup(&content) => {
+                let id = match start_tag.try_get_attribute("id") {
+                    Ok(Some(id)) => String::from_utf8_lossy(&id.value).into_owned(),
+                    _ => String::new(),
+                };
+                return Err(PsmlError::FragmentError {
+                    id,
+                    msg: "markup content is not well-formed XML in media fragment".to_string(),
+                });
+            }
+            MediaEncoding::Markup => content.into_owned(),
+            _ => cdata_sections(&content),
+        };
+        edits.push((start..end, replacement));
+    }
+
+    let mut restored = String::with_capacity(xml.len());
+    let mut copied = 0;
+    for (range, replacement) in edits {
+        restored.push_str(&xml[copied..range.start]);
+        restored.push_str(&replacement);
+        copied = range.end;
+    }
+    restored.push_str(&xml[copied..]);
+    Ok(restored)
+}
+
+/// Serialises any PSML element without an XML declaration, writing the content of media
+/// fragments in their encoding. `root` names the element for types without a serde name.
+/// All PSML output is written through this.
+pub(crate) fn serialise<T: Serialize>(
+    value: &T,
+    root: Option<&str>,
+    indent: Option<(char, usize)>,
+) -> PsmlResult<String> {
+    let mut xml = String::new();
+    let mut ser = Serializer::with_root(&mut xml, root).map_err(PsmlError::SerialiseError)?;
+    if let Some((indent_char, indent_size)) = indent {
+        ser.indent(indent_char, indent_size);
+    }
+    value.serialize(ser).map_err(PsmlError::SerialiseError)?;
+    restore_media(xml)
+}
+
+/// Deserializes any PSML element from a string, locating errors in the input.
+/// Whitespace next to inline elements is kept.
+pub(crate) fn deserialize<T: DeserializeOwned>(xml: &str) -> PsmlResult<T> {
+    let prepared = prepare_input(xml).unwrap_or_default();
+    let input = match prepared.offsets.is_empty() {
+        true => xml,
+        false => &prepared.xml,
+    };
+
+    let consumed = Rc::new(Cell::new(0));
+    let mut de = Deserializer::from_reader(TrackingReader {
+        input: input.as_bytes(),
+        consumed: consumed.clone(),
+    });
+
+    T::deserialize(&mut de).map_err(|err| {
+        PsmlError::parse(xml, original_offset(&prepared.offsets, consumed.get()), err)
+    })
+}
+
+/// Parses a PSML document from a string.
+/// Errors report the line, column and element path where parsing failed.
+pub fn from_str(xml: &str) -> PsmlResult<Document> {
+    deserialize(xml)
+}
+
+/// Parses a PSML document from a reader.
+pub fn from_reader<R: Read>(mut reader: R) -> PsmlResult<Document> {
+    let mut xml = String::new();
+    reader.read_to_string(&mut xml)?;
+    from_str(&xml)
+}
+
+/// Serialises a PSML document to a string with the default options.
+pub fn to_string(document: &Document) -> PsmlResult<String> {
+    to_string_with_options(document, &WriteOptions::default())
+}
+
+/// Serialises a PSML document to a string.
+pub fn to_string_with_options(document: &Document, options: &WriteOptions) -> PsmlResult<String> {
+    let mut xml = String::new();
+    if options.declaration {
+        xml.push_str(XML_DECLARATION);
+        if options.indent.is_some() {
+            xml.push('\n');
+        }
+    }
+    xml.push_str(&serialise(document, None, options.indent)?);
+    Ok(xml)
+}
+
+/// Serialises a PSML document to a writer with the default options.
+pub fn to_writer<W: Write>(writer: W, document: &Document) -> PsmlResult<()> {
+    to_writer_with_options(writer, document, &WriteOptions::default())
+}
+
+/// Serialises a PSML document to a writer.
+pub fn to_writer_with_options<W: Write>(
+    mut writer: W,
+    document: &Document,
+    options: &WriteOptions,
+) -> PsmlResult<()> {
+    writer.write_all(to_string_with_options(document, options)?.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,319 @@
+//! Index and bulk rewriting of cross-references across a set of documents.
+//!
+//! A reference is an xref, a block xref or an xref property value.
+//! Targets are compared as written, so relative hrefs only match the same relative href.
+
+use std::collections::HashMap;
+
+use crate::{
+    model::{
+        BlockXRef, Document, Fragment, PropertiesFragment, Property, PropertyValue, Section, XRef,
+        XRefFragment, XRefTarget,
+    },
+    toc::TocPart,
+    visit::{self, Visitor},
+    visit_mut::{self, VisitorMut},
+};
+
+/// The element a reference is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind<'a> {
+    XRef,
+    BlockXRef,
+    /// The value of an xref property.
+    Property {
+        name: &'a str,
+    },
+}
+
+/// A reference from a fragment in a document to its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference<'a> {
+    /// Index of the document in the indexed set.
+    pub document: usize,
+    /// ID of the section containing the reference.
+    pub section: &'a str,
+    /// ID of the fragment containing the reference.
+    pub fragment: &'a str,
+    pub kind: ReferenceKind<'a>,
+    pub target: XRefTarget<'a>,
+    /// Whether the target could not be resolved when the document was exported,
+    /// which is only known for block xrefs.
+    pub unresolved: bool,
+}
+
+/// The attribute identifying the target document of a reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetKey<'a> {
+    URIID(&'a str),
+    Docid(&'a str),
+    Href(&'a str),
+}
+
+/// The references in a set of documents, by target.
+#[derive(Debug, Clone)]
+pub struct XRefIndex<'a> {
+    references: Vec<Reference<'a>>,
+    /// Indices of the references to each target.
+    targets: HashMap<TargetKey<'a>, Vec<usize>>,
+    /// URI ID and docid of each document.
+    ids: Vec<(Option<&'a str>, Option<&'a str>)>,
+}
+
+impl<'a> XRefIndex<'a> {
+    /// Indexes the references in the documents.
+    pub fn new(documents: impl IntoIterator<Item = &'a Document>) -> Self {
+        let mut collector = Collector::default();
+        let mut ids = vec![];
+        for (index, document) in documents.into_iter().enumerate() {
+            collector.document = index;
+            collector.visit_document(document);
+            ids.push((document.uriid(), document.docid()));
+        }
+
+        let mut targets: HashMap<TargetKey, Vec<usize>> = HashMap::new();
+        for (index, reference) in collector.references.iter().enumerate() {
+            for key in target_keys(&reference.target) {
+                targets.entry(key).or_default().push(index);
+            }
+        }
+
+        XRefIndex {
+            references: collector.references,
+            targets,
+            ids,
+        }
+    }
+
+    /// Returns every reference, in document order.
+    pub fn references(&self) -> &[Reference<'a>] {
+        &self.references
+    }
+
+    /// Returns the references from the document at `index` in the set.
+    pub fn outgoing(&self, document: usize) -> impl Iterator<Item = &Reference<'a>> {
+        self.references
+            .iter()
+            .filter(move |reference| reference.document == document)
+    }
+
+    /// Returns the references to the target, in document order.
+    pub fn to(&self, key: TargetKey) -> Vec<&Reference<'a>> {
+        self.targets
+            .get(&key)
+            .map(|indices| indices.iter().map(|&i| &self.references[i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the references to a fragment of the target, in document order.
+    /// References to the whole document have the fragment `default`.
+    pub fn to_fragment(&self, key: TargetKey, fragment: &str) -> Vec<&Reference<'a>> {
+        self.to(key)
+            .into_iter()
+            .filter(|reference| reference.target.frag == fragment)
+            .collect()
+    }
+
+    /// Returns the references to the document at `index` in the set by its URI ID or docid,
+    /// in document order.
+    pub fn reverse(&self, document: usize) -> Vec<&Reference<'a>> {
+        let Some(&(uriid, docid)) = self.ids.get(document) else {
+            return vec![];
+        };
+        let mut indices: Vec<usize> = [uriid.map(TargetKey::URIID), docid.map(TargetKey::Docid)]
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.targets.get(&key))
+            .flatten()
+            .copied()
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(|i| &self.references[i]).collect()
+    }
+}
+
+/// Returns the keys of the attributes set on a target.
+fn target_keys<'a>(target: &XRefTarget<'a>) -> impl Iterator<Item = TargetKey<'a>> {
+    [
+        target.uriid.map(TargetKey::URIID),
+        target.docid.map(TargetKey::Docid),
+        target.href.map(TargetKey::Href),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// Returns true if the target is within the document with the URI ID and docid:
+/// it has no href, docid or uriid, or has the document's URI ID or docid.
+pub(crate) fn is_internal(target: &XRefTarget, uriid: Option<&str>, docid: Option<&str>) -> bool {
+    match (target.uriid, target.docid, target.href) {
+        (None, None, None) => true,
+        (Some(target), _, _) => Some(target) == uriid,
+        (None, Some(target), _) => Some(target) == docid,
+        (None, None, Some(_)) => false,
+    }
+}
+
+/// Visitor that records each reference with the section and fragment containing it.
+#[derive(Default)]
+struct Collector<'a> {
+    document: usize,
+    section: &'a str,
+    fragment: &'a str,
+    property: Option<&'a str>,
+    references: Vec<Reference<'a>>,
+}
+
+impl<'a> Collector<'a> {
+    fn push(&mut self, kind: ReferenceKind<'a>, target: XRefTarget<'a>, unresolved: bool) {
+        self.references.push(Reference {
+            document: self.document,
+            section: self.section,
+            fragment: self.fragment,
+            kind,
+            target,
+            unresolved,
+        });
+    }
+}
+
+impl<'a> Visitor<'a> for Collector<'a> {
+    fn visit_section(&mut self, node: &'a Section) {
+        self.section = &node.id;
+        visit::walk_section(self, node);
+    }
+
+    fn visit_fragment(&mut self, node: &'a Fragment) {
+        self.fragment = &node.id;
+        visit::walk_fragment(self, node);
+    }
+
+    fn visit_properties_fragment(&mut self, node: &'a PropertiesFragment) {
+        self.fragment = &node.id;
+        visit::walk_properties_fragment(self, node);
+    }
+
+    fn visit_xref_fragment(&mut self, node: &'a XRefFragment) {
+        self.fragment = &node.id;
+        visit::walk_xref_fragment(self, node);
+    }
+
+    fn visit_property(&mut self, node: &'a Property) {
+        self.property = Some(&node.name);
+        visit::walk_property(self, node);
+        self.property = None;
+    }
+
+    fn visit_property_value(&mut self, node: &'a PropertyValue) {
+        match (node, self.property) {
+            (PropertyValue::XRef(xref), Some(name)) => self.push(
+                ReferenceKind::Property { name },
+                xref.as_ref().into(),
+                false,
+            ),
+            _ => visit::walk_property_value(self, node),
+        }
+    }
+
+    fn visit_block_xref(&mut self, node: &'a BlockXRef) {
+        self.push(
+            ReferenceKind::BlockXRef,
+            node.into(),
+            node.unresolved == Some(true),
+        );
+    }
+
+    fn visit_xref(&mut self, node: &'a XRef) {
+        self.push(ReferenceKind::XRef, node.into(), false);
+    }
+}
+
+/// An owned target to replace the target of a reference with.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NewTarget {
+    pub href: Option<String>,
+    pub docid: Option<String>,
+    pub uriid: Option<String>,
+    /// ID of the target fragment, `default` for the whole document.
+    pub frag: String,
+}
+
+impl From<&XRefTarget<'_>> for NewTarget {
+    fn from(target: &XRefTarget) -> Self {
+        NewTarget {
+            href: target.href.map(str::to_string),
+            docid: target.docid.map(str::to_string),
+            uriid: target.uriid.map(str::to_string),
+            frag: target.frag.to_string(),
+        }
+    }
+}
+
+/// Replaces the target of every reference in the documents for which `map` returns a new target,
+/// and returns the number of references changed.
+/// Parts of the tables of contents generated from block xrefs are given the new target document,
+/// but are not counted.
+pub fn rewrite<'a>(
+    documents: impl IntoIterator<Item = &'a mut Document>,
+    map: impl FnMut(&XRefTarget) -> Option<NewTarget>,
+) -> usize {
+    let mut rewriter = Rewriter { map, count: 0 };
+    for document in documents {
+        rewriter.visit_document(document);
+    }
+    rewriter.count
+}
+
+/// Visitor that replaces the targets of references.
+struct Rewriter<F> {
+    map: F,
+    count: usize,
+}
+
+impl<F: FnMut(&XRefTarget) -> Option<NewTarget>> Rewriter<F> {
+    /// Returns the new target for a reference if it changed.
+    fn retarget(&mut self, target: XRefTarget) -> Option<NewTarget> {
+        let new = (self.map)(&target).filter(|new| *new != NewTarget::from(&target))?;
+        self.count += 1;
+        Some(new)
+    }
+}
+
+impl<F: FnMut(&XRefTarget) -> Option<NewTarget>> VisitorMut for Rewriter<F> {
+    fn visit_block_xref(&mut self, node: &mut BlockXRef) {
+        if let Some(new) = self.retarget(XRefTarget::from(&*node)) {
+            node.href = new.href;
+            node.docid = new.docid;
+            node.uriid = new.uriid;
+            node.frag = new.frag;
+        }
+    }
+
+    fn visit_toc_part(&mut self, node: &mut TocPart) {
+        // Parts copy the target document of block xrefs, so are updated without counting them.
+        if node.href.is_some() || node.docid.is_some() || node.uriid.is_some() {
+            let target = XRefTarget {
+                href: node.href.as_deref(),
+                docid: node.docid.as_deref(),
+                uriid: node.uriid.as_deref(),
+                frag: "default",
+            };
+            if let Some(new) = (self.map)(&target).filter(|new| *new != NewTarget::from(&target)) {
+                node.href = new.href;
+                node.docid = new.docid;
+                node.uriid = new.uriid;
+            }
+        }
+        visit_mut::walk_toc_part(self, node);
+    }
+
+    fn visit_xref(&mut self, node: &mut XRef) {
+        if let Some(new) = self.retarget(XRefTarget::from(&*node)) {
+            node.href = new.href;
+            node.docid = new.docid;
+            node.uriid = new.uriid;
+            node.frag_id = new.frag;
+        }
+    }
+}